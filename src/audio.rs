@@ -0,0 +1,197 @@
+use crate::core::apu::DEFAULT_SAMPLE_RATE;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+/// Audio queued ahead of the device, what the rate control aims for.
+const TARGET_LATENCY_SECONDS: f64 = 0.06;
+/// The resampling ratio stays this close to 1, about 17 cents of pitch. It covers the gap
+/// between the 59.7 Hz Game Boy and a 60 Hz display.
+const MAX_RATE_ADJUSTMENT: f64 = 0.01;
+/// Past this many times the target the emulator is running away, queued audio is dropped.
+const MAX_LATENCY_FACTOR: usize = 4;
+
+/// Linear interpolation from the emulated rate to the device rate.
+struct Resampler {
+    /// position between `previous` and the next input frame
+    position: f64,
+    previous: (f32, f32),
+}
+
+impl Resampler {
+    fn new() -> Resampler {
+        Resampler {
+            position: 0.0,
+            previous: (0.0, 0.0),
+        }
+    }
+    /// Resample interleaved stereo `input`, producing `ratio` output frames per input frame.
+    fn process(&mut self, input: &[f32], ratio: f64, gain: f32, out: &mut VecDeque<f32>) {
+        let frames = input.len() / 2;
+        let previous = self.previous;
+        // frame 0 is the last one of the previous call
+        let frame = |i: usize| {
+            if i == 0 {
+                previous
+            } else {
+                (input[2 * i - 2], input[2 * i - 1])
+            }
+        };
+        let step = 1.0 / ratio;
+        let mut position = self.position;
+        while position < frames as f64 {
+            let i = position as usize;
+            let t = (position - i as f64) as f32;
+            let (left0, right0) = frame(i);
+            let (left1, right1) = frame(i + 1);
+            out.push_back((left0 + (left1 - left0) * t) * gain);
+            out.push_back((right0 + (right1 - right0) * t) * gain);
+            position += step;
+        }
+        if frames > 0 {
+            self.previous = frame(frames);
+            self.position = position - frames as f64;
+        }
+    }
+}
+
+/// Host audio output. Samples are resampled to the device rate with a ratio nudged by how
+/// full the device queue is, so audio follows the video pace without under or overruns.
+pub struct Speaker {
+    /// interleaved stereo frames waiting for the device
+    queue: Arc<Mutex<VecDeque<f32>>>,
+    /// rate of the pushed samples
+    input_rate: u32,
+    /// device rate
+    sample_rate: u32,
+    resampler: Resampler,
+    volume: f32,
+    muted: bool,
+    #[cfg(feature = "audio")]
+    _stream: Option<cpal::Stream>,
+}
+
+impl Speaker {
+    /// Open the default output device for samples generated at `input_rate`, without one or
+    /// without the `audio` feature samples are dropped.
+    pub fn new(input_rate: u32, volume: f32, muted: bool) -> Speaker {
+        let queue = Arc::new(Mutex::new(VecDeque::new()));
+        #[cfg(feature = "audio")]
+        let (stream, sample_rate) = match output::open(queue.clone()) {
+            Ok((stream, sample_rate)) => (Some(stream), sample_rate),
+            Err(err) => {
+                eprintln!("no audio: {}", err);
+                (None, DEFAULT_SAMPLE_RATE)
+            }
+        };
+        #[cfg(not(feature = "audio"))]
+        let sample_rate = DEFAULT_SAMPLE_RATE;
+        Speaker {
+            queue,
+            input_rate,
+            sample_rate,
+            resampler: Resampler::new(),
+            volume,
+            muted,
+            #[cfg(feature = "audio")]
+            _stream: stream,
+        }
+    }
+    #[cfg(feature = "audio")]
+    fn playing(&self) -> bool {
+        self._stream.is_some()
+    }
+    #[cfg(not(feature = "audio"))]
+    fn playing(&self) -> bool {
+        false
+    }
+    pub fn volume(&self) -> f32 {
+        self.volume
+    }
+    /// 0.0 to 1.0
+    pub fn set_volume(&mut self, volume: f32) {
+        self.volume = volume.clamp(0.0, 1.0);
+    }
+    pub fn muted(&self) -> bool {
+        self.muted
+    }
+    pub fn set_muted(&mut self, muted: bool) {
+        self.muted = muted;
+    }
+    /// Queue interleaved stereo `samples` generated at the input rate.
+    pub fn push(&mut self, samples: &[f32]) {
+        if !self.playing() {
+            return;
+        }
+        let target = TARGET_LATENCY_SECONDS * self.sample_rate as f64;
+        let mut queue = self.queue.lock().unwrap();
+        let queued = (queue.len() / 2) as f64;
+        // stretch while the queue is short, squeeze while it is long
+        let error = ((target - queued) / target).clamp(-1.0, 1.0);
+        let ratio =
+            self.sample_rate as f64 / self.input_rate as f64 * (1.0 + MAX_RATE_ADJUSTMENT * error);
+        let gain = if self.muted { 0.0 } else { self.volume };
+        self.resampler.process(samples, ratio, gain, &mut queue);
+        let max_len = 2 * MAX_LATENCY_FACTOR * target as usize;
+        if queue.len() > max_len {
+            let excess = queue.len() - max_len;
+            // keep left and right paired
+            queue.drain(..excess + excess % 2);
+        }
+    }
+}
+
+#[cfg(feature = "audio")]
+mod output {
+    use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+    use cpal::{FromSample, SizedSample};
+    use std::collections::VecDeque;
+    use std::error::Error;
+    use std::sync::{Arc, Mutex};
+
+    /// Start a stream on the default device that plays from `queue`, returns its rate.
+    pub fn open(queue: Arc<Mutex<VecDeque<f32>>>) -> Result<(cpal::Stream, u32), Box<dyn Error>> {
+        let device = cpal::default_host()
+            .default_output_device()
+            .ok_or("no output device")?;
+        let config = device.default_output_config()?;
+        let sample_rate = config.sample_rate().0;
+        let stream = match config.sample_format() {
+            cpal::SampleFormat::F32 => build::<f32>(&device, &config.into(), queue)?,
+            cpal::SampleFormat::I16 => build::<i16>(&device, &config.into(), queue)?,
+            cpal::SampleFormat::U16 => build::<u16>(&device, &config.into(), queue)?,
+            format => return Err(format!("unsupported sample format {}", format).into()),
+        };
+        stream.play()?;
+        Ok((stream, sample_rate))
+    }
+
+    fn build<T: SizedSample + FromSample<f32>>(
+        device: &cpal::Device,
+        config: &cpal::StreamConfig,
+        queue: Arc<Mutex<VecDeque<f32>>>,
+    ) -> Result<cpal::Stream, cpal::BuildStreamError> {
+        let channels = config.channels as usize;
+        device.build_output_stream(
+            config,
+            move |data: &mut [T], _| {
+                let mut queue = queue.lock().unwrap();
+                for frame in data.chunks_mut(channels) {
+                    // silence on underrun
+                    let left = queue.pop_front().unwrap_or(0.0);
+                    let right = queue.pop_front().unwrap_or(0.0);
+                    for (channel, sample) in frame.iter_mut().enumerate() {
+                        let val = match (channels, channel) {
+                            (1, _) => (left + right) / 2.0,
+                            (_, 0) => left,
+                            (_, 1) => right,
+                            _ => 0.0,
+                        };
+                        *sample = T::from_sample(val);
+                    }
+                }
+            },
+            |err| eprintln!("audio stream error: {}", err),
+            None,
+        )
+    }
+}
@@ -1,18 +1,56 @@
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use clap_num::maybe_hex;
 use macroquad::prelude::*;
+use rboy::audio::Speaker;
+use rboy::core::audio_sink::WavWriter;
 use rboy::core::constants::{LCD_HEIGHT, LCD_WIDTH};
-use rboy::{core, graphic};
-use std::path::Path;
-use std::{thread, time};
+use rboy::core::joypad::Button;
+use rboy::core::memory::RtcClock;
+use rboy::core::Core;
+use rboy::{disassembler, graphic};
+use std::fs;
+use std::process;
 
 const WINDOW_SCALE: u8 = 2;
+const KEY_MAP: [(KeyCode, Button); 8] = [
+    (KeyCode::Right, Button::Right),
+    (KeyCode::Left, Button::Left),
+    (KeyCode::Up, Button::Up),
+    (KeyCode::Down, Button::Down),
+    (KeyCode::X, Button::A),
+    (KeyCode::Z, Button::B),
+    (KeyCode::Backspace, Button::Select),
+    (KeyCode::Enter, Button::Start),
+];
+const MUTE_KEY: KeyCode = KeyCode::M;
+const VOLUME_DOWN_KEY: KeyCode = KeyCode::Minus;
+const VOLUME_UP_KEY: KeyCode = KeyCode::Equal;
+const VOLUME_STEP: f32 = 0.1;
+/// toggle sound channels 1 to 4
+const CHANNEL_KEYS: [KeyCode; 4] = [KeyCode::Key1, KeyCode::Key2, KeyCode::Key3, KeyCode::Key4];
+
+#[cfg(feature = "audio")]
+const AUDIO_HELP: &str = "Sound plays on the default output device.";
+#[cfg(not(feature = "audio"))]
+const AUDIO_HELP: &str =
+    "This build is silent, it was built without the default `audio` feature. --record-audio \
+     works without it.";
 
 #[derive(Parser)]
-#[command(version, about, long_about = None)]
+#[command(
+    version,
+    about,
+    long_about = None,
+    subcommand_negates_reqs = true,
+    after_help = AUDIO_HELP
+)]
 struct RboyCli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// path to .gb
-    path: String,
+    #[arg(required = true)]
+    path: Option<String>,
 
     #[arg(short, long, default_value_t = WINDOW_SCALE)]
     scale: u8,
@@ -22,11 +60,56 @@ struct RboyCli {
 
     #[arg(short, long, value_parser=maybe_hex::<u16>)]
     break_point: Option<u16>,
+
+    /// drive the cartridge clock from the host time instead of emulated cycles
+    #[arg(long, action)]
+    wall_clock_rtc: bool,
+
+    /// sound volume in percent
+    #[arg(long, default_value_t = 100, value_parser = clap::value_parser!(u8).range(0..=100))]
+    volume: u8,
+
+    /// start with the sound muted
+    #[arg(long, action)]
+    mute: bool,
+
+    /// sound channels to leave out of the mix, 1 to 4
+    #[arg(long, value_delimiter = ',', value_parser = clap::value_parser!(u8).range(1..=4))]
+    mute_channel: Vec<u8>,
+
+    /// write the sound output of the run to a WAV file
+    #[arg(long, value_name = "OUT.wav")]
+    record_audio: Option<String>,
+
+    /// run this many frames as fast as possible without a window or input, then exit
+    #[arg(long, value_name = "N")]
+    frames: Option<u32>,
 }
 
-fn window_conf() -> Conf {
+#[derive(Subcommand)]
+enum Command {
+    /// Disassemble a rom bank in RGBDS syntax
+    Disasm {
+        /// path to .gb
+        path: String,
+
+        /// rom bank to dump
+        #[arg(short, long, default_value_t = 0)]
+        bank: usize,
+
+        /// first address, defaults to the start of the bank
+        #[arg(short, long, value_parser=maybe_hex::<u16>)]
+        start: Option<u16>,
+
+        /// last address, defaults to the end of the bank
+        #[arg(short, long, value_parser=maybe_hex::<u16>)]
+        end: Option<u16>,
+    },
+}
+
+fn window_conf(title: &str) -> Conf {
     Conf {
-        window_title: "Window Conf".to_owned(),
+        window_title: format!("rboy - {}", title),
         window_height: LCD_HEIGHT as i32 * WINDOW_SCALE as i32,
         window_width: LCD_WIDTH as i32 * WINDOW_SCALE as i32,
         window_resizable: false,
@@ -34,37 +117,98 @@ fn window_conf() -> Conf {
     }
 }
 
-#[macroquad::main(window_conf)]
-async fn main() {
-    let cli = RboyCli::parse();
+fn disasm(path: &str, bank: usize, start: Option<u16>, end: Option<u16>) {
+    let game_rom = match fs::read(path) {
+        Ok(game_rom) => game_rom,
+        Err(err) => {
+            eprintln!("can't read {}: {}", path, err);
+            process::exit(1);
+        }
+    };
+    let bank_range = disassembler::bank_range(bank);
+    let start = start.unwrap_or(*bank_range.start());
+    let end = end.unwrap_or(*bank_range.end());
+    for line in disassembler::disassemble_bank(&game_rom, bank, start..=end) {
+        println!("{}", line);
+    }
+}
 
-    if !Path::new(&cli.path).exists() {
-        eprint!("file doesn't exist.")
+fn main() {
+    let cli = RboyCli::parse();
+    match cli.command {
+        Some(Command::Disasm {
+            ref path,
+            bank,
+            start,
+            end,
+        }) => disasm(path, bank, start, end),
+        None => {
+            let path = cli.path.clone().expect("path to .gb");
+            let mut gameboy_core = rboy::core::Core::new(true);
+            if cli.wall_clock_rtc {
+                gameboy_core.set_rtc_clock(RtcClock::WallClock);
+            }
+            gameboy_core.set_debug_views(cli.debug);
+            let cartridge = match gameboy_core.load_game_rom(&path) {
+                Ok(cartridge) => cartridge,
+                Err(err) => {
+                    eprintln!("can't load {}: {}", path, err);
+                    process::exit(1);
+                }
+            };
+            for warning in cartridge.warnings() {
+                eprintln!("warning: {}", warning);
+            }
+            let title = cartridge.title.clone();
+            if let Some(record_path) = &cli.record_audio {
+                match WavWriter::create(record_path) {
+                    Ok(writer) => gameboy_core.add_audio_sink(Box::new(writer)),
+                    Err(err) => {
+                        eprintln!("can't record audio to {}: {}", record_path, err);
+                        process::exit(1);
+                    }
+                }
+            }
+            for &channel in &cli.mute_channel {
+                gameboy_core.set_audio_channel_enabled(channel as usize - 1, false);
+            }
+            if let Some(frames) = cli.frames {
+                run_headless(&mut gameboy_core, frames);
+                return;
+            }
+            macroquad::Window::from_config(window_conf(&title), run(cli, gameboy_core))
+        }
     }
+}
 
-    let mut gameboy_core = rboy::core::Core::new(true);
-    gameboy_core.load_game_rom(&cli.path);
+async fn run(cli: RboyCli, mut gameboy_core: Core) {
     let screen = graphic::Screen::new(cli.scale, cli.debug);
+    let mut speaker = Speaker::new(
+        gameboy_core.sample_rate(),
+        cli.volume as f32 / 100.0,
+        cli.mute,
+    );
 
-    let mut i = 0;
-    let mut count = 0;
-
-    // let one_sixtyth = time::Duration::from_secs_f64(1.0 / 60.0);
-    // let mut now = time::Instant::now();
-
+    let mut lock_up_reported = false;
+    prevent_quit();
     loop {
-        // println!(
-        //     "----------------------------------\n{} {}",
-        //     count, gameboy_core.cpu
-        // );
-        count += 1;
+        if is_quit_requested() {
+            shut_down(&mut gameboy_core);
+            break;
+        }
         if gameboy_core.tick(cli.break_point) {
+            report_lock_up(&gameboy_core, &mut lock_up_reported);
+            for (key, button) in KEY_MAP {
+                gameboy_core.set_button(button, is_key_down(key));
+            }
+            handle_audio_keys(&mut gameboy_core, &mut speaker);
+            speaker.push(&gameboy_core.drain_audio_samples());
             clear_background(LIGHTGRAY);
 
-            screen.draw_frame();
-            screen.draw_bg_frame(gameboy_core.get_bg_frame_buffer());
-            screen.draw_tiles_frame(gameboy_core.get_tiles_frame_buffer());
+            screen.draw_frame(gameboy_core.frame_buffer());
             if cli.debug {
+                screen.draw_bg_frame(gameboy_core.get_bg_frame_buffer());
+                screen.draw_tiles_frame(gameboy_core.get_tiles_frame_buffer());
                 draw_text(
                     format!("FPS: {:.2}", 1.0 / get_frame_time()).as_str(),
                     0.,
@@ -75,12 +219,51 @@ async fn main() {
             }
             next_frame().await;
         }
-        // println!("count {}", count);
+    }
+}
+
+fn run_headless(gameboy_core: &mut Core, frames: u32) {
+    let mut frame = 0;
+    let mut lock_up_reported = false;
+    while frame < frames {
+        if gameboy_core.tick(None) {
+            report_lock_up(gameboy_core, &mut lock_up_reported);
+            frame += 1;
+        }
+    }
+    shut_down(gameboy_core);
+}
 
-        // let r = i / core::constants::LCD_WIDTH;
-        // let c = i % core::constants::LCD_WIDTH;
-        // screen.update_pixel_in_buffer(c, r, 0);
-        // screen.draw_frame();
-        // i = (i + 1) % graphic::GAMEBOY_WINDOW_PIXELS;
+fn report_lock_up(gameboy_core: &Core, reported: &mut bool) {
+    if !*reported && gameboy_core.cpu.is_locked_up() {
+        eprintln!("illegal opcode, cpu locked up\n{}", gameboy_core.cpu);
+        *reported = true;
+    }
+}
+
+fn shut_down(gameboy_core: &mut Core) {
+    if let Err(err) = gameboy_core.save() {
+        eprintln!("can't write save: {}", err);
+    }
+    if let Err(err) = gameboy_core.finish_audio() {
+        eprintln!("can't write audio recording: {}", err);
+    }
+}
+
+fn handle_audio_keys(gameboy_core: &mut Core, speaker: &mut Speaker) {
+    if is_key_pressed(MUTE_KEY) {
+        speaker.set_muted(!speaker.muted());
+    }
+    if is_key_pressed(VOLUME_DOWN_KEY) {
+        speaker.set_volume(speaker.volume() - VOLUME_STEP);
+    }
+    if is_key_pressed(VOLUME_UP_KEY) {
+        speaker.set_volume(speaker.volume() + VOLUME_STEP);
+    }
+    for (channel, key) in CHANNEL_KEYS.into_iter().enumerate() {
+        if is_key_pressed(key) {
+            let enabled = gameboy_core.audio_channel_enabled(channel);
+            gameboy_core.set_audio_channel_enabled(channel, !enabled);
+        }
     }
 }
@@ -0,0 +1,729 @@
+use crate::core::constants::*;
+
+const CYCLES_PER_SECOND: u32 = 4_194_304;
+pub const DEFAULT_SAMPLE_RATE: u32 = 48_000;
+/// The frame sequencer steps on falling edges of this DIV bit, 512 Hz.
+pub const DIV_FRAME_SEQUENCER_BIT: u8 = 1 << 4;
+
+/// Waveforms of the square channels, one bit per duty step.
+const DUTY_PATTERNS: [u8; 4] = [0b0000_0001, 0b1000_0001, 0b1000_0111, 0b0111_1110];
+const MAX_PERIOD: u16 = 0x7ff;
+/// NRx4 trigger and length enable bits
+const TRIGGER: u8 = 0x80;
+const LENGTH_ENABLE: u8 = 0x40;
+
+/// Length timer, stops its channel when it runs out.
+#[derive(Default)]
+struct Length {
+    enabled: bool,
+    remaining: u16,
+}
+
+impl Length {
+    /// Returns false when the channel has to stop.
+    fn tick(&mut self) -> bool {
+        if self.enabled && self.remaining > 0 {
+            self.remaining -= 1;
+            return self.remaining > 0;
+        }
+        true
+    }
+    fn trigger(&mut self, max: u16) {
+        if self.remaining == 0 {
+            self.remaining = max;
+        }
+    }
+}
+
+/// Volume envelope, NRx2.
+#[derive(Default)]
+struct Envelope {
+    initial: u8,
+    increase: bool,
+    /// 64 Hz ticks between volume steps, 0 holds the volume
+    pace: u8,
+    timer: u8,
+    volume: u8,
+}
+
+impl Envelope {
+    fn set(&mut self, val: u8) {
+        self.initial = val >> 4;
+        self.increase = val & 0x08 != 0;
+        self.pace = val & 0x07;
+    }
+    /// The DAC is off when NRx2 sets a 0 volume that decreases.
+    fn dac_enabled(val: u8) -> bool {
+        val & 0xf8 != 0
+    }
+    fn trigger(&mut self) {
+        self.timer = self.pace;
+        self.volume = self.initial;
+    }
+    fn tick(&mut self) {
+        if self.pace == 0 {
+            return;
+        }
+        self.timer = self.timer.saturating_sub(1);
+        if self.timer > 0 {
+            return;
+        }
+        self.timer = self.pace;
+        if self.increase && self.volume < 15 {
+            self.volume += 1;
+        } else if !self.increase && self.volume > 0 {
+            self.volume -= 1;
+        }
+    }
+}
+
+/// Channel 1 period sweep, NR10.
+#[derive(Default)]
+struct Sweep {
+    /// 128 Hz ticks between period updates
+    pace: u8,
+    decrease: bool,
+    shift: u8,
+    timer: u8,
+    enabled: bool,
+    /// period the sweep works on, writes to NR13/NR14 don't change it
+    shadow: u16,
+}
+
+impl Sweep {
+    fn set(&mut self, val: u8) {
+        self.pace = (val >> 4) & 0x07;
+        self.decrease = val & 0x08 != 0;
+        self.shift = val & 0x07;
+    }
+    fn reload_timer(&mut self) {
+        // a 0 pace still runs the timer as 8
+        self.timer = if self.pace == 0 { 8 } else { self.pace };
+    }
+    fn next_period(&self) -> u16 {
+        let delta = self.shadow >> self.shift;
+        if self.decrease {
+            self.shadow - delta
+        } else {
+            self.shadow + delta
+        }
+    }
+}
+
+/// Channels 1 and 2.
+struct Square {
+    enabled: bool,
+    dac: bool,
+    duty: u8,
+    duty_step: u8,
+    period: u16,
+    /// cycles left before the next duty step
+    timer: i32,
+    length: Length,
+    envelope: Envelope,
+    /// channel 1 only
+    sweep: Option<Sweep>,
+}
+
+impl Square {
+    fn new(sweep: bool) -> Square {
+        Square {
+            enabled: false,
+            dac: false,
+            duty: 0,
+            duty_step: 0,
+            period: 0,
+            timer: 0,
+            length: Length::default(),
+            envelope: Envelope::default(),
+            sweep: sweep.then(Sweep::default),
+        }
+    }
+    fn period_cycles(&self) -> i32 {
+        (2048 - self.period as i32) * 4
+    }
+    fn trigger(&mut self) {
+        self.enabled = self.dac;
+        self.length.trigger(64);
+        self.timer = self.period_cycles();
+        self.envelope.trigger();
+        if let Some(sweep) = &mut self.sweep {
+            sweep.shadow = self.period;
+            sweep.reload_timer();
+            sweep.enabled = sweep.pace != 0 || sweep.shift != 0;
+            // the overflow check runs right away
+            if sweep.shift != 0 && sweep.next_period() > MAX_PERIOD {
+                self.enabled = false;
+            }
+        }
+    }
+    /// Returns the new period when the sweep changed it.
+    fn tick_sweep(&mut self) -> Option<u16> {
+        let sweep = self.sweep.as_mut()?;
+        sweep.timer = sweep.timer.saturating_sub(1);
+        if sweep.timer > 0 {
+            return None;
+        }
+        sweep.reload_timer();
+        if !sweep.enabled || sweep.pace == 0 {
+            return None;
+        }
+        let period = sweep.next_period();
+        if period > MAX_PERIOD {
+            self.enabled = false;
+            return None;
+        }
+        if sweep.shift == 0 {
+            return None;
+        }
+        sweep.shadow = period;
+        self.period = period;
+        // and once more with the new period
+        if sweep.next_period() > MAX_PERIOD {
+            self.enabled = false;
+        }
+        Some(period)
+    }
+    fn tick(&mut self, cycles: i32) {
+        self.timer -= cycles;
+        while self.timer <= 0 {
+            self.timer += self.period_cycles();
+            self.duty_step = (self.duty_step + 1) % 8;
+        }
+    }
+    /// Digital output, None when the DAC is off.
+    fn output(&self) -> Option<u8> {
+        if !self.dac {
+            return None;
+        }
+        let high = DUTY_PATTERNS[self.duty as usize] & (0x80 >> self.duty_step) != 0;
+        Some(if self.enabled && high {
+            self.envelope.volume
+        } else {
+            0
+        })
+    }
+}
+
+/// Channel 3, plays the 32 4-bit samples of wave RAM.
+#[derive(Default)]
+struct Wave {
+    enabled: bool,
+    dac: bool,
+    /// NR32 output level, 0 mutes and 1 to 3 shift the sample right by 0 to 2
+    level: u8,
+    period: u16,
+    timer: i32,
+    position: u8,
+    length: Length,
+}
+
+impl Wave {
+    fn period_cycles(&self) -> i32 {
+        (2048 - self.period as i32) * 2
+    }
+    fn trigger(&mut self) {
+        self.enabled = self.dac;
+        self.length.trigger(256);
+        self.timer = self.period_cycles();
+        self.position = 0;
+    }
+    fn tick(&mut self, cycles: i32) {
+        if !self.enabled {
+            return;
+        }
+        self.timer -= cycles;
+        while self.timer <= 0 {
+            self.timer += self.period_cycles();
+            self.position = (self.position + 1) % 32;
+        }
+    }
+    fn output(&self, wave_ram: &[u8; 16]) -> Option<u8> {
+        if !self.dac {
+            return None;
+        }
+        if !self.enabled || self.level == 0 {
+            return Some(0);
+        }
+        // high nibble first
+        let byte = wave_ram[self.position as usize / 2];
+        let sample = if self.position & 1 == 0 {
+            byte >> 4
+        } else {
+            byte & 0x0f
+        };
+        Some(sample >> (self.level - 1))
+    }
+}
+
+/// Channel 4, pseudo-random noise from a linear feedback shift register.
+#[derive(Default)]
+struct Noise {
+    enabled: bool,
+    dac: bool,
+    /// NR43
+    clock_shift: u8,
+    narrow: bool,
+    divider: u8,
+    timer: i32,
+    lfsr: u16,
+    length: Length,
+    envelope: Envelope,
+}
+
+impl Noise {
+    fn period_cycles(&self) -> i32 {
+        let divisor = if self.divider == 0 {
+            8
+        } else {
+            self.divider as i32 * 16
+        };
+        divisor << self.clock_shift
+    }
+    fn trigger(&mut self) {
+        self.enabled = self.dac;
+        self.length.trigger(64);
+        self.timer = self.period_cycles();
+        self.envelope.trigger();
+        self.lfsr = 0;
+    }
+    fn tick(&mut self, cycles: i32) {
+        // shifts 14 and 15 don't clock the LFSR
+        if !self.enabled || self.clock_shift >= 14 {
+            return;
+        }
+        self.timer -= cycles;
+        while self.timer <= 0 {
+            self.timer += self.period_cycles();
+            let bit = !(self.lfsr ^ (self.lfsr >> 1)) & 1;
+            self.lfsr = (self.lfsr & 0x7fff) | (bit << 15);
+            // 7-bit mode copies the new bit to bit 7 as well
+            if self.narrow {
+                self.lfsr = (self.lfsr & !0x80) | (bit << 7);
+            }
+            self.lfsr >>= 1;
+        }
+    }
+    fn output(&self) -> Option<u8> {
+        if !self.dac {
+            return None;
+        }
+        Some(if self.enabled && self.lfsr & 1 != 0 {
+            self.envelope.volume
+        } else {
+            0
+        })
+    }
+}
+
+/// 0 to 15 from a channel to -1.0 to 1.0, a disabled DAC outputs silence.
+fn dac_output(digital: Option<u8>) -> f32 {
+    match digital {
+        Some(val) => val as f32 / 7.5 - 1.0,
+        None => 0.0,
+    }
+}
+
+/// Audio processing unit, four channels mixed into interleaved stereo samples at
+/// `sample_rate`.
+pub struct APU {
+    /// NR10 to NR51 as written, 0xff10-0xff25
+    regs: [u8; 0x16],
+    wave_ram: [u8; 16],
+    power: bool,
+    square1: Square,
+    square2: Square,
+    wave: Wave,
+    noise: Noise,
+    /// host side channel toggles, muted channels still run
+    channel_enabled: [bool; 4],
+    /// next frame sequencer step, 0 to 7
+    frame_step: u8,
+    /// cycles not run yet, channels advance a whole M-cycle at a time
+    pending_cycles: u8,
+    sample_rate: u32,
+    /// towards the next sample, in sample_rate units per cycle
+    sample_clock: u32,
+    /// mix accumulated since the last sample
+    sum: (f32, f32),
+    sum_count: u32,
+    /// DC blocking high-pass filter state, as the output capacitor does
+    capacitor: (f32, f32),
+    charge_factor: f32,
+    samples: Vec<f32>,
+}
+
+impl Default for APU {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl APU {
+    pub fn new() -> APU {
+        let mut apu = APU {
+            regs: [0; 0x16],
+            wave_ram: [0; 16],
+            power: false,
+            square1: Square::new(true),
+            square2: Square::new(false),
+            wave: Wave::default(),
+            noise: Noise::default(),
+            channel_enabled: [true; 4],
+            frame_step: 0,
+            pending_cycles: 0,
+            sample_rate: DEFAULT_SAMPLE_RATE,
+            sample_clock: 0,
+            sum: (0.0, 0.0),
+            sum_count: 0,
+            capacitor: (0.0, 0.0),
+            charge_factor: 0.0,
+            samples: Vec::new(),
+        };
+        apu.set_sample_rate(DEFAULT_SAMPLE_RATE);
+        apu
+    }
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate;
+        self.sample_clock = 0;
+        self.charge_factor = 0.999958f32.powf(CYCLES_PER_SECOND as f32 / sample_rate as f32);
+    }
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+    /// Leave `channel`, 0 to 3, out of the mix.
+    pub fn set_channel_enabled(&mut self, channel: usize, enabled: bool) {
+        self.channel_enabled[channel] = enabled;
+    }
+    pub fn channel_enabled(&self, channel: usize) -> bool {
+        self.channel_enabled[channel]
+    }
+    /// Interleaved left and right samples produced since the last call. At most a second is
+    /// kept when nobody drains them.
+    pub fn drain_samples(&mut self) -> Vec<f32> {
+        std::mem::take(&mut self.samples)
+    }
+    /// 512 Hz clock from DIV, lengths at 256 Hz, the sweep at 128 Hz and envelopes at 64 Hz.
+    pub fn step_frame_sequencer(&mut self) {
+        if !self.power {
+            return;
+        }
+        let step = self.frame_step;
+        self.frame_step = (self.frame_step + 1) % 8;
+        if step & 1 == 0 {
+            self.square1.enabled &= self.square1.length.tick();
+            self.square2.enabled &= self.square2.length.tick();
+            self.wave.enabled &= self.wave.length.tick();
+            self.noise.enabled &= self.noise.length.tick();
+        }
+        if step == 2 || step == 6 {
+            if let Some(period) = self.square1.tick_sweep() {
+                self.regs[(NR13_RW - NR10_RW) as usize] = period as u8;
+                let nr14 = &mut self.regs[(NR14_RW - NR10_RW) as usize];
+                *nr14 = (*nr14 & !0x07) | (period >> 8) as u8;
+            }
+        }
+        if step == 7 {
+            self.square1.envelope.tick();
+            self.square2.envelope.tick();
+            self.noise.envelope.tick();
+        }
+    }
+    /// Run for `cycles` in 4 MHz, a multiple of 2.
+    pub fn tick(&mut self, cycles: u8) {
+        self.pending_cycles += cycles;
+        while self.pending_cycles >= 4 {
+            self.pending_cycles -= 4;
+            if self.power {
+                self.square1.tick(4);
+                self.square2.tick(4);
+                self.wave.tick(4);
+                self.noise.tick(4);
+            }
+            let (left, right) = self.mix();
+            self.sum.0 += left;
+            self.sum.1 += right;
+            self.sum_count += 1;
+            self.sample_clock += self.sample_rate * 4;
+            if self.sample_clock >= CYCLES_PER_SECOND {
+                self.sample_clock -= CYCLES_PER_SECOND;
+                self.push_sample();
+            }
+        }
+    }
+    /// NR50 master volume and NR51 panning.
+    fn mix(&self) -> (f32, f32) {
+        if !self.power {
+            return (0.0, 0.0);
+        }
+        let outputs = [
+            dac_output(self.square1.output()),
+            dac_output(self.square2.output()),
+            dac_output(self.wave.output(&self.wave_ram)),
+            dac_output(self.noise.output()),
+        ];
+        let panning = self.regs[(NR51_RW - NR10_RW) as usize];
+        let (mut left, mut right) = (0.0, 0.0);
+        for (channel, output) in outputs.iter().enumerate() {
+            if !self.channel_enabled[channel] {
+                continue;
+            }
+            if panning & (0x10 << channel) != 0 {
+                left += output;
+            }
+            if panning & (0x01 << channel) != 0 {
+                right += output;
+            }
+        }
+        let volume = self.regs[(NR50_RW - NR10_RW) as usize];
+        let left_volume = (((volume >> 4) & 0x07) + 1) as f32 / 8.0;
+        let right_volume = ((volume & 0x07) + 1) as f32 / 8.0;
+        (left / 4.0 * left_volume, right / 4.0 * right_volume)
+    }
+    fn push_sample(&mut self) {
+        let count = self.sum_count.max(1) as f32;
+        let (left, right) = (self.sum.0 / count, self.sum.1 / count);
+        self.sum = (0.0, 0.0);
+        self.sum_count = 0;
+        let left_out = left - self.capacitor.0;
+        let right_out = right - self.capacitor.1;
+        self.capacitor.0 = left - left_out * self.charge_factor;
+        self.capacitor.1 = right - right_out * self.charge_factor;
+        if self.samples.len() < 2 * self.sample_rate as usize {
+            self.samples.push(left_out);
+            self.samples.push(right_out);
+        }
+    }
+    pub fn get(&self, addr: u16) -> u8 {
+        match addr {
+            NR52_RW => {
+                let channels = [
+                    self.square1.enabled,
+                    self.square2.enabled,
+                    self.wave.enabled,
+                    self.noise.enabled,
+                ];
+                let status = channels
+                    .iter()
+                    .enumerate()
+                    .fold(0, |status, (bit, &on)| status | ((on as u8) << bit));
+                ((self.power as u8) << 7) | status
+            }
+            // the CPU sees the byte being played while the wave channel runs
+            WAVE_RAM_START..=WAVE_RAM_END if self.wave.enabled => {
+                self.wave_ram[self.wave.position as usize / 2]
+            }
+            WAVE_RAM_START..=WAVE_RAM_END => self.wave_ram[(addr - WAVE_RAM_START) as usize],
+            NR10_RW..NR52_RW => self.regs[(addr - NR10_RW) as usize],
+            _ => 0xff,
+        }
+    }
+    pub fn set(&mut self, addr: u16, val: u8) {
+        match addr {
+            NR52_RW => self.set_power(val & 0x80 != 0),
+            WAVE_RAM_START..=WAVE_RAM_END if self.wave.enabled => {
+                self.wave_ram[self.wave.position as usize / 2] = val
+            }
+            WAVE_RAM_START..=WAVE_RAM_END => self.wave_ram[(addr - WAVE_RAM_START) as usize] = val,
+            // registers are read only while off, but DMG still loads the length timers
+            NR11_RW | NR21_RW | NR31_RW | NR41_RW if !self.power => self.set_length(addr, val),
+            NR10_RW..NR52_RW if self.power => {
+                self.regs[(addr - NR10_RW) as usize] = val;
+                self.set_channel(addr, val);
+            }
+            _ => {}
+        }
+    }
+    fn set_power(&mut self, power: bool) {
+        if power && !self.power {
+            self.frame_step = 0;
+        }
+        if !power && self.power {
+            self.regs = [0; 0x16];
+            self.square1 = Square::new(true);
+            self.square2 = Square::new(false);
+            self.wave = Wave::default();
+            self.noise = Noise::default();
+        }
+        self.power = power;
+    }
+    fn set_length(&mut self, addr: u16, val: u8) {
+        match addr {
+            NR11_RW => self.square1.length.remaining = 64 - (val & 0x3f) as u16,
+            NR21_RW => self.square2.length.remaining = 64 - (val & 0x3f) as u16,
+            NR31_RW => self.wave.length.remaining = 256 - val as u16,
+            _ => self.noise.length.remaining = 64 - (val & 0x3f) as u16,
+        }
+    }
+    fn set_channel(&mut self, addr: u16, val: u8) {
+        match addr {
+            NR10_RW => {
+                if let Some(sweep) = &mut self.square1.sweep {
+                    sweep.set(val);
+                }
+            }
+            NR11_RW | NR21_RW | NR31_RW | NR41_RW => {
+                if addr == NR11_RW {
+                    self.square1.duty = val >> 6;
+                } else if addr == NR21_RW {
+                    self.square2.duty = val >> 6;
+                }
+                self.set_length(addr, val);
+            }
+            NR12_RW | NR22_RW | NR42_RW => {
+                let (envelope, enabled, dac) = match addr {
+                    NR12_RW => (
+                        &mut self.square1.envelope,
+                        &mut self.square1.enabled,
+                        &mut self.square1.dac,
+                    ),
+                    NR22_RW => (
+                        &mut self.square2.envelope,
+                        &mut self.square2.enabled,
+                        &mut self.square2.dac,
+                    ),
+                    _ => (
+                        &mut self.noise.envelope,
+                        &mut self.noise.enabled,
+                        &mut self.noise.dac,
+                    ),
+                };
+                envelope.set(val);
+                *dac = Envelope::dac_enabled(val);
+                *enabled &= *dac;
+            }
+            NR13_RW => self.square1.period = (self.square1.period & 0x700) | val as u16,
+            NR23_RW => self.square2.period = (self.square2.period & 0x700) | val as u16,
+            NR33_RW => self.wave.period = (self.wave.period & 0x700) | val as u16,
+            NR14_RW | NR24_RW => {
+                let square = if addr == NR14_RW {
+                    &mut self.square1
+                } else {
+                    &mut self.square2
+                };
+                square.period = (square.period & 0xff) | ((val & 0x07) as u16) << 8;
+                square.length.enabled = val & LENGTH_ENABLE != 0;
+                if val & TRIGGER != 0 {
+                    square.trigger();
+                }
+            }
+            NR30_RW => {
+                self.wave.dac = val & 0x80 != 0;
+                self.wave.enabled &= self.wave.dac;
+            }
+            NR32_RW => self.wave.level = (val >> 5) & 0x03,
+            NR34_RW => {
+                self.wave.period = (self.wave.period & 0xff) | ((val & 0x07) as u16) << 8;
+                self.wave.length.enabled = val & LENGTH_ENABLE != 0;
+                if val & TRIGGER != 0 {
+                    self.wave.trigger();
+                }
+            }
+            NR43_RW => {
+                self.noise.clock_shift = val >> 4;
+                self.noise.narrow = val & 0x08 != 0;
+                self.noise.divider = val & 0x07;
+            }
+            NR44_RW => {
+                self.noise.length.enabled = val & LENGTH_ENABLE != 0;
+                if val & TRIGGER != 0 {
+                    self.noise.trigger();
+                }
+            }
+            // NR50 and NR51 are read when mixing
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn powered_apu() -> APU {
+        let mut apu = APU::new();
+        apu.set(NR52_RW, 0x80);
+        apu
+    }
+
+    #[test]
+    fn length_expiry_stops_the_channel() {
+        let mut apu = powered_apu();
+        apu.set(NR12_RW, 0xf0);
+        // 2 length ticks
+        apu.set(NR11_RW, 0x3e);
+        apu.set(NR14_RW, TRIGGER | LENGTH_ENABLE);
+        assert_eq!(apu.get(NR52_RW), 0x81);
+        // lengths tick on even steps
+        apu.step_frame_sequencer();
+        apu.step_frame_sequencer();
+        assert_eq!(apu.get(NR52_RW), 0x81);
+        apu.step_frame_sequencer();
+        assert_eq!(apu.get(NR52_RW), 0x80);
+    }
+
+    #[test]
+    fn sweep_overflow_stops_channel_1() {
+        let mut apu = powered_apu();
+        apu.set(NR12_RW, 0xf0);
+        // pace 1, add period / 2
+        apu.set(NR10_RW, 0x11);
+        apu.set(NR13_RW, 0x00);
+        apu.set(NR14_RW, TRIGGER | 0x05);
+        assert_eq!(apu.get(NR52_RW), 0x81);
+        // the sweep runs on step 2, 0x500 becomes 0x780 and the next 0x780 + 0x3c0 overflows
+        for _ in 0..3 {
+            apu.step_frame_sequencer();
+        }
+        assert_eq!(apu.get(NR13_RW), 0x80);
+        assert_eq!(apu.get(NR14_RW) & 0x07, 0x07);
+        assert_eq!(apu.get(NR52_RW), 0x80);
+        // a trigger that overflows right away doesn't start the channel
+        apu.set(NR14_RW, TRIGGER | 0x07);
+        assert_eq!(apu.get(NR52_RW), 0x80);
+    }
+
+    #[test]
+    fn power_off_clears_the_registers() {
+        let mut apu = powered_apu();
+        apu.set(NR50_RW, 0x77);
+        apu.set(NR51_RW, 0xff);
+        apu.set(NR12_RW, 0xf0);
+        apu.set(NR14_RW, TRIGGER);
+        apu.set(WAVE_RAM_START, 0x12);
+        apu.set(NR52_RW, 0x00);
+        assert_eq!(apu.get(NR52_RW), 0x00);
+        assert_eq!(apu.get(NR50_RW), 0x00);
+        assert_eq!(apu.get(NR51_RW), 0x00);
+        assert_eq!(apu.get(NR12_RW), 0x00);
+        // writes are dropped while off, wave RAM is kept and stays writable
+        apu.set(NR50_RW, 0x77);
+        assert_eq!(apu.get(NR50_RW), 0x00);
+        assert_eq!(apu.get(WAVE_RAM_START), 0x12);
+        apu.set(WAVE_RAM_START + 1, 0x34);
+        assert_eq!(apu.get(WAVE_RAM_START + 1), 0x34);
+    }
+
+    #[test]
+    fn wave_ram_reads_follow_the_playing_byte() {
+        let mut apu = powered_apu();
+        for i in 0..16 {
+            apu.set(WAVE_RAM_START + i, i as u8 * 0x11);
+        }
+        apu.set(NR30_RW, 0x80);
+        // 2 cycles per sample at the top period
+        apu.set(NR33_RW, 0xff);
+        apu.set(NR34_RW, TRIGGER | 0x07);
+        // any address reads the byte holding the current sample
+        apu.tick(4);
+        assert_eq!(apu.wave.position, 2);
+        assert_eq!(apu.get(WAVE_RAM_START), 0x11);
+        assert_eq!(apu.get(WAVE_RAM_END), 0x11);
+        apu.set(WAVE_RAM_START + 5, 0xab);
+        assert_eq!(apu.get(WAVE_RAM_START + 1), 0xab);
+        // stopped, reads go to the address again
+        apu.set(NR30_RW, 0x00);
+        assert_eq!(apu.get(WAVE_RAM_START), 0x00);
+        assert_eq!(apu.get(WAVE_RAM_START + 1), 0xab);
+    }
+}
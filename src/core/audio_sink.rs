@@ -0,0 +1,222 @@
+use crate::core::apu::DEFAULT_SAMPLE_RATE;
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// Receives everything the APU outputs, one frame worth of samples at a time.
+pub trait AudioSink {
+    /// Interleaved left and right samples, -1.0 to 1.0, at `sample_rate`.
+    fn write(&mut self, samples: &[f32], sample_rate: u32) -> io::Result<()>;
+    /// End of the output, nothing is written after.
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+const WAV_HEADER_SIZE: u32 = 44;
+const CHANNELS: u16 = 2;
+const BITS_PER_SAMPLE: u16 = 16;
+
+/// 16-bit stereo PCM WAV at the rate of the first write. The header is brought up to date about
+/// once per second of audio, so the file stays playable up to then if the run dies before
+/// `finish`.
+pub struct WavWriter<W: Write + Seek> {
+    writer: W,
+    sample_rate: Option<u32>,
+    /// bytes of sample data written
+    data_size: u32,
+    /// bytes written since the header was last updated
+    unsynced_size: u32,
+    finished: bool,
+}
+
+impl WavWriter<BufWriter<File>> {
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        WavWriter::new(BufWriter::new(File::create(path)?))
+    }
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    pub fn new(mut writer: W) -> io::Result<Self> {
+        writer.write_all(&[0; WAV_HEADER_SIZE as usize])?;
+        Ok(WavWriter {
+            writer,
+            sample_rate: None,
+            data_size: 0,
+            unsynced_size: 0,
+            finished: false,
+        })
+    }
+    fn write_header(&mut self) -> io::Result<()> {
+        let sample_rate = self.sample_rate.unwrap_or(DEFAULT_SAMPLE_RATE);
+        let block_align = CHANNELS * BITS_PER_SAMPLE / 8;
+        self.unsynced_size = 0;
+        let mut header = Vec::with_capacity(WAV_HEADER_SIZE as usize);
+        header.extend_from_slice(b"RIFF");
+        header.extend_from_slice(&(WAV_HEADER_SIZE - 8 + self.data_size).to_le_bytes());
+        header.extend_from_slice(b"WAVE");
+        header.extend_from_slice(b"fmt ");
+        header.extend_from_slice(&16u32.to_le_bytes());
+        // PCM
+        header.extend_from_slice(&1u16.to_le_bytes());
+        header.extend_from_slice(&CHANNELS.to_le_bytes());
+        header.extend_from_slice(&sample_rate.to_le_bytes());
+        header.extend_from_slice(&(sample_rate * block_align as u32).to_le_bytes());
+        header.extend_from_slice(&block_align.to_le_bytes());
+        header.extend_from_slice(&BITS_PER_SAMPLE.to_le_bytes());
+        header.extend_from_slice(b"data");
+        header.extend_from_slice(&self.data_size.to_le_bytes());
+        self.writer.seek(SeekFrom::Start(0))?;
+        self.writer.write_all(&header)?;
+        self.writer.seek(SeekFrom::End(0))?;
+        Ok(())
+    }
+}
+
+impl<W: Write + Seek> AudioSink for WavWriter<W> {
+    fn write(&mut self, samples: &[f32], sample_rate: u32) -> io::Result<()> {
+        if *self.sample_rate.get_or_insert(sample_rate) != sample_rate {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "sample rate changed during the recording",
+            ));
+        }
+        let bytes: Vec<u8> = samples
+            .iter()
+            .flat_map(|&sample| ((sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16).to_le_bytes())
+            .collect();
+        // the RIFF chunk size has to fit in 32 bits as well
+        let data_size = u32::try_from(bytes.len())
+            .ok()
+            .and_then(|len| self.data_size.checked_add(len))
+            .filter(|size| size.checked_add(WAV_HEADER_SIZE - 8).is_some())
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "recording is too long for a WAV file",
+                )
+            })?;
+        self.writer.write_all(&bytes)?;
+        self.data_size = data_size;
+        self.unsynced_size += bytes.len() as u32;
+        let bytes_per_second = sample_rate * (CHANNELS * BITS_PER_SAMPLE / 8) as u32;
+        if self.unsynced_size >= bytes_per_second {
+            self.write_header()?;
+            self.writer.flush()?;
+        }
+        Ok(())
+    }
+    fn finish(&mut self) -> io::Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        self.finished = true;
+        self.write_header()?;
+        self.writer.flush()
+    }
+}
+
+impl<W: Write + Seek> Drop for WavWriter<W> {
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn u16_at(wav: &[u8], offset: usize) -> u16 {
+        u16::from_le_bytes([wav[offset], wav[offset + 1]])
+    }
+
+    fn u32_at(wav: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(wav[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn header_after_finish() {
+        let mut wav = Cursor::new(Vec::new());
+        let mut writer = WavWriter::new(&mut wav).unwrap();
+        writer.write(&[0.0, 1.0, -1.0, 0.5], 44100).unwrap();
+        writer.write(&[2.0, -2.0], 44100).unwrap();
+        writer.finish().unwrap();
+        drop(writer);
+        let wav = wav.into_inner();
+
+        assert_eq!(wav.len(), WAV_HEADER_SIZE as usize + 12);
+        assert_eq!(&wav[0..4], b"RIFF");
+        assert_eq!(u32_at(&wav, 4), wav.len() as u32 - 8);
+        assert_eq!(&wav[8..16], b"WAVEfmt ");
+        assert_eq!(u32_at(&wav, 16), 16);
+        // PCM, stereo, 44100 Hz, 4 bytes per frame, 16 bits
+        assert_eq!(u16_at(&wav, 20), 1);
+        assert_eq!(u16_at(&wav, 22), 2);
+        assert_eq!(u32_at(&wav, 24), 44100);
+        assert_eq!(u32_at(&wav, 28), 44100 * 4);
+        assert_eq!(u16_at(&wav, 32), 4);
+        assert_eq!(u16_at(&wav, 34), 16);
+        assert_eq!(&wav[36..40], b"data");
+        assert_eq!(u32_at(&wav, 40), 12);
+        let samples: Vec<i16> = wav[44..]
+            .chunks_exact(2)
+            .map(|b| i16::from_le_bytes([b[0], b[1]]))
+            .collect();
+        // out of range samples are clamped
+        assert_eq!(
+            samples,
+            [0, i16::MAX, -i16::MAX, i16::MAX / 2, i16::MAX, -i16::MAX]
+        );
+    }
+
+    #[test]
+    fn header_is_updated_every_second() {
+        let mut wav = Cursor::new(Vec::new());
+        let mut writer = WavWriter::new(&mut wav).unwrap();
+        writer.write(&[0.0; 8], DEFAULT_SAMPLE_RATE).unwrap();
+        assert_eq!(u32_at(writer.writer.get_ref(), 40), 0);
+        // a second of stereo samples
+        let second = vec![0.0; 2 * DEFAULT_SAMPLE_RATE as usize];
+        writer.write(&second, DEFAULT_SAMPLE_RATE).unwrap();
+        writer.write(&[0.0; 8], DEFAULT_SAMPLE_RATE).unwrap();
+        // a killed run never gets to finish
+        std::mem::forget(writer);
+        let wav = wav.into_inner();
+        let synced = 16 + 4 * DEFAULT_SAMPLE_RATE;
+        assert_eq!(wav.len() as u32, WAV_HEADER_SIZE + synced + 16);
+        assert_eq!(u32_at(&wav, 4), WAV_HEADER_SIZE - 8 + synced);
+        assert_eq!(u32_at(&wav, 24), DEFAULT_SAMPLE_RATE);
+        assert_eq!(u32_at(&wav, 40), synced);
+    }
+
+    #[test]
+    fn data_size_overflow_is_an_error() {
+        let mut wav = Cursor::new(Vec::new());
+        let mut writer = WavWriter::new(&mut wav).unwrap();
+        // room for 4 more bytes before the RIFF size overflows
+        writer.data_size = u32::MAX - (WAV_HEADER_SIZE - 8) - 4;
+        let err = writer.write(&[0.0; 4], 48000).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(writer.writer.get_ref().len(), WAV_HEADER_SIZE as usize);
+    }
+
+    #[test]
+    fn empty_recording_uses_the_default_rate() {
+        let mut wav = Cursor::new(Vec::new());
+        drop(WavWriter::new(&mut wav).unwrap());
+        let wav = wav.into_inner();
+        assert_eq!(wav.len(), WAV_HEADER_SIZE as usize);
+        assert_eq!(u32_at(&wav, 24), DEFAULT_SAMPLE_RATE);
+        assert_eq!(u32_at(&wav, 40), 0);
+    }
+
+    #[test]
+    fn sample_rate_change_is_an_error() {
+        let mut wav = Cursor::new(Vec::new());
+        let mut writer = WavWriter::new(&mut wav).unwrap();
+        writer.write(&[0.0; 2], 48000).unwrap();
+        let err = writer.write(&[0.0; 2], 44100).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+}
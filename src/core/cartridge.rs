@@ -0,0 +1,451 @@
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::io;
+
+pub const HEADER_END: usize = 0x014f;
+const TITLE_START: usize = 0x0134;
+const TITLE_END: usize = 0x0143;
+const CGB_FLAG: usize = 0x0143;
+const NEW_LICENSEE_CODE: usize = 0x0144;
+const SGB_FLAG: usize = 0x0146;
+const CARTRIDGE_TYPE: usize = 0x0147;
+const ROM_SIZE: usize = 0x0148;
+const RAM_SIZE: usize = 0x0149;
+const OLD_LICENSEE_CODE: usize = 0x014b;
+const MASK_ROM_VERSION: usize = 0x014c;
+const HEADER_CHECKSUM: usize = 0x014d;
+const GLOBAL_CHECKSUM: usize = 0x014e;
+
+/// old licensee code telling the new one should be used instead
+const USE_NEW_LICENSEE: u8 = 0x33;
+
+pub const ROM_BANK_SIZE: usize = 0x4000;
+pub const RAM_BANK_SIZE: usize = 0x2000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CgbSupport {
+    None,
+    /// works on DMG, uses CGB features when available
+    Enhanced,
+    Only,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MapperKind {
+    RomOnly,
+    MBC1,
+    MBC2,
+    MMM01,
+    MBC3,
+    MBC5,
+    MBC6,
+    MBC7,
+    PocketCamera,
+    TAMA5,
+    HuC3,
+    HuC1,
+}
+
+/// Decoded 0x0147 cartridge type byte.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CartridgeType {
+    pub code: u8,
+    pub mapper: MapperKind,
+    pub ram: bool,
+    pub battery: bool,
+    pub timer: bool,
+    pub rumble: bool,
+}
+
+impl CartridgeType {
+    pub fn from_code(code: u8) -> Option<CartridgeType> {
+        use MapperKind::*;
+        // mapper, ram, battery, timer, rumble
+        let (mapper, ram, battery, timer, rumble) = match code {
+            0x00 => (RomOnly, false, false, false, false),
+            0x01 => (MBC1, false, false, false, false),
+            0x02 => (MBC1, true, false, false, false),
+            0x03 => (MBC1, true, true, false, false),
+            0x05 => (MBC2, false, false, false, false),
+            0x06 => (MBC2, false, true, false, false),
+            0x08 => (RomOnly, true, false, false, false),
+            0x09 => (RomOnly, true, true, false, false),
+            0x0b => (MMM01, false, false, false, false),
+            0x0c => (MMM01, true, false, false, false),
+            0x0d => (MMM01, true, true, false, false),
+            0x0f => (MBC3, false, true, true, false),
+            0x10 => (MBC3, true, true, true, false),
+            0x11 => (MBC3, false, false, false, false),
+            0x12 => (MBC3, true, false, false, false),
+            0x13 => (MBC3, true, true, false, false),
+            0x19 => (MBC5, false, false, false, false),
+            0x1a => (MBC5, true, false, false, false),
+            0x1b => (MBC5, true, true, false, false),
+            0x1c => (MBC5, false, false, false, true),
+            0x1d => (MBC5, true, false, false, true),
+            0x1e => (MBC5, true, true, false, true),
+            0x20 => (MBC6, true, true, false, false),
+            0x22 => (MBC7, true, true, false, true),
+            0xfc => (PocketCamera, true, true, false, false),
+            0xfd => (TAMA5, true, true, true, false),
+            0xfe => (HuC3, true, true, true, false),
+            0xff => (HuC1, true, true, false, false),
+            _ => return None,
+        };
+        Some(CartridgeType {
+            code,
+            mapper,
+            ram,
+            battery,
+            timer,
+            rumble,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Licensee {
+    /// 0x014b
+    Old(u8),
+    /// two ASCII characters at 0x0144, used when the old code is 0x33
+    New([u8; 2]),
+}
+
+impl Display for Licensee {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Licensee::Old(code) => write!(f, "${:02x}", code),
+            Licensee::New(code) => write!(f, "{}", String::from_utf8_lossy(code)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CartridgeError {
+    /// the image can't be read
+    Io {
+        kind: io::ErrorKind,
+        message: String,
+    },
+    /// the image ends before the header does
+    TooSmall(usize),
+    UnknownCartridgeType(u8),
+    UnknownRomSize(u8),
+    UnknownRamSize(u8),
+    /// the boot rom locks up on a bad header checksum
+    HeaderChecksum {
+        expected: u8,
+        actual: u8,
+    },
+    /// not checked by the hardware
+    GlobalChecksum {
+        expected: u16,
+        actual: u16,
+    },
+    RomSizeMismatch {
+        expected: usize,
+        actual: usize,
+    },
+}
+
+impl Display for CartridgeError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            CartridgeError::Io { message, .. } => write!(f, "{}", message),
+            CartridgeError::TooSmall(len) => write!(
+                f,
+                "image is {} bytes, too small to hold a cartridge header",
+                len
+            ),
+            CartridgeError::UnknownCartridgeType(code) => {
+                write!(f, "unknown cartridge type ${:02x}", code)
+            }
+            CartridgeError::UnknownRomSize(code) => write!(f, "unknown rom size ${:02x}", code),
+            CartridgeError::UnknownRamSize(code) => write!(f, "unknown ram size ${:02x}", code),
+            CartridgeError::HeaderChecksum { expected, actual } => write!(
+                f,
+                "header checksum is ${:02x} but the header sums to ${:02x}",
+                expected, actual
+            ),
+            CartridgeError::GlobalChecksum { expected, actual } => write!(
+                f,
+                "global checksum is ${:04x} but the rom sums to ${:04x}",
+                expected, actual
+            ),
+            CartridgeError::RomSizeMismatch { expected, actual } => write!(
+                f,
+                "header declares {} bytes of rom but the image is {} bytes",
+                expected, actual
+            ),
+        }
+    }
+}
+
+impl Error for CartridgeError {}
+
+impl From<io::Error> for CartridgeError {
+    fn from(err: io::Error) -> Self {
+        CartridgeError::Io {
+            kind: err.kind(),
+            message: err.to_string(),
+        }
+    }
+}
+
+/// Parsed cartridge header, 0x0100 to 0x014f.
+#[derive(Debug, Clone)]
+pub struct Cartridge {
+    pub title: String,
+    pub cgb: CgbSupport,
+    pub sgb: bool,
+    pub cartridge_type: CartridgeType,
+    pub rom_size: usize,
+    pub ram_size: usize,
+    pub licensee: Licensee,
+    pub version: u8,
+    pub header_checksum: u8,
+    pub global_checksum: u16,
+    computed_header_checksum: u8,
+    computed_global_checksum: u16,
+    image_size: usize,
+}
+
+impl Cartridge {
+    /// Parse the header of `rom`, fails when the image can't be mapped at all.
+    pub fn parse(rom: &[u8]) -> Result<Cartridge, CartridgeError> {
+        if rom.len() <= HEADER_END {
+            return Err(CartridgeError::TooSmall(rom.len()));
+        }
+
+        let cgb = match rom[CGB_FLAG] {
+            0xc0 => CgbSupport::Only,
+            flag if flag & 0x80 != 0 => CgbSupport::Enhanced,
+            _ => CgbSupport::None,
+        };
+        // newer carts use the last bytes of the title for the manufacturer code and cgb flag
+        let title_end = if cgb == CgbSupport::None {
+            TITLE_END
+        } else {
+            CGB_FLAG - 1
+        };
+        let title: String = rom[TITLE_START..=title_end]
+            .iter()
+            .take_while(|&&c| c != 0)
+            .map(|&c| {
+                if c.is_ascii_graphic() || c == b' ' {
+                    c as char
+                } else {
+                    '?'
+                }
+            })
+            .collect();
+
+        let cartridge_type = CartridgeType::from_code(rom[CARTRIDGE_TYPE])
+            .ok_or(CartridgeError::UnknownCartridgeType(rom[CARTRIDGE_TYPE]))?;
+        let rom_size = match rom[ROM_SIZE] {
+            code @ 0x00..=0x08 => (2 * ROM_BANK_SIZE) << code,
+            code => return Err(CartridgeError::UnknownRomSize(code)),
+        };
+        let ram_size = match rom[RAM_SIZE] {
+            0x00 => 0,
+            // unofficial 2 KiB
+            0x01 => 0x800,
+            0x02 => RAM_BANK_SIZE,
+            0x03 => 4 * RAM_BANK_SIZE,
+            0x04 => 16 * RAM_BANK_SIZE,
+            0x05 => 8 * RAM_BANK_SIZE,
+            code => return Err(CartridgeError::UnknownRamSize(code)),
+        };
+        let licensee = match rom[OLD_LICENSEE_CODE] {
+            USE_NEW_LICENSEE => Licensee::New([rom[NEW_LICENSEE_CODE], rom[NEW_LICENSEE_CODE + 1]]),
+            code => Licensee::Old(code),
+        };
+
+        let computed_header_checksum = rom[TITLE_START..=MASK_ROM_VERSION]
+            .iter()
+            .fold(0u8, |sum, &b| sum.wrapping_sub(b).wrapping_sub(1));
+        let computed_global_checksum = rom
+            .iter()
+            .enumerate()
+            .filter(|(addr, _)| *addr != GLOBAL_CHECKSUM && *addr != GLOBAL_CHECKSUM + 1)
+            .fold(0u16, |sum, (_, &b)| sum.wrapping_add(b as u16));
+
+        Ok(Cartridge {
+            title,
+            cgb,
+            sgb: rom[SGB_FLAG] == 0x03,
+            cartridge_type,
+            rom_size,
+            ram_size,
+            licensee,
+            version: rom[MASK_ROM_VERSION],
+            header_checksum: rom[HEADER_CHECKSUM],
+            global_checksum: u16::from_be_bytes([rom[GLOBAL_CHECKSUM], rom[GLOBAL_CHECKSUM + 1]]),
+            computed_header_checksum,
+            computed_global_checksum,
+            image_size: rom.len(),
+        })
+    }
+
+    pub fn header_checksum_ok(&self) -> bool {
+        self.header_checksum == self.computed_header_checksum
+    }
+
+    pub fn global_checksum_ok(&self) -> bool {
+        self.global_checksum == self.computed_global_checksum
+    }
+
+    /// Problems that don't prevent the image from being mapped.
+    pub fn warnings(&self) -> Vec<CartridgeError> {
+        let mut warnings = Vec::new();
+        if !self.header_checksum_ok() {
+            warnings.push(CartridgeError::HeaderChecksum {
+                expected: self.header_checksum,
+                actual: self.computed_header_checksum,
+            });
+        }
+        if !self.global_checksum_ok() {
+            warnings.push(CartridgeError::GlobalChecksum {
+                expected: self.global_checksum,
+                actual: self.computed_global_checksum,
+            });
+        }
+        if self.rom_size != self.image_size {
+            warnings.push(CartridgeError::RomSizeMismatch {
+                expected: self.rom_size,
+                actual: self.image_size,
+            });
+        }
+        warnings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 32 KiB ROM only image with valid checksums.
+    fn image() -> Vec<u8> {
+        let mut rom = vec![0; 2 * ROM_BANK_SIZE];
+        rom[TITLE_START..TITLE_START + 4].copy_from_slice(b"TEST");
+        rom[OLD_LICENSEE_CODE] = 0x01;
+        fix_checksums(&mut rom);
+        rom
+    }
+
+    fn fix_checksums(rom: &mut [u8]) {
+        rom[HEADER_CHECKSUM] = rom[TITLE_START..=MASK_ROM_VERSION]
+            .iter()
+            .fold(0u8, |sum, &b| sum.wrapping_sub(b).wrapping_sub(1));
+        rom[GLOBAL_CHECKSUM] = 0;
+        rom[GLOBAL_CHECKSUM + 1] = 0;
+        let sum = rom.iter().fold(0u16, |sum, &b| sum.wrapping_add(b as u16));
+        rom[GLOBAL_CHECKSUM..GLOBAL_CHECKSUM + 2].copy_from_slice(&sum.to_be_bytes());
+    }
+
+    #[test]
+    fn valid_header() {
+        let cartridge = Cartridge::parse(&image()).unwrap();
+        assert_eq!(cartridge.title, "TEST");
+        assert_eq!(cartridge.cgb, CgbSupport::None);
+        assert_eq!(cartridge.cartridge_type.mapper, MapperKind::RomOnly);
+        assert_eq!(cartridge.rom_size, 2 * ROM_BANK_SIZE);
+        assert_eq!(cartridge.ram_size, 0);
+        assert_eq!(cartridge.licensee, Licensee::Old(0x01));
+        assert!(cartridge.header_checksum_ok());
+        assert!(cartridge.global_checksum_ok());
+        assert!(cartridge.warnings().is_empty());
+    }
+
+    #[test]
+    fn cgb_flag_shortens_the_title() {
+        let mut rom = image();
+        rom[TITLE_START..=TITLE_END].copy_from_slice(b"ABCDEFGHIJKLMNO\x80");
+        fix_checksums(&mut rom);
+        let cartridge = Cartridge::parse(&rom).unwrap();
+        assert_eq!(cartridge.cgb, CgbSupport::Enhanced);
+        assert_eq!(cartridge.title, "ABCDEFGHIJKLMNO");
+        rom[CGB_FLAG] = 0xc0;
+        assert_eq!(Cartridge::parse(&rom).unwrap().cgb, CgbSupport::Only);
+    }
+
+    #[test]
+    fn new_licensee() {
+        let mut rom = image();
+        rom[OLD_LICENSEE_CODE] = USE_NEW_LICENSEE;
+        rom[NEW_LICENSEE_CODE..NEW_LICENSEE_CODE + 2].copy_from_slice(b"01");
+        let cartridge = Cartridge::parse(&rom).unwrap();
+        assert_eq!(cartridge.licensee, Licensee::New(*b"01"));
+    }
+
+    #[test]
+    fn bad_header_checksum() {
+        let mut rom = image();
+        let actual = rom[HEADER_CHECKSUM];
+        rom[HEADER_CHECKSUM] = actual.wrapping_add(1);
+        let cartridge = Cartridge::parse(&rom).unwrap();
+        assert!(!cartridge.header_checksum_ok());
+        assert!(cartridge
+            .warnings()
+            .contains(&CartridgeError::HeaderChecksum {
+                expected: actual.wrapping_add(1),
+                actual,
+            }));
+    }
+
+    #[test]
+    fn bad_global_checksum() {
+        let mut rom = image();
+        rom[0x0200] = 0x12;
+        let cartridge = Cartridge::parse(&rom).unwrap();
+        assert!(cartridge.header_checksum_ok());
+        assert!(!cartridge.global_checksum_ok());
+        assert_eq!(
+            cartridge.warnings(),
+            vec![CartridgeError::GlobalChecksum {
+                expected: cartridge.global_checksum,
+                actual: cartridge.global_checksum.wrapping_add(0x12),
+            }]
+        );
+    }
+
+    #[test]
+    fn rom_size_mismatch() {
+        let mut rom = image();
+        rom[ROM_SIZE] = 0x01;
+        fix_checksums(&mut rom);
+        assert_eq!(
+            Cartridge::parse(&rom).unwrap().warnings(),
+            vec![CartridgeError::RomSizeMismatch {
+                expected: 4 * ROM_BANK_SIZE,
+                actual: 2 * ROM_BANK_SIZE,
+            }]
+        );
+    }
+
+    #[test]
+    fn unmappable_images() {
+        assert_eq!(
+            Cartridge::parse(&[0; HEADER_END]).unwrap_err(),
+            CartridgeError::TooSmall(HEADER_END)
+        );
+        let mut rom = image();
+        rom[CARTRIDGE_TYPE] = 0x04;
+        assert_eq!(
+            Cartridge::parse(&rom).unwrap_err(),
+            CartridgeError::UnknownCartridgeType(0x04)
+        );
+        let mut rom = image();
+        rom[ROM_SIZE] = 0x09;
+        assert_eq!(
+            Cartridge::parse(&rom).unwrap_err(),
+            CartridgeError::UnknownRomSize(0x09)
+        );
+        let mut rom = image();
+        rom[RAM_SIZE] = 0x06;
+        assert_eq!(
+            Cartridge::parse(&rom).unwrap_err(),
+            CartridgeError::UnknownRamSize(0x06)
+        );
+    }
+}
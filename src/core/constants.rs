@@ -4,10 +4,65 @@ pub const SCROLL_Y_RW: u16 = 0xff42;
 pub const SCROLL_X_RW: u16 = 0xff43;
 pub const Y_COORDINATE_R: u16 = 0xff44;
 pub const LY_COMPARE_RW: u16 = 0xff45;
+pub const OAM_DMA: u16 = 0xff46;
 pub const BG_PALETTE_DATA: u16 = 0xff47;
+pub const OBJ_PALETTE_0_DATA: u16 = 0xff48;
+pub const OBJ_PALETTE_1_DATA: u16 = 0xff49;
 pub const WINDOW_Y_POSITION_RW: u16 = 0xff4a;
 pub const WINDOW_X_POSITION_MINUS_7_RW: u16 = 0xff4b;
 
+pub const JOYPAD_RW: u16 = 0xff00;
+
+// CGB
+pub const SPEED_SWITCH_RW: u16 = 0xff4d;
+pub const VRAM_BANK_RW: u16 = 0xff4f;
+/// VRAM DMA source high and low, destination high and low, length/mode/start
+pub const HDMA1_W: u16 = 0xff51;
+pub const HDMA2_W: u16 = 0xff52;
+pub const HDMA3_W: u16 = 0xff53;
+pub const HDMA4_W: u16 = 0xff54;
+pub const HDMA5_RW: u16 = 0xff55;
+pub const CGB_BG_PALETTE_INDEX_RW: u16 = 0xff68;
+pub const CGB_BG_PALETTE_DATA_RW: u16 = 0xff69;
+pub const CGB_OBJ_PALETTE_INDEX_RW: u16 = 0xff6a;
+pub const CGB_OBJ_PALETTE_DATA_RW: u16 = 0xff6b;
+pub const WRAM_BANK_RW: u16 = 0xff70;
+pub const VRAM_BANK_SIZE: usize = 0x2000;
+pub const WRAM_BANK_SIZE: usize = 0x1000;
+/// 8 palettes of 4 little endian 15-bit colors
+pub const CGB_PALETTE_RAM_SIZE: usize = 64;
+
+// timer
+pub const DIVIDER_RW: u16 = 0xff04;
+pub const TIMER_COUNTER_RW: u16 = 0xff05;
+pub const TIMER_MODULO_RW: u16 = 0xff06;
+pub const TIMER_CONTROL_RW: u16 = 0xff07;
+
+// sound
+pub const NR10_RW: u16 = 0xff10;
+pub const NR11_RW: u16 = 0xff11;
+pub const NR12_RW: u16 = 0xff12;
+pub const NR13_RW: u16 = 0xff13;
+pub const NR14_RW: u16 = 0xff14;
+pub const NR21_RW: u16 = 0xff16;
+pub const NR22_RW: u16 = 0xff17;
+pub const NR23_RW: u16 = 0xff18;
+pub const NR24_RW: u16 = 0xff19;
+pub const NR30_RW: u16 = 0xff1a;
+pub const NR31_RW: u16 = 0xff1b;
+pub const NR32_RW: u16 = 0xff1c;
+pub const NR33_RW: u16 = 0xff1d;
+pub const NR34_RW: u16 = 0xff1e;
+pub const NR41_RW: u16 = 0xff20;
+pub const NR42_RW: u16 = 0xff21;
+pub const NR43_RW: u16 = 0xff22;
+pub const NR44_RW: u16 = 0xff23;
+pub const NR50_RW: u16 = 0xff24;
+pub const NR51_RW: u16 = 0xff25;
+pub const NR52_RW: u16 = 0xff26;
+pub const WAVE_RAM_START: u16 = 0xff30;
+pub const WAVE_RAM_END: u16 = 0xff3f;
+
 // interrupt
 pub const INTERRUPT_ENABLE: u16 = 0xffff;
 pub const INTERRUPT_FLAG: u16 = 0xff0f;
@@ -44,6 +99,7 @@ pub const EXTERNAL_RAM_START: usize = 0xa000;
 pub const RAM_START: usize = 0xc000;
 pub const OAM_RAM_START: usize = 0xfe00;
 pub const OAM_RAM_SIZE: usize = 0xa0;
+pub const UNUSABLE_START: usize = 0xfea0;
 
 /// Joypad addr
 pub const IO_START: usize = 0xff00;
@@ -51,7 +107,14 @@ pub const HRAM_START: usize = 0xff80;
 
 // clocks
 pub const OAM_CYCLE_IN_4MHZ: u16 = 80;
-pub const MAX_DRAWING_CYCLE_IN_4MHZ: u16 = 289;
-pub const DRAW_AND_HBLANK_CYCLE_IN_4MHZ: u16 = 376;
-pub const VBLANK_CYCLE_IN_4MHZ: u16 = 456;
+pub const SCANLINE_CYCLE_IN_4MHZ: u16 = 456;
+pub const LAST_LINE_LY_CYCLE_IN_4MHZ: u16 = 4;
 pub const VBLANK_END_LY: u8 = 153;
+/// 154 lines
+pub const FRAME_CYCLE_IN_4MHZ: u32 = SCANLINE_CYCLE_IN_4MHZ as u32 * (VBLANK_END_LY as u32 + 1);
+/// OAM DMA copies a byte every 4 cycles after a 4 cycle start up
+pub const OAM_DMA_STARTUP_CYCLE_IN_4MHZ: u8 = 4;
+pub const OAM_DMA_BYTE_CYCLE_IN_4MHZ: u8 = 4;
+/// VRAM DMA copies 16 bytes per block in 32 cycles, the CPU waits meanwhile
+pub const VRAM_DMA_BLOCK_SIZE: u16 = 16;
+pub const VRAM_DMA_BLOCK_CYCLE_IN_4MHZ: u16 = 32;
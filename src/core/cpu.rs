@@ -1,8 +1,11 @@
-use std::{fmt::{self, Display, Formatter}, thread::sleep, time::Duration};
+use std::fmt::{self, Display, Formatter};
 
 use crate::core::memory;
 
-use super::constants::{INTERRPUT_LIST, INTERRUPT_ENABLE, INTERRUPT_FLAG};
+use super::constants::{DIVIDER_RW, INTERRPUT_LIST, INTERRUPT_ENABLE, INTERRUPT_FLAG, JOYPAD_RW};
+use super::instruction::{
+    decode, decode_from, Condition, Instruction, Operand, Register16, Register8,
+};
 pub enum RegisterValue {
     A(u8),
     F(u8),
@@ -44,6 +47,16 @@ pub struct CPU {
     pub register_sp: u16,
     pub register_pc: u16,
     interrupt_master_enable_flag: bool,
+    /// EI was executed, IME is set once the next instruction completes
+    ime_scheduled: bool,
+    /// set after an illegal opcode, the cpu stops fetching until reset
+    locked_up: bool,
+    /// HALT, waiting for IF & IE to become non zero
+    halted: bool,
+    /// STOP, waiting for a joypad line to go low
+    stopped: bool,
+    /// HALT with IME off and an interrupt already pending fails to increment PC
+    halt_bug: bool,
 }
 
 macro_rules! set_register_value {
@@ -91,540 +104,222 @@ impl Display for CPU {
     }
 }
 
-macro_rules! ld {
-    ($self:expr, $mem:ident, $variant:ident, $get_mem:ident, $len:expr) => {{
-        let v = $self.$get_mem($mem);
-        $self.set_value(&RegisterValue::$variant(v));
-        $len
-    }};
-    ($self:expr, $mem:ident, "(HL)", $get_mem:ident, $len:expr) => {{
-        let v = $self.$get_mem($mem);
-        $self.set_mem_hl($mem, v);
-        $len
-    }};
-    ($self:expr, $to_v:ident, $from_v:ident, $len:expr) => {{
-        if let RegisterValue::$from_v(v) = $self.get_value(&RegisterValue::$from_v(0)) {
-            $self.set_value(&RegisterValue::$to_v(v));
-        }
-        $len
-    }};
-    ($self:expr, $mem:ident, ff($to_v:ident), $from_v:ident, $len:expr) => {{
-        if let RegisterValue::$from_v(v) = $self.get_value(&RegisterValue::$from_v(0)) {
-            if let RegisterValue::$to_v(addr0) = $self.get_value(&RegisterValue::$to_v(0)) {
-                let addr = addr0 as u16 + 0xff00;
-                $mem.set(addr, v);
-            }
-        }
-        $len
-    }};
-    ($self:expr, $mem:ident, ($to_v:ident), $from_v:ident, $len:expr) => {{
-        if let RegisterValue::$from_v(v) = $self.get_value(&RegisterValue::$from_v(0)) {
-            if let RegisterValue::$to_v(addr) = $self.get_value(&RegisterValue::$to_v(0)) {
-                $mem.set(addr, v);
-            }
-        }
-        $len
-    }};
-    ($self:expr, $mem:ident, $to_v:ident, ($from_v:ident), $len:expr) => {{
-        if let RegisterValue::$from_v(addr) = $self.get_value(&RegisterValue::$from_v(0)) {
-            $self.set_value(&RegisterValue::$to_v($mem.get(addr)));
-        }
-        $len
-    }};
-    ($self:expr, $mem:ident, "(HL)", $mem_shift:tt, $from_v:ident, $len:expr) => {{
-        if let RegisterValue::$from_v(v) = $self.get_value(&RegisterValue::$from_v(0)) {
-            if let RegisterValue::HL(addr) = $self.get_value(&RegisterValue::HL(0)) {
-                $mem.set(addr, v);
-                $self.set_value(&RegisterValue::HL(addr $mem_shift 1))
-            }
-        }
-        $len
-    }};
-    ($self:expr, $mem:ident, $to_v:ident, "(HL)", $mem_shift:tt, $len:expr) => {{
-        if let RegisterValue::HL(addr) = $self.get_value(&RegisterValue::HL(0)) {
-            let v = $mem.get(addr);
-            $self.set_value(&RegisterValue::HL(addr $mem_shift 1));
-            $self.set_value(&RegisterValue::$to_v(v));
-        }
-        $len
-    }};
-    ($self:expr, $mem:ident, "(a8)", $from_v:ident, $len:expr) => {{
-        if let RegisterValue::$from_v(v) = $self.get_value(&RegisterValue::$from_v(0)) {
-            let addr = $self.get_mem_a8($mem);
-            $mem.set(addr, v);
-        }
-        $len
-    }};
-    ($self:expr, $mem:ident, "(a16)", $from_v:ident, $len:expr) => {{
-        if let RegisterValue::$from_v(v) = $self.get_value(&RegisterValue::$from_v(0)) {
-            let addr = $self.get_mem_u16($mem);
-            $mem.set(addr, v);
-        }
-        $len
-    }};
-}
-macro_rules! ldh {
-    ($self:expr, $mem:ident, $to_v:ident, "(a8)", $len:expr) => {{
-        let addr = $self.get_mem_u8($mem) as u16 + 0xff00;
-        let v = $mem.get(addr);
-        $self.set_value(&RegisterValue::$to_v(v));
-        $len
-    }};
-    ($self:expr, $mem:ident, $to_v:ident, "(a16)", $len:expr) => {{
-        let addr = $self.get_mem_u16($mem);
-        let v = $mem.get(addr);
-        $self.set_value(&RegisterValue::$to_v(v));
-        $len
-    }};
-    ($self:expr, $mem:ident, "(a8)", $from_v:ident, $len:expr) => {{
-        if let RegisterValue::$from_v(v) = $self.get_value(&RegisterValue::$from_v(0)) {
-            let addr = $self.get_mem_u8($mem) as u16 + 0xff00;
-            $mem.set(addr, v);
-        }
-        $len
-    }};
-}
-macro_rules! push {
-    ($self:expr, $mem:ident, $reg:ident, $len:expr) => {{
-        if let RegisterValue::$reg(v) = $self.get_value(&RegisterValue::$reg(0)) {
-            $self.register_sp -= 1;
-            $mem.set($self.register_sp, (v >> 8) as u8);
-            $self.register_sp -= 1;
-            $mem.set($self.register_sp, (v & 0xff) as u8);
-        }
-        $len
-    }};
+/// decimal adjust A after a BCD add or subtract
+fn daa(cpu: &mut CPU) {
+    let mut a = cpu.register_a;
+    let mut c = cpu.get_flag(&Flag::C(false));
+    let h = cpu.get_flag(&Flag::H(false));
+    if !cpu.get_flag(&Flag::N(false)) {
+        if c || a > 0x99 {
+            a = a.wrapping_add(0x60);
+            c = true;
+        }
+        if h || (a & 0x0f) > 0x09 {
+            a = a.wrapping_add(0x06);
+        }
+    } else {
+        if c {
+            a = a.wrapping_sub(0x60);
+        }
+        if h {
+            a = a.wrapping_sub(0x06);
+        }
+    }
+    cpu.register_a = a;
+    cpu.set_flag(&Flag::Z(a == 0));
+    cpu.set_flag(&Flag::H(false));
+    cpu.set_flag(&Flag::C(c));
 }
 
-macro_rules! pop {
-    ($self:expr, $mem:ident, AF, $len:expr) => {{
-        $self.register_a = $mem.get($self.register_sp + 1);
-        $self.register_f = ($mem.get($self.register_sp) & 0xf0);
-        $self.register_sp += 2;
-        $len
-    }};
-    ($self:expr, $mem:ident, $reg:ident, $len:expr) => {{
-        let v =
-            $mem.get($self.register_sp) as u16 + (($mem.get($self.register_sp + 1) as u16) << 8);
-        $self.set_value(&RegisterValue::$reg(v));
-        $self.register_sp += 2;
-        $len
-    }};
+fn scf(cpu: &mut CPU) {
+    cpu.set_flag(&Flag::N(false));
+    cpu.set_flag(&Flag::H(false));
+    cpu.set_flag(&Flag::C(true));
 }
 
-macro_rules! dec {
-    ($self:expr, $mem:ident, (HL), $len:expr) => {{
-        if let RegisterValue::HL(addr) = $self.get_value(&RegisterValue::HL(0)) {
-            let v = $mem.get(addr);
-            let h = ((v & 0xf) == 0);
-            let v = v - 1;
-            $mem.set(addr, v);
-            $self.set_flag(&Flag::Z(v == 0));
-            $self.set_flag(&Flag::N(true));
-            $self.set_flag(&Flag::H(h));
-        }
-        $len
-    }};
-    ($self:expr, $reg:ident, $len:expr) => {{
-        if let RegisterValue::$reg(v) = $self.get_value(&RegisterValue::$reg(0)) {
-            let h = ((v & 0xf) == 0);
-            let v = v - 1;
-            $self.set_value(&RegisterValue::$reg(v));
-            $self.set_flag(&Flag::Z(v == 0));
-            $self.set_flag(&Flag::N(true));
-            $self.set_flag(&Flag::H(h));
-        }
-        $len
-    }};
-    ($self:expr, $reg:ident, $len:expr, no_flag) => {{
-        if let RegisterValue::$reg(v) = $self.get_value(&RegisterValue::$reg(0)) {
-            let v = v - 1;
-            $self.set_value(&RegisterValue::$reg(v));
-        }
-        $len
-    }};
+fn ccf(cpu: &mut CPU) {
+    let c = cpu.get_flag(&Flag::C(false));
+    cpu.set_flag(&Flag::N(false));
+    cpu.set_flag(&Flag::H(false));
+    cpu.set_flag(&Flag::C(!c));
 }
 
-macro_rules! inc {
-    ($self:expr, $mem:ident, (HL), 12) => {{
-        if let RegisterValue::HL(addr) = $self.get_value(&RegisterValue::HL(0)) {
-            let v = $mem.get(addr);
-            let v = v + 1;
-            $mem.set(addr, v);
-            $self.set_flag(&Flag::Z(v == 0));
-            $self.set_flag(&Flag::N(false));
-            $self.set_flag(&Flag::H((v & 0xf) == 0));
-        }
-        12
-    }};
-    ($self:expr, $reg:ident, 4) => {{
-        if let RegisterValue::$reg(v) = $self.get_value(&RegisterValue::$reg(0)) {
-            let v = v + 1;
-            $self.set_value(&RegisterValue::$reg(v));
-            $self.set_flag(&Flag::Z(v == 0));
-            $self.set_flag(&Flag::N(false));
-            $self.set_flag(&Flag::H((v & 0xf) == 0));
-        }
-        4
-    }};
-    ($self:expr, $reg:ident, 8) => {{
-        if let RegisterValue::$reg(v) = $self.get_value(&RegisterValue::$reg(0)) {
-            let v = v + 1;
-            $self.set_value(&RegisterValue::$reg(v));
-        }
-        8
-    }};
+fn cpl(cpu: &mut CPU) {
+    cpu.register_a = !cpu.register_a;
+    cpu.register_f |= 0b01100000;
 }
 
-macro_rules! xor {
-    ($self:expr, $reg:ident, $len:expr) => {{
-        if let RegisterValue::$reg(v) = $self.get_value(&RegisterValue::$reg(0)) {
-            $self.register_a ^= v;
-            let z = $self.register_a == 0;
-            $self.set_flag(&Flag::Z(z));
-            $self.set_flag(&Flag::N(false));
-            $self.set_flag(&Flag::H(false));
-            $self.set_flag(&Flag::C(false));
-        }
-        $len
-    }};
-}
-macro_rules! or {
-    ($self:expr, $reg:ident, $len:expr) => {{
-        if let RegisterValue::$reg(v) = $self.get_value(&RegisterValue::$reg(0)) {
-            $self.register_a |= v;
-            let z = $self.register_a == 0;
-            $self.set_flag(&Flag::Z(z));
-            $self.set_flag(&Flag::N(false));
-            $self.set_flag(&Flag::H(false));
-            $self.set_flag(&Flag::C(false));
-        }
-        $len
-    }};
-}
-macro_rules! and {
-    ($self:expr, $reg:ident, $len:expr) => {{
-        if let RegisterValue::$reg(v) = $self.get_value(&RegisterValue::$reg(0)) {
-            $self.register_a &= v;
-            $self.set_flag(&Flag::Z($self.register_a == 0));
-            $self.set_flag(&Flag::N(false));
-            $self.set_flag(&Flag::H(true));
-            $self.set_flag(&Flag::C(false));
-        }
-        $len
-    }};
-    ($self:expr, $mem:ident, "d8", $len:expr) => {{
-        $self.register_a &= $self.get_mem_u8($mem);
-        let z = $self.register_a == 0;
-        $self.set_flag(&Flag::Z(z));
-        $self.set_flag(&Flag::N(false));
-        $self.set_flag(&Flag::H(true));
-        $self.set_flag(&Flag::C(false));
-        $len
-    }};
-}
-macro_rules! sub {
-    ($self:expr, $reg:ident, $len:expr) => {{
-        if let RegisterValue::$reg(v) = $self.get_value(&RegisterValue::$reg(0)) {
-            let z = ($self.register_a == v);
-            let h = ($self.register_a & 0xf) < (v & 0xf);
-            let c = $self.register_a < v;
-            $self.register_a -= v;
-            $self.set_flag(&Flag::Z(z));
-            $self.set_flag(&Flag::N(true));
-            $self.set_flag(&Flag::H(h));
-            $self.set_flag(&Flag::C(c));
-        }
-        $len
-    }};
-}
-macro_rules! add {
-    ($self:expr, $reg_to:ident, $reg_from:ident, 4) => {{
-        if let RegisterValue::$reg_to(v0) = $self.get_value(&RegisterValue::$reg_to(0)) {
-            if let RegisterValue::$reg_from(v1) = $self.get_value(&RegisterValue::$reg_from(0)) {
-                let h = (v1 & 0xf) > (0xf - (v0 & 0xf));
-                let c = v1 > (0xff - v0);
-                let z = (v0 + v1 == 0);
-                $self.set_value(&RegisterValue::$reg_to(v0 + v1));
-                $self.set_flag(&Flag::Z(z));
-                $self.set_flag(&Flag::N(false));
-                $self.set_flag(&Flag::H(h));
-                $self.set_flag(&Flag::C(c));
-            }
-        }
-        4
-    }};
-    ($self:expr, $reg_to:ident, $reg_from:ident, 8) => {{
-        if let RegisterValue::$reg_to(v0) = $self.get_value(&RegisterValue::$reg_to(0)) {
-            if let RegisterValue::$reg_from(v1) = $self.get_value(&RegisterValue::$reg_from(0)) {
-                let h = (v1 & 0xfff) > (0xfff - (v0 & 0xfff));
-                let c = v1 > (0xffff - v0);
-                $self.set_value(&RegisterValue::$reg_to(v0 + v1));
-                $self.set_flag(&Flag::N(false));
-                $self.set_flag(&Flag::H(h));
-                $self.set_flag(&Flag::C(c));
-            }
-        }
-        8
-    }};
-    ($self:expr, $mem:ident, $reg:ident, (HL), $len:expr) => {{
-        if let RegisterValue::$reg(v0) = $self.get_value(&RegisterValue::$reg(0)) {
-            let v1 = $self.get_mem_hl($mem);
-
-            let h = (v1 & 0xf) > (0xf - (v0 & 0xf));
-            let c = v1 > (0xff - v0);
-            let z = (v0 + v1 == 0);
-            $self.set_value(&RegisterValue::$reg(v0 + v1));
-            $self.set_flag(&Flag::Z(z));
-            $self.set_flag(&Flag::N(false));
-            $self.set_flag(&Flag::H(h));
-            $self.set_flag(&Flag::C(c));
-        }
-        $len
-    }};
+/// HL + r16, Z is left untouched
+fn add_hl_impl(cpu: &mut CPU, t: u16) {
+    let hl = cpu.read_register16(Register16::HL);
+    let h = (hl & 0xfff) + (t & 0xfff) > 0xfff;
+    let c = hl as u32 + t as u32 > 0xffff;
+    cpu.write_register16(Register16::HL, hl.wrapping_add(t));
+    cpu.set_flag(&Flag::N(false));
+    cpu.set_flag(&Flag::H(h));
+    cpu.set_flag(&Flag::C(c));
 }
-macro_rules! bit {
-    ($self:expr, $reg:ident, $shift:expr, $len:expr) => {{
-        let v = ($self.$reg) & (1 << $shift);
-        $self.set_flag(&Flag::Z(v == 0));
-        $self.set_flag(&Flag::N(false));
-        $self.set_flag(&Flag::H(true));
-        $len
-    }};
+
+fn add_impl(cpu: &mut CPU, t: u8, carry: bool) {
+    let carry = carry as u8;
+    let a = cpu.register_a;
+    let v = a.wrapping_add(t).wrapping_add(carry);
+    let h = (a & 0xf) + (t & 0xf) + carry > 0xf;
+    let c = a as u16 + t as u16 + carry as u16 > 0xff;
+    cpu.register_a = v;
+    cpu.set_flag(&Flag::Z(v == 0));
+    cpu.set_flag(&Flag::N(false));
+    cpu.set_flag(&Flag::H(h));
+    cpu.set_flag(&Flag::C(c));
 }
-macro_rules! check_condition {
-    ($self:expr, $flag:ident) => {{
-        $self.get_flag(&Flag::$flag(false))
-    }};
-    ($self:expr, "N", $flag:ident) => {{
-        !$self.get_flag(&Flag::$flag(false))
-    }};
+
+/// SP + signed 8 bit offset, shared by `ADD SP, r8` and `LD HL, SP + r8`
+fn add_sp_impl(cpu: &mut CPU, t: u8) -> u16 {
+    let sp = cpu.register_sp;
+    let h = (sp & 0xf) + (t as u16 & 0xf) > 0xf;
+    let c = (sp & 0xff) + t as u16 > 0xff;
+    cpu.set_flag(&Flag::Z(false));
+    cpu.set_flag(&Flag::N(false));
+    cpu.set_flag(&Flag::H(h));
+    cpu.set_flag(&Flag::C(c));
+    sp.wrapping_add_signed(t as i8 as i16)
 }
 
-macro_rules! call {
-    ($self:expr, $mem:ident, "a16", $len:expr) => {{
-        let v = $self.get_mem_u16($mem);
-        $self.register_sp -= 1;
-        $mem.set($self.register_sp, ($self.register_pc >> 8) as u8);
-        $self.register_sp -= 1;
-        $mem.set($self.register_sp, ($self.register_pc & 0xff) as u8);
-        $self.register_pc = v;
-        $len
-    }};
-    ($self:expr, $mem:ident, $flag:ident, "a16", $len0:expr, $len1:expr) => {{
-        let v = $self.get_mem_u16($mem);
-        let c = check_condition!($self, $flag);
-        if c {
-            $self.register_sp -= 1;
-            $mem.set($self.register_sp, ($self.register_pc >> 8) as u8);
-            $self.register_sp -= 1;
-            $mem.set($self.register_sp, ($self.register_pc & 0xff) as u8);
-            $self.register_pc = v;
-            $len0
-        } else {
-            $len1
-        }
-    }};
+fn sub_impl(cpu: &mut CPU, t: u8, carry: bool) -> u8 {
+    let carry = carry as u8;
+    let a = cpu.register_a;
+    let v = a.wrapping_sub(t).wrapping_sub(carry);
+    let h = (a & 0xf) < (t & 0xf) + carry;
+    let c = (a as u16) < t as u16 + carry as u16;
+    cpu.set_flag(&Flag::Z(v == 0));
+    cpu.set_flag(&Flag::N(true));
+    cpu.set_flag(&Flag::H(h));
+    cpu.set_flag(&Flag::C(c));
+    v
 }
 
-macro_rules! rl {
-    ($self:expr, $reg:ident, $len:expr) => {{
-        if let RegisterValue::$reg(v) = $self.get_value(&RegisterValue::$reg(0)) {
-            let c = (v >= 128);
-            let mut v = v << 1;
-            if $self.get_flag(&Flag::C(false)) {
-                v += 1;
-            }
-            $self.set_value(&RegisterValue::$reg(v));
-            // set flag
-            $self.set_flag(&Flag::Z(v == 0));
-            $self.set_flag(&Flag::N(false));
-            $self.set_flag(&Flag::H(false));
-            $self.set_flag(&Flag::C(c));
-        }
-        $len
-    }};
+fn and_impl(cpu: &mut CPU, t: u8) {
+    cpu.register_a &= t;
+    cpu.set_flag(&Flag::Z(cpu.register_a == 0));
+    cpu.set_flag(&Flag::N(false));
+    cpu.set_flag(&Flag::H(true));
+    cpu.set_flag(&Flag::C(false));
 }
 
-macro_rules! jr {
-    ($self:expr, $mem:ident, $len:expr) => {{
-        let addr = $self.get_pc_and_move();
-        let v = ($mem.get(addr) as i8) as i16;
-        $self.register_pc = ($self.register_pc as i16 + v) as u16;
-        $len
-    }};
-    ($self:expr, $mem:ident, $flag:ident, $len0:expr, $len1:expr) => {{
-        let addr = $self.get_pc_and_move();
-        let v = ($mem.get(addr) as i8) as i16;
-        let c = check_condition!($self, $flag);
-        if c {
-            $self.register_pc = ($self.register_pc as i16 + v) as u16;
-            $len0
-        } else {
-            $len1
-        }
-    }};
-    ($self:expr, $mem:ident, "N", $flag:ident, $len0:expr, $len1:expr) => {{
-        let addr = $self.get_pc_and_move();
-        let v = ($mem.get(addr) as i8) as i16;
-        let c = check_condition!($self, "N", $flag);
-        if c {
-            $self.register_pc = ($self.register_pc as i16 + v) as u16;
-            $len0
-        } else {
-            $len1
-        }
-    }};
+fn or_impl(cpu: &mut CPU, t: u8) {
+    cpu.register_a |= t;
+    cpu.set_flag(&Flag::Z(cpu.register_a == 0));
+    cpu.set_flag(&Flag::N(false));
+    cpu.set_flag(&Flag::H(false));
+    cpu.set_flag(&Flag::C(false));
 }
 
-macro_rules! jp {
-    ($self:expr, $mem:ident, (HL), $len:expr) => {{
-        $self.register_pc = (($self.register_h as u16) << 8) + $self.register_l as u16;
-        $len
-    }};
-    ($self:expr, $mem:ident, "a16", $len:expr) => {{
-        let addr = $self.get_mem_u16($mem);
-        $self.register_pc = addr;
-        $len
-    }};
-    ($self:expr, $mem:ident, $flag:ident, "a16", $len0:expr, $len1:expr) => {{
-        let c = check_condition!($self, $flag);
-        let addr = $self.get_mem_u16($mem);
-        if c {
-            $self.register_pc = addr;
-            $len0
-        } else {
-            $len1
-        }
-    }};
+fn xor_impl(cpu: &mut CPU, t: u8) {
+    cpu.register_a ^= t;
+    cpu.set_flag(&Flag::Z(cpu.register_a == 0));
+    cpu.set_flag(&Flag::N(false));
+    cpu.set_flag(&Flag::H(false));
+    cpu.set_flag(&Flag::C(false));
 }
 
-fn rla(cpu: &mut CPU, len: u8) -> u8 {
-    let c = cpu.register_a >= 128;
-    let mut v = cpu.register_a << 1;
-    if cpu.get_flag(&Flag::C(false)) {
-        v += 1;
-    }
-    cpu.register_a = v;
-    cpu.set_flag(&Flag::Z(false));
+/// rotate left, bit 7 to carry and bit 0
+fn rlc_impl(cpu: &mut CPU, v: u8) -> u8 {
+    let c = v >= 0b10000000;
+    let t = v.rotate_left(1);
+    cpu.set_flag(&Flag::Z(t == 0));
     cpu.set_flag(&Flag::N(false));
     cpu.set_flag(&Flag::H(false));
     cpu.set_flag(&Flag::C(c));
-    len
+    t
 }
 
-fn ret(cpu: &mut CPU, mem: &memory::Memory, len: u8) -> u8 {
-    let v = mem.get(cpu.register_sp) as u16 + ((mem.get(cpu.register_sp + 1) as u16) << 8);
-    cpu.register_sp += 2;
-    cpu.register_pc = v;
-    len
+/// rotate right, bit 0 to carry and bit 7
+fn rrc_impl(cpu: &mut CPU, v: u8) -> u8 {
+    let c = (v & 1) != 0;
+    let t = v.rotate_right(1);
+    cpu.set_flag(&Flag::Z(t == 0));
+    cpu.set_flag(&Flag::N(false));
+    cpu.set_flag(&Flag::H(false));
+    cpu.set_flag(&Flag::C(c));
+    t
 }
 
-macro_rules! ret {
-    ($self:expr, $mem:ident, $flag:ident, $len0:expr, $len1:expr) => {{
-        let c = check_condition!($self, $flag);
-        if c {
-            ret($self, $mem, $len0)
-        } else {
-            $len1
-        }
-    }};
-    ($self:expr, $mem:ident, "N", $flag:ident, $len0:expr, $len1:expr) => {{
-        let c = check_condition!($self, "N", $flag);
-        if c {
-            ret($self, $mem, $len0)
-        } else {
-            $len1
-        }
-    }};
-}
-fn cp_impl(cpu: &mut CPU, t: u8) {
-    let z = cpu.register_a == t;
-    let h = (cpu.register_a & 0xf) < (t & 0xf);
-    let c = cpu.register_a < t;
-    cpu.set_flag(&Flag::Z(z));
-    cpu.set_flag(&Flag::N(true));
-    cpu.set_flag(&Flag::H(h));
+/// rotate left through carry
+fn rl_impl(cpu: &mut CPU, v: u8) -> u8 {
+    let c = v >= 0b10000000;
+    let t = (v << 1) | cpu.get_flag(&Flag::C(false)) as u8;
+    cpu.set_flag(&Flag::Z(t == 0));
+    cpu.set_flag(&Flag::N(false));
+    cpu.set_flag(&Flag::H(false));
     cpu.set_flag(&Flag::C(c));
+    t
 }
 
-fn cpl(cpu: &mut CPU) -> u8 {
-    cpu.register_a = !cpu.register_a;
-    cpu.register_f = cpu.register_f | 0b01100000;
-    4
+/// rotate right through carry
+fn rr_impl(cpu: &mut CPU, v: u8) -> u8 {
+    let c = (v & 1) != 0;
+    let t = (v >> 1) | ((cpu.get_flag(&Flag::C(false)) as u8) << 7);
+    cpu.set_flag(&Flag::Z(t == 0));
+    cpu.set_flag(&Flag::N(false));
+    cpu.set_flag(&Flag::H(false));
+    cpu.set_flag(&Flag::C(c));
+    t
 }
 
-fn reti(cpu: &mut CPU, mem: &mut memory::Memory) -> u8 {
-    cpu.interrupt_master_enable_flag = true;
-    ret(cpu, mem, 16)
+/// shift left into carry, bit 0 reset
+fn sla_impl(cpu: &mut CPU, v: u8) -> u8 {
+    let c = v >= 0b10000000;
+    let t = v << 1;
+    cpu.set_flag(&Flag::Z(t == 0));
+    cpu.set_flag(&Flag::N(false));
+    cpu.set_flag(&Flag::H(false));
+    cpu.set_flag(&Flag::C(c));
+    t
 }
 
-macro_rules! cp {
-    ($self:expr, $mem:ident, "d8", $len:expr) => {{
-        let t = $self.get_mem_u8($mem);
-        cp_impl($self, t);
-        $len
-    }};
-    ($self:expr, $mem:ident, (HL), $len:expr) => {{
-        let t = $self.get_mem_hl($mem);
-        cp_impl($self, t);
-        $len
-    }};
-    ($self:expr, $reg:ident, $len:expr) => {{
-        if let RegisterValue::$reg(v) = $self.get_value(&RegisterValue::$reg(0)) {
-            cp_impl($self, v);
-        }
-        $len
-    }};
+/// shift right into carry, bit 7 unchanged
+fn sra_impl(cpu: &mut CPU, v: u8) -> u8 {
+    let c = (v & 1) != 0;
+    let t = (v >> 1) | (v & 0b10000000);
+    cpu.set_flag(&Flag::Z(t == 0));
+    cpu.set_flag(&Flag::N(false));
+    cpu.set_flag(&Flag::H(false));
+    cpu.set_flag(&Flag::C(c));
+    t
 }
 
-macro_rules! swap {
-    ($self:expr, $reg:ident, $len:expr) => {{
-        if let RegisterValue::$reg(v) = $self.get_value(&RegisterValue::$reg(0)) {
-            let swap_v = ((v & 0xf) << 4) + (v >> 4);
-            $self.set_value(&RegisterValue::$reg(swap_v));
-            // set flag
-            $self.set_flag(&Flag::Z(swap_v == 0));
-            $self.set_flag(&Flag::N(false));
-            $self.set_flag(&Flag::H(false));
-            $self.set_flag(&Flag::C(false));
-        }
-        $len
-    }};
+/// swap upper and lower nibbles
+fn swap_impl(cpu: &mut CPU, v: u8) -> u8 {
+    let c = false;
+    let t = ((v & 0xf) << 4) + (v >> 4);
+    cpu.set_flag(&Flag::Z(t == 0));
+    cpu.set_flag(&Flag::N(false));
+    cpu.set_flag(&Flag::H(false));
+    cpu.set_flag(&Flag::C(c));
+    t
 }
 
-macro_rules! rst {
-    ($self:expr, $mem:ident, $num:expr, $len:expr) => {{
-        $mem.set($self.register_sp - 1, ($self.register_pc >> 8) as u8);
-        $mem.set($self.register_sp - 2, ($self.register_pc & 0xff) as u8);
-        $self.register_sp -= 2;
-        $self.register_pc = $num;
-        $len
-    }};
+/// shift right into carry, bit 7 reset
+fn srl_impl(cpu: &mut CPU, v: u8) -> u8 {
+    let c = (v & 1) != 0;
+    let t = v >> 1;
+    cpu.set_flag(&Flag::Z(t == 0));
+    cpu.set_flag(&Flag::N(false));
+    cpu.set_flag(&Flag::H(false));
+    cpu.set_flag(&Flag::C(c));
+    t
 }
-macro_rules! res {
-    ($self:expr, $num:expr, $reg:ident, $len:expr) => {{
-        if let RegisterValue::$reg(v) = $self.get_value(&RegisterValue::$reg(0)) {
-            let t = v & !(1 << $num);
-            $self.set_value(&RegisterValue::$reg(t));
-        }
-        $len
-    }};
+
+fn bit_impl(cpu: &mut CPU, v: u8, shift: u8) {
+    cpu.set_flag(&Flag::Z(v & (1 << shift) == 0));
+    cpu.set_flag(&Flag::N(false));
+    cpu.set_flag(&Flag::H(true));
 }
 
-macro_rules! sla {
-    ($self:expr, $reg:ident, $len:expr) => {{
-        if let RegisterValue::$reg(v) = $self.get_value(&RegisterValue::$reg(0)) {
-            let t = (v << 1);
-            $self.set_value(&RegisterValue::$reg(t));
-            // set flag
-            $self.set_flag(&Flag::Z(v == 0));
-            $self.set_flag(&Flag::N(false));
-            $self.set_flag(&Flag::H(false));
-            $self.set_flag(&Flag::C(v >= 0b10000000));
-        }
-        $len
-    }};
+impl Default for CPU {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl CPU {
@@ -641,8 +336,30 @@ impl CPU {
             register_sp: 0,
             register_pc: 0,
             interrupt_master_enable_flag: false,
+            ime_scheduled: false,
+            locked_up: false,
+            halted: false,
+            stopped: false,
+            halt_bug: false,
         }
     }
+    /// Registers as the CGB boot ROM leaves them, A = 0x11 tells games they run on a CGB.
+    pub fn set_cgb_post_boot_state(&mut self) {
+        self.register_a = 0x11;
+        self.register_f = 0x80;
+        self.register_b = 0x00;
+        self.register_c = 0x00;
+        self.register_d = 0xff;
+        self.register_e = 0x56;
+        self.register_h = 0x00;
+        self.register_l = 0x0d;
+        self.register_sp = 0xfffe;
+        self.register_pc = 0x0100;
+    }
+    /// An illegal opcode hung the cpu, PC still points at it.
+    pub fn is_locked_up(&self) -> bool {
+        self.locked_up
+    }
     pub fn set_flag(&mut self, flag: &Flag) {
         let (v, flag_bit) = match flag {
             Flag::Z(v) => (v, FLAG_Z_BIT),
@@ -651,9 +368,9 @@ impl CPU {
             Flag::C(v) => (v, FLAG_C_BIT),
         };
         if *v {
-            self.register_f = self.register_f | flag_bit;
+            self.register_f |= flag_bit;
         } else {
-            self.register_f = self.register_f & (0xff ^ flag_bit);
+            self.register_f &= 0xff ^ flag_bit;
         }
     }
 
@@ -706,289 +423,439 @@ impl CPU {
     }
     pub fn get_flag(&self, flag: &Flag) -> bool {
         match flag {
-            Flag::Z(_) => return (self.register_f & 0b10000000) != 0,
-            Flag::N(_) => return (self.register_f & 0b01000000) != 0,
-            Flag::H(_) => return (self.register_f & 0b00100000) != 0,
-            Flag::C(_) => return (self.register_f & 0b00010000) != 0,
+            Flag::Z(_) => (self.register_f & 0b10000000) != 0,
+            Flag::N(_) => (self.register_f & 0b01000000) != 0,
+            Flag::H(_) => (self.register_f & 0b00100000) != 0,
+            Flag::C(_) => (self.register_f & 0b00010000) != 0,
         }
     }
-    fn get_pc_and_move(&mut self) -> u16 {
-        let v = self.register_pc;
-        self.register_pc += 1;
-        v
+    fn check_condition(&self, condition: Option<Condition>) -> bool {
+        match condition {
+            None => true,
+            Some(Condition::NZ) => !self.get_flag(&Flag::Z(false)),
+            Some(Condition::Z) => self.get_flag(&Flag::Z(false)),
+            Some(Condition::NC) => !self.get_flag(&Flag::C(false)),
+            Some(Condition::C) => self.get_flag(&Flag::C(false)),
+        }
+    }
+
+    pub fn read_register8(&self, reg: Register8) -> u8 {
+        match reg {
+            Register8::A => self.register_a,
+            Register8::B => self.register_b,
+            Register8::C => self.register_c,
+            Register8::D => self.register_d,
+            Register8::E => self.register_e,
+            Register8::H => self.register_h,
+            Register8::L => self.register_l,
+        }
+    }
+    pub fn write_register8(&mut self, reg: Register8, v: u8) {
+        match reg {
+            Register8::A => self.register_a = v,
+            Register8::B => self.register_b = v,
+            Register8::C => self.register_c = v,
+            Register8::D => self.register_d = v,
+            Register8::E => self.register_e = v,
+            Register8::H => self.register_h = v,
+            Register8::L => self.register_l = v,
+        }
     }
-    fn get_mem_u8(&mut self, mem: &memory::Memory) -> u8 {
-        mem.get(self.get_pc_and_move())
+    pub fn read_register16(&self, reg: Register16) -> u16 {
+        let v = match reg {
+            Register16::AF => self.get_value(&RegisterValue::AF(0)),
+            Register16::BC => self.get_value(&RegisterValue::BC(0)),
+            Register16::DE => self.get_value(&RegisterValue::DE(0)),
+            Register16::HL => self.get_value(&RegisterValue::HL(0)),
+            Register16::SP => return self.register_sp,
+        };
+        match v {
+            RegisterValue::AF(v)
+            | RegisterValue::BC(v)
+            | RegisterValue::DE(v)
+            | RegisterValue::HL(v) => v,
+            _ => unreachable!(),
+        }
     }
-    fn get_mem_a8(&mut self, mem: &memory::Memory) -> u16 {
-        self.get_mem_u8(mem) as u16 + 0xff00
+    pub fn write_register16(&mut self, reg: Register16, v: u16) {
+        match reg {
+            // the lower 4 bits of F always read 0
+            Register16::AF => self.set_value(&RegisterValue::AF(v & 0xfff0)),
+            Register16::BC => self.set_value(&RegisterValue::BC(v)),
+            Register16::DE => self.set_value(&RegisterValue::DE(v)),
+            Register16::HL => self.set_value(&RegisterValue::HL(v)),
+            Register16::SP => self.register_sp = v,
+        }
     }
 
-    fn get_mem_u16(&mut self, mem: &memory::Memory) -> u16 {
-        let v0 = self.get_mem_u8(mem) as u16;
-        let v1 = self.get_mem_u8(mem) as u16;
+    /// address of a memory operand, (HL+) and (HL-) update HL
+    fn operand_addr(&mut self, operand: Operand) -> u16 {
+        match operand {
+            Operand::Indirect(reg) => self.read_register16(reg),
+            Operand::IndirectInc => {
+                let hl = self.read_register16(Register16::HL);
+                self.write_register16(Register16::HL, hl.wrapping_add(1));
+                hl
+            }
+            Operand::IndirectDec => {
+                let hl = self.read_register16(Register16::HL);
+                self.write_register16(Register16::HL, hl.wrapping_sub(1));
+                hl
+            }
+            Operand::HighC => 0xff00 + self.register_c as u16,
+            Operand::High(a8) => 0xff00 + a8 as u16,
+            Operand::Direct(a16) => a16,
+            _ => unreachable!("{:?} is not a memory operand", operand),
+        }
+    }
+    fn read_operand(&mut self, mem: &memory::Memory, operand: Operand) -> u8 {
+        match operand {
+            Operand::Reg8(reg) => self.read_register8(reg),
+            Operand::Imm8(v) => v,
+            _ => {
+                let addr = self.operand_addr(operand);
+                mem.get(addr)
+            }
+        }
+    }
+    fn write_operand(&mut self, mem: &mut memory::Memory, operand: Operand, v: u8) {
+        match operand {
+            Operand::Reg8(reg) => self.write_register8(reg, v),
+            _ => {
+                let addr = self.operand_addr(operand);
+                mem.set(addr, v);
+            }
+        }
+    }
+    /// read, modify and write back an 8 bit operand
+    fn modify_operand(
+        &mut self,
+        mem: &mut memory::Memory,
+        operand: Operand,
+        f: impl FnOnce(&mut CPU, u8) -> u8,
+    ) {
+        let v = self.read_operand(mem, operand);
+        let t = f(self, v);
+        self.write_operand(mem, operand, t);
+    }
+
+    fn push_u16(&mut self, mem: &mut memory::Memory, v: u16) {
+        self.register_sp = self.register_sp.wrapping_sub(1);
+        mem.set(self.register_sp, (v >> 8) as u8);
+        self.register_sp = self.register_sp.wrapping_sub(1);
+        mem.set(self.register_sp, (v & 0xff) as u8);
+    }
+    fn pop_u16(&mut self, mem: &memory::Memory) -> u16 {
+        let v0 = mem.get(self.register_sp) as u16;
+        self.register_sp = self.register_sp.wrapping_add(1);
+        let v1 = mem.get(self.register_sp) as u16;
+        self.register_sp = self.register_sp.wrapping_add(1);
         (v1 << 8) + v0
     }
 
-    fn get_mem_hl(&self, mem: &memory::Memory) -> u8 {
-        mem.get(((self.register_h as u16) << 8) + self.register_l as u16)
+    fn interrupt_pending(&self, mem: &memory::Memory) -> bool {
+        (mem.get(INTERRUPT_FLAG) & mem.get(INTERRUPT_ENABLE) & 0b11111) > 0
     }
-    fn set_mem_hl(&self, mem: &mut memory::Memory, v: u8) {
-        mem.set(((self.register_h as u16) << 8) + self.register_l as u16, v);
+
+    fn halt(&mut self, mem: &memory::Memory) {
+        if !self.interrupt_master_enable_flag && self.interrupt_pending(mem) {
+            self.halt_bug = true;
+        } else {
+            self.halted = true;
+        }
     }
 
-    fn check_interrupt(&mut self, mem: &mut memory::Memory) -> bool {
-        if ((mem.get(INTERRUPT_FLAG) & mem.get(INTERRUPT_ENABLE)) & 0b11111) > 0 {
-            for (bit, target_addr) in INTERRPUT_LIST.iter().enumerate() {
-                if mem.get_bit(INTERRUPT_FLAG, bit as u8)
-                    && mem.get_bit(INTERRUPT_ENABLE, bit as u8)
-                {
-                    let flag_bit = 1 << bit;
-                    let v = mem.get(INTERRUPT_FLAG) ^ flag_bit;
-                    mem.set(INTERRUPT_FLAG, v);
-                    mem.set(self.register_sp - 1, (self.register_pc >> 8) as u8);
-                    mem.set(self.register_sp - 2, (self.register_pc & 0xff) as u8);
-                    self.register_sp -= 2;
-                    self.register_pc = *target_addr;
-                    self.interrupt_master_enable_flag = false;
-                    break;
-                }
-            }
-            true
+    /// STOP resets the divider.
+    fn stop(&mut self, mem: &mut memory::Memory) {
+        mem.set(DIVIDER_RW, 0);
+        // an armed CGB speed switch takes the place of the low power mode
+        if mem.switch_speed() {
+            return;
+        }
+        if (mem.get(JOYPAD_RW) & 0x0f) == 0x0f {
+            self.stopped = true;
+        }
+    }
+
+    /// The eleven unused opcodes hang the cpu until reset.
+    fn lock_up(&mut self) {
+        self.register_pc = self.register_pc.wrapping_sub(1);
+        self.locked_up = true;
+    }
+
+    /// Push PC and jump to the highest priority pending interrupt.
+    /// return cpu cycle in 4 MHz
+    fn dispatch_interrupt(&mut self, mem: &mut memory::Memory) -> u8 {
+        self.interrupt_master_enable_flag = false;
+        self.ime_scheduled = false;
+        if self.halt_bug {
+            // EI right before HALT returns to the HALT itself
+            self.halt_bug = false;
+            self.register_pc = self.register_pc.wrapping_sub(1);
+        }
+        self.register_sp = self.register_sp.wrapping_sub(1);
+        mem.set(self.register_sp, (self.register_pc >> 8) as u8);
+        // the vector is picked after the upper byte push, which can overwrite IE
+        let pending = mem.get(INTERRUPT_FLAG) & mem.get(INTERRUPT_ENABLE) & 0b11111;
+        self.register_sp = self.register_sp.wrapping_sub(1);
+        mem.set(self.register_sp, (self.register_pc & 0xff) as u8);
+        if pending == 0 {
+            // cancelled, jump to 0x0000 without acknowledging anything
+            self.register_pc = 0x0000;
         } else {
-            false
+            let bit = pending.trailing_zeros() as u8;
+            mem.set_bit(INTERRUPT_FLAG, bit, false);
+            self.register_pc = INTERRPUT_LIST[bit as usize];
         }
+        20
     }
 
     /// return cpu cycle in 4 MHz
     pub fn tick(&mut self, mem: &mut memory::Memory, break_point: Option<u16>) -> u8 {
-        // check interrupt first but execute after
-        let need_interrupt = self.interrupt_master_enable_flag;
-
-        let op_addr: u8 = mem.get(self.get_pc_and_move());
-        // println!(
-        //     "instruction {:02x} pc:{:04X} interrupt {:08b} {:08b} {}",
-        //     op_addr,
-        //     self.register_pc - 1,
-        //     mem.get(INTERRUPT_ENABLE),
-        //     mem.get(INTERRUPT_FLAG),
-        //     self.interrupt_master_enable_flag
-        // );
-        let need_break = if let Some(bp) = break_point {
-            self.register_pc - 1 == bp
+        if self.locked_up {
+            return 4;
+        }
+        if mem.stall_for_vram_dma() {
+            return 4;
+        }
+        if self.stopped {
+            // any selected joypad line pulled low ends the low power mode
+            if (mem.get(JOYPAD_RW) & 0x0f) == 0x0f {
+                return 4;
+            }
+            self.stopped = false;
+        }
+        let mut wake_cycle = 0;
+        if self.halted {
+            if !self.interrupt_pending(mem) {
+                return 4;
+            }
+            self.halted = false;
+            wake_cycle = 4;
+        }
+        if self.interrupt_master_enable_flag && self.interrupt_pending(mem) {
+            return wake_cycle + self.dispatch_interrupt(mem);
+        }
+        // EI takes effect after the instruction following it
+        let enable_ime = self.ime_scheduled;
+
+        let op_pc = self.register_pc;
+        let instruction = if self.halt_bug {
+            // the byte after HALT is read twice
+            decode_from(
+                |addr| {
+                    mem.get(if addr == op_pc {
+                        addr
+                    } else {
+                        addr.wrapping_sub(1)
+                    })
+                },
+                op_pc,
+            )
         } else {
-            false
+            decode(mem, op_pc)
         };
+        let length = instruction.length() as u16 - self.halt_bug as u16;
+        self.halt_bug = false;
+        let need_break = break_point == Some(op_pc);
         if need_break {
-            self.register_pc -= 1;
             println!("before {}", self);
+            println!("instruction {:?}", instruction);
             println!(
                 "mem sp {:02X} {:02X}",
                 mem.get(self.register_sp),
-                mem.get(self.register_sp + 1)
+                mem.get(self.register_sp.wrapping_add(1))
             );
-            self.register_pc += 1;
-        }
-        let cpu_cycle_in_16mhz = match op_addr {
-            0xcb => {
-                let cb_op_addr: u8 = mem.get(self.get_pc_and_move());
-                println!("cb instruction {:02x}", cb_op_addr);
-                match cb_op_addr {
-                    0x11 => rl!(self, C, 8),
-                    0x27 => sla!(self, A, 8),
-                    0x37 => swap!(self, A, 8),
-                    0x7c => bit!(self, register_h, 7, 8),
-                    0x87 => res!(self, 0, A, 8),
-                    _ => {
-                        self.register_pc -= 2;
-                        todo!("cb opcode 0x{:02X} \n{}", cb_op_addr, self)
-                    }
+        }
+        self.register_pc = op_pc.wrapping_add(length);
+        let cpu_cycle_in_4mhz = self.execute(mem, instruction);
+        if enable_ime && self.ime_scheduled {
+            self.interrupt_master_enable_flag = true;
+            self.ime_scheduled = false;
+        }
+        if need_break {
+            println!("after {}", self);
+            panic!();
+        }
+        wake_cycle + cpu_cycle_in_4mhz
+    }
+
+    /// Run a decoded instruction, PC already points past it.
+    /// return cpu cycle in 4 MHz
+    pub fn execute(&mut self, mem: &mut memory::Memory, instruction: Instruction) -> u8 {
+        let mut branch_taken = false;
+        match instruction {
+            Instruction::Nop => {}
+            Instruction::Stop => self.stop(mem),
+            Instruction::Halt => self.halt(mem),
+            Instruction::Di => {
+                self.interrupt_master_enable_flag = false;
+                self.ime_scheduled = false;
+            }
+            Instruction::Ei => self.ime_scheduled = true,
+            Instruction::Daa => daa(self),
+            Instruction::Cpl => cpl(self),
+            Instruction::Scf => scf(self),
+            Instruction::Ccf => ccf(self),
+            Instruction::Rlca | Instruction::Rrca | Instruction::Rla | Instruction::Rra => {
+                let a = self.register_a;
+                self.register_a = match instruction {
+                    Instruction::Rlca => rlc_impl(self, a),
+                    Instruction::Rrca => rrc_impl(self, a),
+                    Instruction::Rla => rl_impl(self, a),
+                    _ => rr_impl(self, a),
+                };
+                // unlike the CB versions Z is always reset
+                self.set_flag(&Flag::Z(false));
+            }
+            Instruction::Ld(Operand::Reg16(dst), Operand::Reg16(src)) => {
+                let v = self.read_register16(src);
+                self.write_register16(dst, v);
+            }
+            Instruction::Ld(Operand::Reg16(dst), Operand::Imm16(v)) => {
+                self.write_register16(dst, v);
+            }
+            Instruction::Ld(Operand::Reg16(dst), Operand::SpOffset(offset)) => {
+                let v = add_sp_impl(self, offset as u8);
+                self.write_register16(dst, v);
+            }
+            Instruction::Ld(Operand::Direct(addr), Operand::Reg16(src)) => {
+                let v = self.read_register16(src);
+                mem.set(addr, (v & 0xff) as u8);
+                mem.set(addr.wrapping_add(1), (v >> 8) as u8);
+            }
+            Instruction::Ld(dst, src) | Instruction::Ldh(dst, src) => {
+                let v = self.read_operand(mem, src);
+                self.write_operand(mem, dst, v);
+            }
+            Instruction::Inc(Operand::Reg16(reg)) => {
+                let v = self.read_register16(reg);
+                self.write_register16(reg, v.wrapping_add(1));
+            }
+            Instruction::Dec(Operand::Reg16(reg)) => {
+                let v = self.read_register16(reg);
+                self.write_register16(reg, v.wrapping_sub(1));
+            }
+            Instruction::Inc(operand) => self.modify_operand(mem, operand, |cpu, v| {
+                let t = v.wrapping_add(1);
+                cpu.set_flag(&Flag::Z(t == 0));
+                cpu.set_flag(&Flag::N(false));
+                cpu.set_flag(&Flag::H((t & 0xf) == 0));
+                t
+            }),
+            Instruction::Dec(operand) => self.modify_operand(mem, operand, |cpu, v| {
+                let t = v.wrapping_sub(1);
+                cpu.set_flag(&Flag::Z(t == 0));
+                cpu.set_flag(&Flag::N(true));
+                cpu.set_flag(&Flag::H((v & 0xf) == 0));
+                t
+            }),
+            Instruction::Add(Operand::Reg16(Register16::SP), Operand::Signed(offset)) => {
+                self.register_sp = add_sp_impl(self, offset as u8);
+            }
+            Instruction::Add(Operand::Reg16(_), Operand::Reg16(src)) => {
+                let v = self.read_register16(src);
+                add_hl_impl(self, v);
+            }
+            Instruction::Add(_, src) => {
+                let v = self.read_operand(mem, src);
+                add_impl(self, v, false);
+            }
+            Instruction::Adc(src) => {
+                let v = self.read_operand(mem, src);
+                let carry = self.get_flag(&Flag::C(false));
+                add_impl(self, v, carry);
+            }
+            Instruction::Sub(src) => {
+                let v = self.read_operand(mem, src);
+                self.register_a = sub_impl(self, v, false);
+            }
+            Instruction::Sbc(src) => {
+                let v = self.read_operand(mem, src);
+                let carry = self.get_flag(&Flag::C(false));
+                self.register_a = sub_impl(self, v, carry);
+            }
+            Instruction::And(src) => {
+                let v = self.read_operand(mem, src);
+                and_impl(self, v);
+            }
+            Instruction::Xor(src) => {
+                let v = self.read_operand(mem, src);
+                xor_impl(self, v);
+            }
+            Instruction::Or(src) => {
+                let v = self.read_operand(mem, src);
+                or_impl(self, v);
+            }
+            Instruction::Cp(src) => {
+                let v = self.read_operand(mem, src);
+                sub_impl(self, v, false);
+            }
+            Instruction::Jr(condition, offset) => {
+                if self.check_condition(condition) {
+                    self.register_pc = self.register_pc.wrapping_add_signed(offset as i16);
+                    branch_taken = true;
                 }
             }
-            0x00 => 4,
-            0x01 => ld!(self, mem, BC, get_mem_u16, 12),
-            0x03 => inc!(self, BC, 8),
-            0x04 => inc!(self, B, 4),
-            0x05 => dec!(self, B, 4),
-            0x06 => ld!(self, mem, B, get_mem_u8, 8),
-            0x09 => add!(self, HL, BC, 8),
-            0x0b => dec!(self, BC, 8, no_flag),
-            0x0c => inc!(self, C, 4),
-            0x0d => dec!(self, C, 4),
-            0x0e => ld!(self, mem, C, get_mem_u8, 8),
-            0x11 => ld!(self, mem, DE, get_mem_u16, 12),
-            0x12 => ld!(self, mem, (DE), A, 8),
-            0x13 => inc!(self, DE, 8),
-            0x14 => inc!(self, D, 4),
-            0x15 => dec!(self, D, 4),
-            0x16 => ld!(self, mem, D, get_mem_u8, 8),
-            0x17 => rla(self, 4),
-            0x18 => jr!(self, mem, 12),
-            0x19 => add!(self, HL, DE, 8),
-            0x1a => ld!(self, mem, A, (DE), 8),
-            0x1b => dec!(self, DE, 8, no_flag),
-            0x1c => inc!(self, E, 4),
-            0x1d => dec!(self, E, 4),
-            0x1e => ld!(self, mem, E, get_mem_u8, 8),
-            0x20 => jr!(self, mem, "N", Z, 12, 8),
-            0x21 => ld!(self, mem, HL, get_mem_u16, 12),
-            0x22 => ld!(self, mem, "(HL)", +, A, 8),
-            0x23 => inc!(self, HL, 8),
-            0x24 => inc!(self, H, 4),
-            0x25 => dec!(self, H, 4),
-            0x26 => ld!(self, mem, H, get_mem_u8, 8),
-            0x28 => jr!(self, mem, Z, 12, 8),
-            0x2a => ld!(self, mem, A, "(HL)", +, 8),
-            0x2b => dec!(self, HL, 8, no_flag),
-            0x2c => inc!(self, L, 4),
-            0x2d => dec!(self, L, 4),
-            0x2e => ld!(self, mem, L, get_mem_u8, 8),
-            0x2f => cpl(self),
-            0x30 => jr!(self, mem, "N", C, 12, 8),
-            0x31 => ld!(self, mem, SP, get_mem_u16, 12),
-            0x32 => ld!(self, mem, "(HL)", -, A, 8),
-            0x33 => inc!(self, SP, 8),
-            0x34 => inc!(self, mem, (HL), 12),
-            0x35 => dec!(self, mem, (HL), 12),
-            0x36 => ld!(self, mem, "(HL)", get_mem_u8, 12),
-            0x38 => jr!(self, mem, C, 12, 8),
-            0x3b => dec!(self, SP, 8, no_flag),
-            0x3c => inc!(self, A, 4),
-            0x3d => dec!(self, A, 4),
-            0x3e => ld!(self, mem, A, get_mem_u8, 8),
-            0x40 => ld!(self, B, B, 4),
-            0x41 => ld!(self, B, C, 4),
-            0x42 => ld!(self, B, D, 4),
-            0x43 => ld!(self, B, E, 4),
-            0x44 => ld!(self, B, H, 4),
-            0x45 => ld!(self, B, L, 4),
-            0x46 => ld!(self, mem, B, (HL), 8),
-            0x47 => ld!(self, B, A, 4),
-            0x4a => ld!(self, C, D, 4),
-            0x4b => ld!(self, C, E, 4),
-            0x4c => ld!(self, C, H, 4),
-            0x4d => ld!(self, C, L, 4),
-            0x4e => ld!(self, mem, C, (HL), 8),
-            0x4f => ld!(self, C, A, 4),
-            0x50 => ld!(self, D, B, 4),
-            0x51 => ld!(self, D, C, 4),
-            0x52 => ld!(self, D, D, 4),
-            0x53 => ld!(self, D, E, 4),
-            0x54 => ld!(self, D, H, 4),
-            0x55 => ld!(self, D, L, 4),
-            0x56 => ld!(self, mem, D, (HL), 8),
-            0x57 => ld!(self, D, A, 4),
-            0x5e => ld!(self, mem, E, (HL), 8),
-            0x5f => ld!(self, E, A, 4),
-            0x60 => ld!(self, H, B, 4),
-            0x61 => ld!(self, H, C, 4),
-            0x62 => ld!(self, H, D, 4),
-            0x63 => ld!(self, H, E, 4),
-            0x64 => ld!(self, H, H, 4),
-            0x65 => ld!(self, H, L, 4),
-            0x66 => ld!(self, mem, H, (HL), 8),
-            0x67 => ld!(self, H, A, 4),
-            0x68 => ld!(self, L, B, 4),
-            0x69 => ld!(self, L, C, 4),
-            0x6e => ld!(self, mem, L, (HL), 8),
-            0x70 => ld!(self, mem, (HL), B, 8),
-            0x71 => ld!(self, mem, (HL), C, 8),
-            0x72 => ld!(self, mem, (HL), D, 8),
-            0x73 => ld!(self, mem, (HL), E, 8),
-            0x74 => ld!(self, mem, (HL), H, 8),
-            0x75 => ld!(self, mem, (HL), L, 8),
-            0x77 => ld!(self, mem, (HL), A, 8),
-            0x78 => ld!(self, A, B, 4),
-            0x79 => ld!(self, A, C, 4),
-            0x7a => ld!(self, A, D, 4),
-            0x7b => ld!(self, A, E, 4),
-            0x7c => ld!(self, A, H, 4),
-            0x7d => ld!(self, A, L, 4),
-            0x7e => ld!(self, mem, A, (HL), 8),
-            0x86 => add!(self, mem, A, (HL), 8),
-            0x87 => add!(self, A, A, 4),
-            0x90 => sub!(self, B, 4),
-            0x91 => sub!(self, C, 4),
-            0x92 => sub!(self, D, 4),
-            0x93 => sub!(self, E, 4),
-            0x94 => sub!(self, H, 4),
-            0x95 => sub!(self, L, 4),
-            0xa0 => and!(self, B, 4),
-            0xa1 => and!(self, C, 4),
-            0xa2 => and!(self, D, 4),
-            0xa3 => and!(self, E, 4),
-            0xa4 => and!(self, H, 4),
-            0xa5 => and!(self, L, 4),
-            0xa7 => and!(self, A, 4),
-            0xa8 => xor!(self, B, 4),
-            0xa9 => xor!(self, C, 4),
-            0xaa => xor!(self, D, 4),
-            0xab => xor!(self, E, 4),
-            0xac => xor!(self, H, 4),
-            0xad => xor!(self, L, 4),
-            0xaf => xor!(self, A, 4),
-            0xb0 => or!(self, B, 4),
-            0xb1 => or!(self, C, 4),
-            0xb2 => or!(self, D, 4),
-            0xb3 => or!(self, E, 4),
-            0xb4 => or!(self, H, 4),
-            0xb5 => or!(self, L, 4),
-            0xb7 => or!(self, A, 4),
-            0xb9 => cp!(self, C, 4), // need to verify
-            0xbe => cp!(self, mem, (HL), 8),
-            0xc0 => ret!(self, mem, "N", Z, 20, 8),
-            0xc1 => pop!(self, mem, BC, 12),
-            0xc3 => jp!(self, mem, "a16", 16),
-            0xc5 => push!(self, mem, BC, 16),
-            0xc7 => rst!(self, mem, 0x00, 16),
-            0xc8 => ret!(self, mem, Z, 20, 8),
-            0xc9 => ret(self, mem, 16),
-            0xca => jp!(self, mem, Z, "a16", 16, 12),
-            0xcc => call!(self, mem, Z, "a16", 24, 12),
-            0xcd => call!(self, mem, "a16", 24),
-            0xcf => rst!(self, mem, 0x08, 16),
-            0xd1 => pop!(self, mem, DE, 12),
-            0xd5 => push!(self, mem, DE, 16),
-            0xd7 => rst!(self, mem, 0x10, 16),
-            0xd9 => reti(self, mem),
-            0xdf => rst!(self, mem, 0x18, 16),
-            0xe0 => ldh!(self, mem, "(a8)", A, 12),
-            0xe1 => pop!(self, mem, HL, 12),
-            0xe2 => ld!(self, mem, ff(C), A, 8),
-            0xe5 => push!(self, mem, HL, 16),
-            0xe6 => and!(self, mem, "d8", 8),
-            0xe7 => rst!(self, mem, 0x20, 16),
-            0xe9 => jp!(self, mem, (HL), 4),
-            0xea => ld!(self, mem, "(a16)", A, 16),
-            0xef => rst!(self, mem, 0x28, 16),
-            0xf0 => ldh!(self, mem, A, "(a8)", 12),
-            0xf1 => pop!(self, mem, AF, 12),
-            0xf3 => {
-                self.interrupt_master_enable_flag = false;
-                4
+            Instruction::Jp(condition, addr) => {
+                if self.check_condition(condition) {
+                    self.register_pc = addr;
+                    branch_taken = true;
+                }
+            }
+            Instruction::JpHl => self.register_pc = self.read_register16(Register16::HL),
+            Instruction::Call(condition, addr) => {
+                if self.check_condition(condition) {
+                    self.push_u16(mem, self.register_pc);
+                    self.register_pc = addr;
+                    branch_taken = true;
+                }
+            }
+            Instruction::Ret(condition) => {
+                if self.check_condition(condition) {
+                    self.register_pc = self.pop_u16(mem);
+                    branch_taken = true;
+                }
             }
-            0xf5 => push!(self, mem, AF, 16),
-            0xf7 => rst!(self, mem, 0x30, 16),
-            0xfa => ldh!(self, mem, A, "(a16)", 16), // it's actually ld
-            0xfb => {
+            Instruction::Reti => {
                 self.interrupt_master_enable_flag = true;
-                4
+                self.register_pc = self.pop_u16(mem);
             }
-            0xfe => cp!(self, mem, "d8", 8),
-            0xff => rst!(self, mem, 0x38, 16),
-            _ => {
-                self.register_pc -= 1;
-                println!("opcode 0x{:02X} \n{}", op_addr, self);
-                panic!()
+            Instruction::Rst(vector) => {
+                self.push_u16(mem, self.register_pc);
+                self.register_pc = vector as u16;
             }
-        };
-        if need_interrupt {
-            self.check_interrupt(mem);
+            Instruction::Push(reg) => {
+                let v = self.read_register16(reg);
+                self.push_u16(mem, v);
+            }
+            Instruction::Pop(reg) => {
+                let v = self.pop_u16(mem);
+                self.write_register16(reg, v);
+            }
+            Instruction::Rlc(operand) => self.modify_operand(mem, operand, rlc_impl),
+            Instruction::Rrc(operand) => self.modify_operand(mem, operand, rrc_impl),
+            Instruction::Rl(operand) => self.modify_operand(mem, operand, rl_impl),
+            Instruction::Rr(operand) => self.modify_operand(mem, operand, rr_impl),
+            Instruction::Sla(operand) => self.modify_operand(mem, operand, sla_impl),
+            Instruction::Sra(operand) => self.modify_operand(mem, operand, sra_impl),
+            Instruction::Swap(operand) => self.modify_operand(mem, operand, swap_impl),
+            Instruction::Srl(operand) => self.modify_operand(mem, operand, srl_impl),
+            Instruction::Bit(bit, operand) => {
+                let v = self.read_operand(mem, operand);
+                bit_impl(self, v, bit);
+            }
+            Instruction::Res(bit, operand) => {
+                self.modify_operand(mem, operand, |_, v| v & !(1 << bit))
+            }
+            Instruction::Set(bit, operand) => {
+                self.modify_operand(mem, operand, |_, v| v | (1 << bit))
+            }
+            Instruction::Illegal(_) => self.lock_up(),
         }
-        if need_break {
-            println!("after {}", self);
-            panic!();
+        match instruction.branch_cycles() {
+            Some(cycles) if branch_taken => cycles,
+            _ => instruction.cycles(),
         }
-        cpu_cycle_in_16mhz / 4
     }
 }
@@ -0,0 +1,555 @@
+use super::memory;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Register8 {
+    A,
+    B,
+    C,
+    D,
+    E,
+    H,
+    L,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Register16 {
+    AF,
+    BC,
+    DE,
+    HL,
+    SP,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Condition {
+    NZ,
+    Z,
+    NC,
+    C,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operand {
+    Reg8(Register8),
+    Reg16(Register16),
+    /// (BC), (DE) or (HL)
+    Indirect(Register16),
+    /// (HL+)
+    IndirectInc,
+    /// (HL-)
+    IndirectDec,
+    /// (0xff00 + C)
+    HighC,
+    /// (0xff00 + a8)
+    High(u8),
+    /// (a16)
+    Direct(u16),
+    /// d8
+    Imm8(u8),
+    /// d16
+    Imm16(u16),
+    /// r8, signed offset of ADD SP
+    Signed(i8),
+    /// SP + r8
+    SpOffset(i8),
+}
+
+impl Operand {
+    /// bytes following the opcode
+    fn len(&self) -> u8 {
+        match self {
+            Operand::High(_) | Operand::Imm8(_) | Operand::Signed(_) | Operand::SpOffset(_) => 1,
+            Operand::Direct(_) | Operand::Imm16(_) => 2,
+            _ => 0,
+        }
+    }
+    /// cycles spent fetching immediates and accessing memory
+    fn access_cycles(&self) -> u8 {
+        match self {
+            Operand::Reg8(_) | Operand::Reg16(_) => 0,
+            Operand::Indirect(_) | Operand::IndirectInc | Operand::IndirectDec | Operand::HighC => {
+                4
+            }
+            Operand::Imm8(_) | Operand::Signed(_) | Operand::SpOffset(_) => 4,
+            Operand::High(_) | Operand::Imm16(_) => 8,
+            Operand::Direct(_) => 12,
+        }
+    }
+    fn is_memory(&self) -> bool {
+        matches!(
+            self,
+            Operand::Indirect(_)
+                | Operand::IndirectInc
+                | Operand::IndirectDec
+                | Operand::HighC
+                | Operand::High(_)
+                | Operand::Direct(_)
+        )
+    }
+}
+
+/// One decoded SM83 instruction with its operands.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Instruction {
+    Nop,
+    Stop,
+    Halt,
+    Di,
+    Ei,
+    Daa,
+    Cpl,
+    Scf,
+    Ccf,
+    Rlca,
+    Rrca,
+    Rla,
+    Rra,
+    Ld(Operand, Operand),
+    Ldh(Operand, Operand),
+    Inc(Operand),
+    Dec(Operand),
+    Add(Operand, Operand),
+    Adc(Operand),
+    Sub(Operand),
+    Sbc(Operand),
+    And(Operand),
+    Xor(Operand),
+    Or(Operand),
+    Cp(Operand),
+    Jr(Option<Condition>, i8),
+    Jp(Option<Condition>, u16),
+    JpHl,
+    Call(Option<Condition>, u16),
+    Ret(Option<Condition>),
+    Reti,
+    Rst(u8),
+    Push(Register16),
+    Pop(Register16),
+    Rlc(Operand),
+    Rrc(Operand),
+    Rl(Operand),
+    Rr(Operand),
+    Sla(Operand),
+    Sra(Operand),
+    Swap(Operand),
+    Srl(Operand),
+    Bit(u8, Operand),
+    Res(u8, Operand),
+    Set(u8, Operand),
+    /// one of the eleven unused opcodes, hangs the cpu
+    Illegal(u8),
+}
+
+const R8_TABLE: [Operand; 8] = [
+    Operand::Reg8(Register8::B),
+    Operand::Reg8(Register8::C),
+    Operand::Reg8(Register8::D),
+    Operand::Reg8(Register8::E),
+    Operand::Reg8(Register8::H),
+    Operand::Reg8(Register8::L),
+    Operand::Indirect(Register16::HL),
+    Operand::Reg8(Register8::A),
+];
+const R16_TABLE: [Register16; 4] = [
+    Register16::BC,
+    Register16::DE,
+    Register16::HL,
+    Register16::SP,
+];
+const R16_STACK_TABLE: [Register16; 4] = [
+    Register16::BC,
+    Register16::DE,
+    Register16::HL,
+    Register16::AF,
+];
+const R16_MEM_TABLE: [Operand; 4] = [
+    Operand::Indirect(Register16::BC),
+    Operand::Indirect(Register16::DE),
+    Operand::IndirectInc,
+    Operand::IndirectDec,
+];
+const CONDITION_TABLE: [Condition; 4] = [Condition::NZ, Condition::Z, Condition::NC, Condition::C];
+
+impl Instruction {
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            Instruction::Nop => "nop",
+            Instruction::Stop => "stop",
+            Instruction::Halt => "halt",
+            Instruction::Di => "di",
+            Instruction::Ei => "ei",
+            Instruction::Daa => "daa",
+            Instruction::Cpl => "cpl",
+            Instruction::Scf => "scf",
+            Instruction::Ccf => "ccf",
+            Instruction::Rlca => "rlca",
+            Instruction::Rrca => "rrca",
+            Instruction::Rla => "rla",
+            Instruction::Rra => "rra",
+            Instruction::Ld(Operand::HighC, _) | Instruction::Ld(_, Operand::HighC) => "ldh",
+            Instruction::Ld(..) => "ld",
+            Instruction::Ldh(..) => "ldh",
+            Instruction::Inc(_) => "inc",
+            Instruction::Dec(_) => "dec",
+            Instruction::Add(..) => "add",
+            Instruction::Adc(_) => "adc",
+            Instruction::Sub(_) => "sub",
+            Instruction::Sbc(_) => "sbc",
+            Instruction::And(_) => "and",
+            Instruction::Xor(_) => "xor",
+            Instruction::Or(_) => "or",
+            Instruction::Cp(_) => "cp",
+            Instruction::Jr(..) => "jr",
+            Instruction::Jp(..) | Instruction::JpHl => "jp",
+            Instruction::Call(..) => "call",
+            Instruction::Ret(_) => "ret",
+            Instruction::Reti => "reti",
+            Instruction::Rst(_) => "rst",
+            Instruction::Push(_) => "push",
+            Instruction::Pop(_) => "pop",
+            Instruction::Rlc(_) => "rlc",
+            Instruction::Rrc(_) => "rrc",
+            Instruction::Rl(_) => "rl",
+            Instruction::Rr(_) => "rr",
+            Instruction::Sla(_) => "sla",
+            Instruction::Sra(_) => "sra",
+            Instruction::Swap(_) => "swap",
+            Instruction::Srl(_) => "srl",
+            Instruction::Bit(..) => "bit",
+            Instruction::Res(..) => "res",
+            Instruction::Set(..) => "set",
+            Instruction::Illegal(_) => "db",
+        }
+    }
+
+    /// instruction length in bytes, including the 0xcb prefix
+    pub fn length(&self) -> u8 {
+        match self {
+            Instruction::Stop | Instruction::Jr(..) => 2,
+            Instruction::Jp(..) | Instruction::Call(..) => 3,
+            Instruction::Ld(dst, src) | Instruction::Ldh(dst, src) | Instruction::Add(dst, src) => {
+                1 + dst.len() + src.len()
+            }
+            Instruction::Inc(op)
+            | Instruction::Dec(op)
+            | Instruction::Adc(op)
+            | Instruction::Sub(op)
+            | Instruction::Sbc(op)
+            | Instruction::And(op)
+            | Instruction::Xor(op)
+            | Instruction::Or(op)
+            | Instruction::Cp(op) => 1 + op.len(),
+            Instruction::Rlc(_)
+            | Instruction::Rrc(_)
+            | Instruction::Rl(_)
+            | Instruction::Rr(_)
+            | Instruction::Sla(_)
+            | Instruction::Sra(_)
+            | Instruction::Swap(_)
+            | Instruction::Srl(_)
+            | Instruction::Bit(..)
+            | Instruction::Res(..)
+            | Instruction::Set(..) => 2,
+            _ => 1,
+        }
+    }
+
+    /// cycles in 4 MHz, for conditional branches this is the not taken cost
+    pub fn cycles(&self) -> u8 {
+        match self {
+            Instruction::Ld(Operand::Reg16(Register16::SP), Operand::Reg16(Register16::HL)) => 8,
+            Instruction::Ld(Operand::Reg16(Register16::HL), Operand::SpOffset(_)) => 12,
+            Instruction::Ld(Operand::Direct(_), Operand::Reg16(Register16::SP)) => 20,
+            Instruction::Ld(dst, src) | Instruction::Ldh(dst, src) => {
+                4 + dst.access_cycles() + src.access_cycles()
+            }
+            Instruction::Inc(Operand::Reg16(_)) | Instruction::Dec(Operand::Reg16(_)) => 8,
+            Instruction::Inc(op) | Instruction::Dec(op) if op.is_memory() => 12,
+            Instruction::Inc(_) | Instruction::Dec(_) => 4,
+            Instruction::Add(Operand::Reg16(Register16::SP), _) => 16,
+            Instruction::Add(Operand::Reg16(_), _) => 8,
+            Instruction::Add(_, src) => 4 + src.access_cycles(),
+            Instruction::Adc(op)
+            | Instruction::Sub(op)
+            | Instruction::Sbc(op)
+            | Instruction::And(op)
+            | Instruction::Xor(op)
+            | Instruction::Or(op)
+            | Instruction::Cp(op) => 4 + op.access_cycles(),
+            Instruction::Jr(None, _) => 12,
+            Instruction::Jr(Some(_), _) => 8,
+            Instruction::Jp(None, _) => 16,
+            Instruction::Jp(Some(_), _) => 12,
+            Instruction::Call(None, _) => 24,
+            Instruction::Call(Some(_), _) => 12,
+            Instruction::Ret(None) | Instruction::Reti => 16,
+            Instruction::Ret(Some(_)) => 8,
+            Instruction::Rst(_) | Instruction::Push(_) => 16,
+            Instruction::Pop(_) => 12,
+            Instruction::Bit(_, op) if op.is_memory() => 12,
+            Instruction::Bit(..) => 8,
+            Instruction::Rlc(op)
+            | Instruction::Rrc(op)
+            | Instruction::Rl(op)
+            | Instruction::Rr(op)
+            | Instruction::Sla(op)
+            | Instruction::Sra(op)
+            | Instruction::Swap(op)
+            | Instruction::Srl(op)
+            | Instruction::Res(_, op)
+            | Instruction::Set(_, op) => {
+                if op.is_memory() {
+                    16
+                } else {
+                    8
+                }
+            }
+            _ => 4,
+        }
+    }
+
+    /// cycles in 4 MHz when a conditional branch is taken
+    pub fn branch_cycles(&self) -> Option<u8> {
+        match self {
+            Instruction::Jr(Some(_), _) => Some(12),
+            Instruction::Jp(Some(_), _) => Some(16),
+            Instruction::Call(Some(_), _) => Some(24),
+            Instruction::Ret(Some(_)) => Some(20),
+            _ => None,
+        }
+    }
+}
+
+/// Decode the instruction at `pc` without executing it.
+pub fn decode(mem: &memory::Memory, pc: u16) -> Instruction {
+    decode_from(|addr| mem.get(addr), pc)
+}
+
+/// Decode the instruction at `pc` with bytes supplied by `read`.
+pub fn decode_from(read: impl Fn(u16) -> u8, pc: u16) -> Instruction {
+    let op = read(pc);
+    let d8 = || read(pc.wrapping_add(1));
+    let d16 = || read(pc.wrapping_add(1)) as u16 + ((read(pc.wrapping_add(2)) as u16) << 8);
+    let a = Operand::Reg8(Register8::A);
+    let r8 = |i: u8| R8_TABLE[(i & 7) as usize];
+    let r16 = |i: u8| R16_TABLE[((i >> 4) & 3) as usize];
+    let cond = |i: u8| CONDITION_TABLE[((i >> 3) & 3) as usize];
+    match op {
+        0x00 => Instruction::Nop,
+        0x10 => Instruction::Stop,
+        0x76 => Instruction::Halt,
+        0xf3 => Instruction::Di,
+        0xfb => Instruction::Ei,
+        0x07 => Instruction::Rlca,
+        0x0f => Instruction::Rrca,
+        0x17 => Instruction::Rla,
+        0x1f => Instruction::Rra,
+        0x27 => Instruction::Daa,
+        0x2f => Instruction::Cpl,
+        0x37 => Instruction::Scf,
+        0x3f => Instruction::Ccf,
+        0x08 => Instruction::Ld(Operand::Direct(d16()), Operand::Reg16(Register16::SP)),
+        0x18 => Instruction::Jr(None, d8() as i8),
+        0x20 | 0x28 | 0x30 | 0x38 => Instruction::Jr(Some(cond(op)), d8() as i8),
+        0x01 | 0x11 | 0x21 | 0x31 => {
+            Instruction::Ld(Operand::Reg16(r16(op)), Operand::Imm16(d16()))
+        }
+        0x09 | 0x19 | 0x29 | 0x39 => {
+            Instruction::Add(Operand::Reg16(Register16::HL), Operand::Reg16(r16(op)))
+        }
+        0x02 | 0x12 | 0x22 | 0x32 => Instruction::Ld(R16_MEM_TABLE[(op >> 4) as usize], a),
+        0x0a | 0x1a | 0x2a | 0x3a => Instruction::Ld(a, R16_MEM_TABLE[(op >> 4) as usize]),
+        0x03 | 0x13 | 0x23 | 0x33 => Instruction::Inc(Operand::Reg16(r16(op))),
+        0x0b | 0x1b | 0x2b | 0x3b => Instruction::Dec(Operand::Reg16(r16(op))),
+        0x04 | 0x0c | 0x14 | 0x1c | 0x24 | 0x2c | 0x34 | 0x3c => Instruction::Inc(r8(op >> 3)),
+        0x05 | 0x0d | 0x15 | 0x1d | 0x25 | 0x2d | 0x35 | 0x3d => Instruction::Dec(r8(op >> 3)),
+        0x06 | 0x0e | 0x16 | 0x1e | 0x26 | 0x2e | 0x36 | 0x3e => {
+            Instruction::Ld(r8(op >> 3), Operand::Imm8(d8()))
+        }
+        0x40..=0x7f => Instruction::Ld(r8(op >> 3), r8(op)),
+        0x80..=0x87 => Instruction::Add(a, r8(op)),
+        0x88..=0x8f => Instruction::Adc(r8(op)),
+        0x90..=0x97 => Instruction::Sub(r8(op)),
+        0x98..=0x9f => Instruction::Sbc(r8(op)),
+        0xa0..=0xa7 => Instruction::And(r8(op)),
+        0xa8..=0xaf => Instruction::Xor(r8(op)),
+        0xb0..=0xb7 => Instruction::Or(r8(op)),
+        0xb8..=0xbf => Instruction::Cp(r8(op)),
+        0xc6 => Instruction::Add(a, Operand::Imm8(d8())),
+        0xce => Instruction::Adc(Operand::Imm8(d8())),
+        0xd6 => Instruction::Sub(Operand::Imm8(d8())),
+        0xde => Instruction::Sbc(Operand::Imm8(d8())),
+        0xe6 => Instruction::And(Operand::Imm8(d8())),
+        0xee => Instruction::Xor(Operand::Imm8(d8())),
+        0xf6 => Instruction::Or(Operand::Imm8(d8())),
+        0xfe => Instruction::Cp(Operand::Imm8(d8())),
+        0xc0 | 0xc8 | 0xd0 | 0xd8 => Instruction::Ret(Some(cond(op))),
+        0xc9 => Instruction::Ret(None),
+        0xd9 => Instruction::Reti,
+        0xc2 | 0xca | 0xd2 | 0xda => Instruction::Jp(Some(cond(op)), d16()),
+        0xc3 => Instruction::Jp(None, d16()),
+        0xe9 => Instruction::JpHl,
+        0xc4 | 0xcc | 0xd4 | 0xdc => Instruction::Call(Some(cond(op)), d16()),
+        0xcd => Instruction::Call(None, d16()),
+        0xc7 | 0xcf | 0xd7 | 0xdf | 0xe7 | 0xef | 0xf7 | 0xff => Instruction::Rst(op & 0x38),
+        0xc1 | 0xd1 | 0xe1 | 0xf1 => Instruction::Pop(R16_STACK_TABLE[((op >> 4) & 3) as usize]),
+        0xc5 | 0xd5 | 0xe5 | 0xf5 => Instruction::Push(R16_STACK_TABLE[((op >> 4) & 3) as usize]),
+        0xe0 => Instruction::Ldh(Operand::High(d8()), a),
+        0xf0 => Instruction::Ldh(a, Operand::High(d8())),
+        0xe2 => Instruction::Ld(Operand::HighC, a),
+        0xf2 => Instruction::Ld(a, Operand::HighC),
+        0xea => Instruction::Ld(Operand::Direct(d16()), a),
+        0xfa => Instruction::Ld(a, Operand::Direct(d16())),
+        0xe8 => Instruction::Add(Operand::Reg16(Register16::SP), Operand::Signed(d8() as i8)),
+        0xf8 => Instruction::Ld(
+            Operand::Reg16(Register16::HL),
+            Operand::SpOffset(d8() as i8),
+        ),
+        0xf9 => Instruction::Ld(
+            Operand::Reg16(Register16::SP),
+            Operand::Reg16(Register16::HL),
+        ),
+        0xcb => decode_cb(d8()),
+        0xd3 | 0xdb | 0xdd | 0xe3 | 0xe4 | 0xeb | 0xec | 0xed | 0xf4 | 0xfc | 0xfd => {
+            Instruction::Illegal(op)
+        }
+    }
+}
+
+fn decode_cb(op: u8) -> Instruction {
+    let target = R8_TABLE[(op & 7) as usize];
+    let bit = (op >> 3) & 7;
+    match op >> 6 {
+        0 => match bit {
+            0 => Instruction::Rlc(target),
+            1 => Instruction::Rrc(target),
+            2 => Instruction::Rl(target),
+            3 => Instruction::Rr(target),
+            4 => Instruction::Sla(target),
+            5 => Instruction::Sra(target),
+            6 => Instruction::Swap(target),
+            _ => Instruction::Srl(target),
+        },
+        1 => Instruction::Bit(bit, target),
+        2 => Instruction::Res(bit, target),
+        _ => Instruction::Set(bit, target),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Length of every unprefixed opcode, 0 for the unused ones.
+    #[rustfmt::skip]
+    const LENGTHS: [u8; 256] = [
+        1, 3, 1, 1, 1, 1, 2, 1, 3, 1, 1, 1, 1, 1, 2, 1,
+        2, 3, 1, 1, 1, 1, 2, 1, 2, 1, 1, 1, 1, 1, 2, 1,
+        2, 3, 1, 1, 1, 1, 2, 1, 2, 1, 1, 1, 1, 1, 2, 1,
+        2, 3, 1, 1, 1, 1, 2, 1, 2, 1, 1, 1, 1, 1, 2, 1,
+        1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+        1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+        1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+        1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+        1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+        1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+        1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+        1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1,
+        1, 1, 3, 3, 3, 1, 2, 1, 1, 1, 3, 2, 3, 3, 2, 1,
+        1, 1, 3, 0, 3, 1, 2, 1, 1, 1, 3, 0, 3, 0, 2, 1,
+        2, 1, 1, 0, 0, 1, 2, 1, 2, 1, 3, 0, 0, 0, 2, 1,
+        2, 1, 1, 1, 0, 1, 2, 1, 2, 1, 3, 1, 0, 0, 2, 1,
+    ];
+
+    /// Cycles in 4 MHz of every unprefixed opcode, branches not taken. 0 for the unused ones
+    /// and the 0xcb prefix.
+    #[rustfmt::skip]
+    const CYCLES: [u8; 256] = [
+        4, 12,  8,  8,  4,  4,  8,  4, 20,  8,  8,  8,  4,  4,  8,  4,
+        4, 12,  8,  8,  4,  4,  8,  4, 12,  8,  8,  8,  4,  4,  8,  4,
+        8, 12,  8,  8,  4,  4,  8,  4,  8,  8,  8,  8,  4,  4,  8,  4,
+        8, 12,  8,  8, 12, 12, 12,  4,  8,  8,  8,  8,  4,  4,  8,  4,
+        4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4,
+        4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4,
+        4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4,
+        8,  8,  8,  8,  8,  8,  4,  8,  4,  4,  4,  4,  4,  4,  8,  4,
+        4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4,
+        4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4,
+        4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4,
+        4,  4,  4,  4,  4,  4,  8,  4,  4,  4,  4,  4,  4,  4,  8,  4,
+        8, 12, 12, 16, 12, 16,  8, 16,  8, 16, 12,  0, 12, 24,  8, 16,
+        8, 12, 12,  0, 12, 16,  8, 16,  8, 16, 12,  0, 12,  0,  8, 16,
+       12, 12,  8,  0,  0, 16,  8, 16, 16,  4, 16,  0,  0,  0,  8, 16,
+       12, 12,  8,  4,  0, 16,  8, 16, 12,  8, 16,  4,  0,  0,  8, 16,
+    ];
+
+    fn decode_bytes(bytes: [u8; 3]) -> Instruction {
+        decode_from(|addr| bytes[addr as usize], 0)
+    }
+
+    #[test]
+    fn unprefixed_lengths_and_cycles() {
+        for op in 0..=0xffu8 {
+            if op == 0xcb {
+                continue;
+            }
+            let instruction = decode_bytes([op, 0, 0]);
+            if LENGTHS[op as usize] == 0 {
+                assert_eq!(instruction, Instruction::Illegal(op), "{:02x}", op);
+                continue;
+            }
+            assert_eq!(instruction.length(), LENGTHS[op as usize], "{:02x}", op);
+            assert_eq!(instruction.cycles(), CYCLES[op as usize], "{:02x}", op);
+        }
+    }
+
+    #[test]
+    fn branch_cycles() {
+        for op in 0..=0xffu8 {
+            let taken = match op {
+                0x20 | 0x28 | 0x30 | 0x38 => Some(12),
+                0xc2 | 0xca | 0xd2 | 0xda => Some(16),
+                0xc0 | 0xc8 | 0xd0 | 0xd8 => Some(20),
+                0xc4 | 0xcc | 0xd4 | 0xdc => Some(24),
+                _ => None,
+            };
+            assert_eq!(
+                decode_bytes([op, 0, 0]).branch_cycles(),
+                taken,
+                "{:02x}",
+                op
+            );
+        }
+    }
+
+    #[test]
+    fn prefixed_lengths_and_cycles() {
+        for op in 0..=0xffu8 {
+            let instruction = decode_bytes([0xcb, op, 0]);
+            let cycles = match (op & 7 == 6, (0x40..0x80).contains(&op)) {
+                (false, _) => 8,
+                (true, true) => 12,
+                (true, false) => 16,
+            };
+            assert_eq!(instruction.length(), 2, "cb {:02x}", op);
+            assert_eq!(instruction.cycles(), cycles, "cb {:02x}", op);
+            assert_eq!(instruction.branch_cycles(), None, "cb {:02x}", op);
+        }
+    }
+
+    #[test]
+    fn operands() {
+        assert_eq!(
+            decode_bytes([0xfa, 0x34, 0x12]),
+            Instruction::Ld(Operand::Reg8(Register8::A), Operand::Direct(0x1234))
+        );
+        assert_eq!(
+            decode_bytes([0x20, 0xfe, 0]),
+            Instruction::Jr(Some(Condition::NZ), -2)
+        );
+        assert_eq!(
+            decode_bytes([0xcb, 0x7e, 0]),
+            Instruction::Bit(7, Operand::Indirect(Register16::HL))
+        );
+    }
+}
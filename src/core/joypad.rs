@@ -0,0 +1,126 @@
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Button {
+    Right,
+    Left,
+    Up,
+    Down,
+    A,
+    B,
+    Select,
+    Start,
+}
+
+impl Button {
+    /// P1 bit 4 selects the directions, bit 5 the actions, both use the low nibble.
+    fn select_bit(self) -> u8 {
+        match self {
+            Button::Right | Button::Left | Button::Up | Button::Down => 0b0001_0000,
+            _ => 0b0010_0000,
+        }
+    }
+    fn line(self) -> u8 {
+        match self {
+            Button::Right | Button::A => 0b0001,
+            Button::Left | Button::B => 0b0010,
+            Button::Up | Button::Select => 0b0100,
+            Button::Down | Button::Start => 0b1000,
+        }
+    }
+}
+
+/// P1 button matrix, a selected group pulls the lines of its pressed buttons low.
+pub struct Joypad {
+    /// P1 bits 4-5 as written, 0 selects the group
+    select: u8,
+    /// pressed buttons by group
+    directions: u8,
+    actions: u8,
+}
+
+impl Default for Joypad {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Joypad {
+    pub fn new() -> Joypad {
+        Joypad {
+            select: 0b0011_0000,
+            directions: 0,
+            actions: 0,
+        }
+    }
+    /// Low nibble of P1, 0 for pressed.
+    fn lines(&self) -> u8 {
+        let mut pressed = 0;
+        if self.select & 0b0001_0000 == 0 {
+            pressed |= self.directions;
+        }
+        if self.select & 0b0010_0000 == 0 {
+            pressed |= self.actions;
+        }
+        !pressed & 0x0f
+    }
+    pub fn get(&self) -> u8 {
+        0b1100_0000 | self.select | self.lines()
+    }
+    /// Returns whether a line went from high to low.
+    pub fn set(&mut self, val: u8) -> bool {
+        let before = self.lines();
+        self.select = val & 0b0011_0000;
+        before & !self.lines() != 0
+    }
+    /// Returns whether a line went from high to low.
+    pub fn set_button(&mut self, button: Button, pressed: bool) -> bool {
+        let before = self.lines();
+        let group = if button.select_bit() == 0b0001_0000 {
+            &mut self.directions
+        } else {
+            &mut self.actions
+        };
+        if pressed {
+            *group |= button.line();
+        } else {
+            *group &= !button.line();
+        }
+        before & !self.lines() != 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unselected_groups_read_released() {
+        let mut joypad = Joypad::new();
+        joypad.set_button(Button::A, true);
+        joypad.set_button(Button::Down, true);
+        joypad.set(0x30);
+        assert_eq!(joypad.get(), 0xff);
+        joypad.set(0x20);
+        assert_eq!(joypad.get(), 0xe7);
+        joypad.set(0x10);
+        assert_eq!(joypad.get(), 0xde);
+        // with both groups selected the lines are shared
+        joypad.set(0x00);
+        assert_eq!(joypad.get(), 0xc6);
+    }
+
+    #[test]
+    fn interrupt_on_press_in_a_selected_group() {
+        let mut joypad = Joypad::new();
+        joypad.set(0x20);
+        assert!(!joypad.set_button(Button::A, true));
+        assert!(joypad.set_button(Button::Left, true));
+        // a line already held low by the other group doesn't fall again
+        joypad.set(0x00);
+        assert!(!joypad.set_button(Button::B, true));
+        assert!(!joypad.set_button(Button::Left, false));
+        assert!(!joypad.set_button(Button::B, false));
+        // selecting a group with a button held pulls its line low
+        joypad.set(0x30);
+        assert!(joypad.set(0x10));
+    }
+}
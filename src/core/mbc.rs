@@ -0,0 +1,1462 @@
+use crate::core::cartridge::{Cartridge, MapperKind, RAM_BANK_SIZE, ROM_BANK_SIZE};
+use crate::core::memory::{Mapper, RtcClock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Value read from unmapped or disabled cartridge space.
+const OPEN_BUS: u8 = 0xff;
+const LOGO_START: usize = 0x0104;
+const LOGO_SIZE: usize = 48;
+
+/// Pick the mapper for `rom` from its cartridge type byte.
+pub fn from_cartridge(rom: Vec<u8>, cartridge: &Cartridge) -> Box<dyn Mapper> {
+    match cartridge.cartridge_type.mapper {
+        MapperKind::MBC1 => Box::new(MBC1::new(rom, cartridge.ram_size)),
+        MapperKind::MBC3 => Box::new(MBC3::new(
+            rom,
+            cartridge.ram_size,
+            cartridge.cartridge_type.timer,
+        )),
+        MapperKind::MBC2 => Box::new(MBC2::new(rom)),
+        MapperKind::MBC5 => Box::new(MBC5::new(
+            rom,
+            cartridge.ram_size,
+            cartridge.cartridge_type.rumble,
+        )),
+        MapperKind::MMM01 => Box::new(MMM01::new(rom, cartridge.ram_size)),
+        MapperKind::HuC1 => Box::new(HuC1::new(rom, cartridge.ram_size)),
+        MapperKind::HuC3 => Box::new(HuC3::new(rom, cartridge.ram_size)),
+        _ => Box::new(RomOnly::new(rom, cartridge.ram_size)),
+    }
+}
+
+/// Number of `bank_size` banks in `len` bytes rounded up to a power of two, used to mask bank
+/// numbers the way the unconnected address lines do.
+fn bank_count(len: usize, bank_size: usize) -> usize {
+    len.div_ceil(bank_size).next_power_of_two()
+}
+
+fn read_banked(data: &[u8], bank: usize, bank_size: usize, offset: u16) -> u8 {
+    let bank = bank & (bank_count(data.len(), bank_size) - 1);
+    data.get(bank * bank_size + offset as usize)
+        .copied()
+        .unwrap_or(OPEN_BUS)
+}
+
+fn load_ram(ram: &mut [u8], data: &[u8]) {
+    let len = ram.len().min(data.len());
+    ram[..len].copy_from_slice(&data[..len]);
+}
+
+fn write_banked(data: &mut [u8], bank: usize, bank_size: usize, offset: u16, val: u8) {
+    let bank = bank & (bank_count(data.len(), bank_size) - 1);
+    if let Some(byte) = data.get_mut(bank * bank_size + offset as usize) {
+        *byte = val;
+    }
+}
+
+/// 32 KiB of ROM and up to one bank of RAM with no banking.
+pub struct RomOnly {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+}
+
+impl RomOnly {
+    pub fn new(rom: Vec<u8>, ram_size: usize) -> RomOnly {
+        RomOnly {
+            rom,
+            ram: vec![0; ram_size.min(RAM_BANK_SIZE)],
+        }
+    }
+}
+
+impl Mapper for RomOnly {
+    fn read_rom(&self, addr: u16) -> u8 {
+        self.rom.get(addr as usize).copied().unwrap_or(OPEN_BUS)
+    }
+    fn write_rom(&mut self, _addr: u16, _val: u8) {}
+    fn read_ram(&self, addr: u16) -> u8 {
+        self.ram.get(addr as usize).copied().unwrap_or(OPEN_BUS)
+    }
+    fn write_ram(&mut self, addr: u16, val: u8) {
+        if let Some(byte) = self.ram.get_mut(addr as usize) {
+            *byte = val;
+        }
+    }
+    fn save_data(&self) -> Vec<u8> {
+        self.ram.clone()
+    }
+    fn load_save_data(&mut self, data: &[u8]) {
+        load_ram(&mut self.ram, data);
+    }
+}
+
+/// MBC1, up to 2 MiB ROM and 32 KiB RAM. MBC1M multicarts wire only 4 bits of the ROM bank
+/// register so the upper bits select one of the 256 KiB games.
+pub struct MBC1 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    ram_enabled: bool,
+    /// 0x2000-0x3fff, 5 bits, 0 reads as 1
+    bank1: u8,
+    /// 0x4000-0x5fff, 2 bits, RAM bank or upper ROM bank bits
+    bank2: u8,
+    /// 0x6000-0x7fff, mode 1 applies bank2 to 0x0000-0x3fff and RAM as well
+    advanced_banking: bool,
+    multicart: bool,
+}
+
+impl MBC1 {
+    pub fn new(rom: Vec<u8>, ram_size: usize) -> MBC1 {
+        let multicart = is_multicart(&rom);
+        MBC1 {
+            rom,
+            ram: vec![0; ram_size],
+            ram_enabled: false,
+            bank1: 1,
+            bank2: 0,
+            advanced_banking: false,
+            multicart,
+        }
+    }
+    fn bank1_bits(&self) -> u8 {
+        if self.multicart {
+            4
+        } else {
+            5
+        }
+    }
+    fn low_rom_bank(&self) -> usize {
+        if self.advanced_banking {
+            (self.bank2 as usize) << self.bank1_bits()
+        } else {
+            0
+        }
+    }
+    fn high_rom_bank(&self) -> usize {
+        let bank1 = self.bank1 & ((1 << self.bank1_bits()) - 1);
+        ((self.bank2 as usize) << self.bank1_bits()) | bank1 as usize
+    }
+    fn ram_bank(&self) -> usize {
+        if self.advanced_banking {
+            self.bank2 as usize
+        } else {
+            0
+        }
+    }
+}
+
+/// MBC1M carts are 1 MiB and repeat the boot logo at the start of the second game, bank 0x10.
+fn is_multicart(rom: &[u8]) -> bool {
+    const SECOND_GAME: usize = 0x10 * ROM_BANK_SIZE;
+    if rom.len() != 0x40 * ROM_BANK_SIZE {
+        return false;
+    }
+    let logo = &rom[LOGO_START..LOGO_START + LOGO_SIZE];
+    logo == &rom[SECOND_GAME + LOGO_START..SECOND_GAME + LOGO_START + LOGO_SIZE]
+}
+
+impl Mapper for MBC1 {
+    fn read_rom(&self, addr: u16) -> u8 {
+        match addr {
+            0x0000..0x4000 => read_banked(&self.rom, self.low_rom_bank(), ROM_BANK_SIZE, addr),
+            _ => read_banked(
+                &self.rom,
+                self.high_rom_bank(),
+                ROM_BANK_SIZE,
+                addr - 0x4000,
+            ),
+        }
+    }
+    fn write_rom(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x0000..0x2000 => self.ram_enabled = val & 0x0f == 0x0a,
+            // the zero check only sees the 5 bits, so 0x20, 0x40 and 0x60 map to 0x21, 0x41, 0x61
+            0x2000..0x4000 => self.bank1 = (val & 0x1f).max(1),
+            0x4000..0x6000 => self.bank2 = val & 0x03,
+            _ => self.advanced_banking = val & 0x01 != 0,
+        }
+    }
+    fn read_ram(&self, addr: u16) -> u8 {
+        if !self.ram_enabled || self.ram.is_empty() {
+            return OPEN_BUS;
+        }
+        read_banked(&self.ram, self.ram_bank(), RAM_BANK_SIZE, addr)
+    }
+    fn write_ram(&mut self, addr: u16, val: u8) {
+        if !self.ram_enabled || self.ram.is_empty() {
+            return;
+        }
+        let bank = self.ram_bank();
+        write_banked(&mut self.ram, bank, RAM_BANK_SIZE, addr, val);
+    }
+    fn save_data(&self) -> Vec<u8> {
+        self.ram.clone()
+    }
+    fn load_save_data(&mut self, data: &[u8]) {
+        load_ram(&mut self.ram, data);
+    }
+}
+
+const CYCLES_PER_SECOND: u32 = 4_194_304;
+/// RTC registers as five little endian u32, twice, then a u64 unix timestamp, the layout
+/// VBA-M and BGB append to the RAM in .sav files
+const RTC_SAVE_SIZE: usize = 48;
+/// older saves store a 32 bit timestamp
+const RTC_SAVE_SIZE_32BIT: usize = 44;
+
+const RTC_DH_DAY_HIGH: u8 = 0b0000_0001;
+const RTC_DH_HALT: u8 = 0b0100_0000;
+const RTC_DH_CARRY: u8 = 0b1000_0000;
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// MBC3 real-time clock, registers 0x08-0x0c: seconds, minutes, hours, day low and day high
+/// with the day bit 8, halt and day carry flags.
+#[derive(Debug, Clone, Copy, Default)]
+struct RtcRegisters {
+    seconds: u8,
+    minutes: u8,
+    hours: u8,
+    day_low: u8,
+    day_high: u8,
+}
+
+impl RtcRegisters {
+    fn get(&self, reg: u8) -> u8 {
+        match reg {
+            0x08 => self.seconds,
+            0x09 => self.minutes,
+            0x0a => self.hours,
+            0x0b => self.day_low,
+            _ => self.day_high,
+        }
+    }
+    /// Unused bits are not stored.
+    fn set(&mut self, reg: u8, val: u8) {
+        match reg {
+            0x08 => self.seconds = val & 0x3f,
+            0x09 => self.minutes = val & 0x3f,
+            0x0a => self.hours = val & 0x1f,
+            0x0b => self.day_low = val,
+            _ => self.day_high = val & (RTC_DH_DAY_HIGH | RTC_DH_HALT | RTC_DH_CARRY),
+        }
+    }
+    fn halted(&self) -> bool {
+        self.day_high & RTC_DH_HALT != 0
+    }
+    /// Count one second. Out of range values count up to the register width and wrap to 0
+    /// without carrying into the next register.
+    fn advance_second(&mut self) {
+        self.seconds = (self.seconds + 1) & 0x3f;
+        if self.seconds != 60 {
+            return;
+        }
+        self.seconds = 0;
+        self.minutes = (self.minutes + 1) & 0x3f;
+        if self.minutes != 60 {
+            return;
+        }
+        self.minutes = 0;
+        self.hours = (self.hours + 1) & 0x1f;
+        if self.hours != 24 {
+            return;
+        }
+        self.hours = 0;
+        let (day_low, overflow) = self.day_low.overflowing_add(1);
+        self.day_low = day_low;
+        if overflow {
+            if self.day_high & RTC_DH_DAY_HIGH != 0 {
+                self.day_high = (self.day_high & !RTC_DH_DAY_HIGH) | RTC_DH_CARRY;
+            } else {
+                self.day_high |= RTC_DH_DAY_HIGH;
+            }
+        }
+    }
+    fn save(&self, data: &mut Vec<u8>) {
+        for reg in 0x08..=0x0c {
+            data.extend_from_slice(&(self.get(reg) as u32).to_le_bytes());
+        }
+    }
+    fn load(data: &[u8]) -> RtcRegisters {
+        let mut regs = RtcRegisters::default();
+        for (reg, chunk) in (0x08..=0x0c).zip(data.chunks_exact(4)) {
+            regs.set(reg, chunk[0]);
+        }
+        regs
+    }
+}
+
+struct Rtc {
+    clock: RtcClock,
+    live: RtcRegisters,
+    latched: RtcRegisters,
+    /// cycles towards the next second
+    sub_second: u32,
+    /// host time the registers were last brought up to date, used by RtcClock::WallClock
+    updated_at: u64,
+    /// last value written to the latch register
+    latch_armed: bool,
+}
+
+impl Rtc {
+    fn new() -> Rtc {
+        Rtc {
+            clock: RtcClock::Cycles,
+            live: RtcRegisters::default(),
+            latched: RtcRegisters::default(),
+            sub_second: 0,
+            updated_at: unix_time(),
+            latch_armed: false,
+        }
+    }
+    fn advance_seconds(&mut self, seconds: u64) {
+        if self.live.halted() {
+            return;
+        }
+        for _ in 0..seconds {
+            self.live.advance_second();
+        }
+    }
+    /// Catch up with the host clock.
+    fn sync(&mut self) {
+        let now = unix_time();
+        if self.clock == RtcClock::WallClock {
+            self.advance_seconds(now.saturating_sub(self.updated_at));
+        }
+        self.updated_at = now;
+    }
+    fn tick(&mut self, cycles: u8) {
+        if self.clock != RtcClock::Cycles || self.live.halted() {
+            return;
+        }
+        self.sub_second += cycles as u32;
+        if self.sub_second >= CYCLES_PER_SECOND {
+            self.sub_second -= CYCLES_PER_SECOND;
+            self.live.advance_second();
+        }
+    }
+    fn write_latch(&mut self, val: u8) {
+        if self.latch_armed && val == 0x01 {
+            self.sync();
+            self.latched = self.live;
+        }
+        self.latch_armed = val == 0x00;
+    }
+    fn write(&mut self, reg: u8, val: u8) {
+        self.sync();
+        if reg == 0x08 {
+            self.sub_second = 0;
+        }
+        self.live.set(reg, val);
+    }
+    fn save(&self, data: &mut Vec<u8>) {
+        self.live.save(data);
+        self.latched.save(data);
+        let updated_at = match self.clock {
+            RtcClock::Cycles => self.updated_at,
+            RtcClock::WallClock => unix_time(),
+        };
+        data.extend_from_slice(&updated_at.to_le_bytes());
+    }
+    fn load(&mut self, data: &[u8]) {
+        self.live = RtcRegisters::load(&data[0..20]);
+        self.latched = RtcRegisters::load(&data[20..40]);
+        let mut timestamp = [0; 8];
+        timestamp[..data.len() - 40].copy_from_slice(&data[40..]);
+        self.updated_at = u64::from_le_bytes(timestamp);
+        // with the host clock, time spent with the emulator closed counts
+        self.sync();
+    }
+}
+
+/// MBC3, up to 2 MiB ROM (4 MiB for MBC30), 32 KiB RAM (64 KiB) and an optional RTC mapped
+/// in place of RAM.
+pub struct MBC3 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    rtc: Option<Rtc>,
+    /// RAM and RTC
+    ram_enabled: bool,
+    rom_bank: u8,
+    /// 0x00-0x07 RAM bank, 0x08-0x0c RTC register
+    ram_bank: u8,
+}
+
+impl MBC3 {
+    pub fn new(rom: Vec<u8>, ram_size: usize, has_rtc: bool) -> MBC3 {
+        MBC3 {
+            rom,
+            ram: vec![0; ram_size],
+            rtc: has_rtc.then(Rtc::new),
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_bank: 0,
+        }
+    }
+    /// MBC30 wires all 8 bits of the ROM bank register
+    fn rom_bank_mask(&self) -> u8 {
+        if self.rom.len() > 0x80 * ROM_BANK_SIZE {
+            0xff
+        } else {
+            0x7f
+        }
+    }
+}
+
+impl Mapper for MBC3 {
+    fn read_rom(&self, addr: u16) -> u8 {
+        match addr {
+            0x0000..0x4000 => read_banked(&self.rom, 0, ROM_BANK_SIZE, addr),
+            _ => read_banked(
+                &self.rom,
+                self.rom_bank as usize,
+                ROM_BANK_SIZE,
+                addr - 0x4000,
+            ),
+        }
+    }
+    fn write_rom(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x0000..0x2000 => self.ram_enabled = val & 0x0f == 0x0a,
+            0x2000..0x4000 => self.rom_bank = (val & self.rom_bank_mask()).max(1),
+            0x4000..0x6000 => self.ram_bank = val & 0x0f,
+            _ => {
+                if let Some(rtc) = &mut self.rtc {
+                    rtc.write_latch(val);
+                }
+            }
+        }
+    }
+    fn read_ram(&self, addr: u16) -> u8 {
+        if !self.ram_enabled {
+            return OPEN_BUS;
+        }
+        match self.ram_bank {
+            0x00..=0x07 if !self.ram.is_empty() => {
+                read_banked(&self.ram, self.ram_bank as usize, RAM_BANK_SIZE, addr)
+            }
+            0x08..=0x0c => match &self.rtc {
+                Some(rtc) => rtc.latched.get(self.ram_bank),
+                None => OPEN_BUS,
+            },
+            _ => OPEN_BUS,
+        }
+    }
+    fn write_ram(&mut self, addr: u16, val: u8) {
+        if !self.ram_enabled {
+            return;
+        }
+        match self.ram_bank {
+            0x00..=0x07 if !self.ram.is_empty() => {
+                let bank = self.ram_bank as usize;
+                write_banked(&mut self.ram, bank, RAM_BANK_SIZE, addr, val);
+            }
+            0x08..=0x0c => {
+                if let Some(rtc) = &mut self.rtc {
+                    rtc.write(self.ram_bank, val);
+                }
+            }
+            _ => {}
+        }
+    }
+    fn tick(&mut self, cycles: u8) {
+        if let Some(rtc) = &mut self.rtc {
+            rtc.tick(cycles);
+        }
+    }
+    fn set_rtc_clock(&mut self, clock: RtcClock) {
+        if let Some(rtc) = &mut self.rtc {
+            rtc.sync();
+            rtc.clock = clock;
+        }
+    }
+    fn save_data(&self) -> Vec<u8> {
+        let mut data = self.ram.clone();
+        if let Some(rtc) = &self.rtc {
+            rtc.save(&mut data);
+        }
+        data
+    }
+    fn load_save_data(&mut self, data: &[u8]) {
+        load_ram(&mut self.ram, data);
+        let footer = data.get(self.ram.len()..).unwrap_or_default();
+        if let Some(rtc) = &mut self.rtc {
+            if footer.len() == RTC_SAVE_SIZE || footer.len() == RTC_SAVE_SIZE_32BIT {
+                rtc.load(footer);
+            }
+        }
+    }
+}
+
+const MBC2_RAM_SIZE: usize = 512;
+
+/// MBC2, up to 256 KiB ROM and 512 half-bytes of built-in RAM. Address bit 8 selects between
+/// the RAM enable and ROM bank registers.
+pub struct MBC2 {
+    rom: Vec<u8>,
+    /// only the lower nibbles are stored
+    ram: Vec<u8>,
+    ram_enabled: bool,
+    rom_bank: u8,
+}
+
+impl MBC2 {
+    pub fn new(rom: Vec<u8>) -> MBC2 {
+        MBC2 {
+            rom,
+            ram: vec![0; MBC2_RAM_SIZE],
+            ram_enabled: false,
+            rom_bank: 1,
+        }
+    }
+}
+
+impl Mapper for MBC2 {
+    fn read_rom(&self, addr: u16) -> u8 {
+        match addr {
+            0x0000..0x4000 => read_banked(&self.rom, 0, ROM_BANK_SIZE, addr),
+            _ => read_banked(
+                &self.rom,
+                self.rom_bank as usize,
+                ROM_BANK_SIZE,
+                addr - 0x4000,
+            ),
+        }
+    }
+    fn write_rom(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x0000..0x4000 if addr & 0x0100 == 0 => self.ram_enabled = val & 0x0f == 0x0a,
+            0x0000..0x4000 => self.rom_bank = (val & 0x0f).max(1),
+            _ => {}
+        }
+    }
+    /// The 512 bytes repeat through 0xa000-0xbfff, the upper nibble reads as 1s.
+    fn read_ram(&self, addr: u16) -> u8 {
+        if !self.ram_enabled {
+            return OPEN_BUS;
+        }
+        self.ram[addr as usize % MBC2_RAM_SIZE] | 0xf0
+    }
+    fn write_ram(&mut self, addr: u16, val: u8) {
+        if self.ram_enabled {
+            self.ram[addr as usize % MBC2_RAM_SIZE] = val & 0x0f;
+        }
+    }
+    fn save_data(&self) -> Vec<u8> {
+        self.ram.clone()
+    }
+    fn load_save_data(&mut self, data: &[u8]) {
+        load_ram(&mut self.ram, data);
+        for byte in self.ram.iter_mut() {
+            *byte &= 0x0f;
+        }
+    }
+}
+
+const MBC5_RUMBLE: u8 = 0b0000_1000;
+
+/// MBC5, up to 8 MiB ROM with a 9 bit bank number and 128 KiB RAM. Rumble carts use bit 3 of
+/// the RAM bank register for the motor.
+pub struct MBC5 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    ram_enabled: bool,
+    /// unlike MBC1, bank 0 can be mapped to 0x4000-0x7fff
+    rom_bank: u16,
+    ram_bank: u8,
+    has_rumble: bool,
+    rumble: bool,
+}
+
+impl MBC5 {
+    pub fn new(rom: Vec<u8>, ram_size: usize, has_rumble: bool) -> MBC5 {
+        MBC5 {
+            rom,
+            ram: vec![0; ram_size],
+            ram_enabled: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            has_rumble,
+            rumble: false,
+        }
+    }
+}
+
+impl Mapper for MBC5 {
+    fn read_rom(&self, addr: u16) -> u8 {
+        match addr {
+            0x0000..0x4000 => read_banked(&self.rom, 0, ROM_BANK_SIZE, addr),
+            _ => read_banked(
+                &self.rom,
+                self.rom_bank as usize,
+                ROM_BANK_SIZE,
+                addr - 0x4000,
+            ),
+        }
+    }
+    fn write_rom(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x0000..0x2000 => self.ram_enabled = val == 0x0a,
+            0x2000..0x3000 => self.rom_bank = (self.rom_bank & 0x100) | val as u16,
+            0x3000..0x4000 => self.rom_bank = (self.rom_bank & 0xff) | ((val as u16 & 1) << 8),
+            0x4000..0x6000 if self.has_rumble => {
+                self.rumble = val & MBC5_RUMBLE != 0;
+                self.ram_bank = val & 0x07;
+            }
+            0x4000..0x6000 => self.ram_bank = val & 0x0f,
+            _ => {}
+        }
+    }
+    fn read_ram(&self, addr: u16) -> u8 {
+        if !self.ram_enabled || self.ram.is_empty() {
+            return OPEN_BUS;
+        }
+        read_banked(&self.ram, self.ram_bank as usize, RAM_BANK_SIZE, addr)
+    }
+    fn write_ram(&mut self, addr: u16, val: u8) {
+        if !self.ram_enabled || self.ram.is_empty() {
+            return;
+        }
+        let bank = self.ram_bank as usize;
+        write_banked(&mut self.ram, bank, RAM_BANK_SIZE, addr, val);
+    }
+    fn rumble(&self) -> bool {
+        self.rumble
+    }
+    fn save_data(&self) -> Vec<u8> {
+        self.ram.clone()
+    }
+    fn load_save_data(&mut self, data: &[u8]) {
+        load_ram(&mut self.ram, data);
+    }
+}
+
+/// MMM01 multicart controller. It starts unmapped with the menu in the last 32 KiB of ROM,
+/// the menu writes the game's base bank and masks then sets the map enable bit, which locks
+/// them and hands the game an MBC1 like interface.
+pub struct MMM01 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    mapped: bool,
+    ram_enabled: bool,
+    /// bits 0-4 of the ROM bank, the game's MBC1 bank register
+    rom_bank_low: u8,
+    /// bits 5-6, set by the menu
+    rom_bank_mid: u8,
+    /// bits 7-8, set by the menu
+    rom_bank_high: u8,
+    /// bits of rom_bank_low the game can't change once mapped
+    rom_bank_mask: u8,
+    ram_bank_low: u8,
+    ram_bank_high: u8,
+    /// bits of ram_bank_low the game can't change once mapped
+    ram_bank_mask: u8,
+    mbc1_mode: bool,
+    mbc1_mode_locked: bool,
+}
+
+impl MMM01 {
+    pub fn new(rom: Vec<u8>, ram_size: usize) -> MMM01 {
+        MMM01 {
+            rom,
+            ram: vec![0; ram_size],
+            mapped: false,
+            ram_enabled: false,
+            rom_bank_low: 0,
+            rom_bank_mid: 0,
+            rom_bank_high: 0,
+            rom_bank_mask: 0,
+            ram_bank_low: 0,
+            ram_bank_high: 0,
+            ram_bank_mask: 0,
+            mbc1_mode: false,
+            mbc1_mode_locked: false,
+        }
+    }
+    fn rom_bank_base(&self) -> usize {
+        ((self.rom_bank_high as usize) << 7) | ((self.rom_bank_mid as usize) << 5)
+    }
+    fn rom_bank(&self, high_area: bool) -> usize {
+        if !self.mapped {
+            let last = bank_count(self.rom.len(), ROM_BANK_SIZE) - 1;
+            return if high_area { last } else { last - 1 };
+        }
+        let low = if high_area {
+            // like MBC1 the zero check ignores the bits taken by the menu
+            if self.rom_bank_low & !self.rom_bank_mask == 0 {
+                self.rom_bank_low | 1
+            } else {
+                self.rom_bank_low
+            }
+        } else {
+            self.rom_bank_low & self.rom_bank_mask
+        };
+        self.rom_bank_base() | low as usize
+    }
+    fn ram_bank(&self) -> usize {
+        let low = if self.mbc1_mode {
+            self.ram_bank_low
+        } else {
+            self.ram_bank_low & self.ram_bank_mask
+        };
+        ((self.ram_bank_high as usize) << 2) | low as usize
+    }
+    /// Only the bits outside `mask` change once mapped.
+    fn masked_write(&self, old: u8, new: u8, mask: u8) -> u8 {
+        if self.mapped {
+            (old & mask) | (new & !mask)
+        } else {
+            new
+        }
+    }
+}
+
+impl Mapper for MMM01 {
+    fn read_rom(&self, addr: u16) -> u8 {
+        match addr {
+            0x0000..0x4000 => read_banked(&self.rom, self.rom_bank(false), ROM_BANK_SIZE, addr),
+            _ => read_banked(&self.rom, self.rom_bank(true), ROM_BANK_SIZE, addr - 0x4000),
+        }
+    }
+    fn write_rom(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x0000..0x2000 => {
+                self.ram_enabled = val & 0x0f == 0x0a;
+                if !self.mapped {
+                    self.ram_bank_mask = (val >> 4) & 0x03;
+                    self.mapped = val & 0x40 != 0;
+                }
+            }
+            0x2000..0x4000 => {
+                self.rom_bank_low =
+                    self.masked_write(self.rom_bank_low, val & 0x1f, self.rom_bank_mask);
+                if !self.mapped {
+                    self.rom_bank_mid = (val >> 5) & 0x03;
+                }
+            }
+            0x4000..0x6000 => {
+                self.ram_bank_low =
+                    self.masked_write(self.ram_bank_low, val & 0x03, self.ram_bank_mask);
+                if !self.mapped {
+                    self.ram_bank_high = (val >> 2) & 0x03;
+                    self.rom_bank_high = (val >> 4) & 0x03;
+                    self.mbc1_mode_locked = val & 0x40 != 0;
+                }
+            }
+            _ => {
+                if !self.mapped {
+                    // mask bits 2-5 cover bank bits 1-4
+                    self.rom_bank_mask = (val >> 1) & 0x1e;
+                }
+                if !self.mbc1_mode_locked {
+                    self.mbc1_mode = val & 0x01 != 0;
+                }
+            }
+        }
+    }
+    fn read_ram(&self, addr: u16) -> u8 {
+        if !self.ram_enabled || self.ram.is_empty() {
+            return OPEN_BUS;
+        }
+        read_banked(&self.ram, self.ram_bank(), RAM_BANK_SIZE, addr)
+    }
+    fn write_ram(&mut self, addr: u16, val: u8) {
+        if !self.ram_enabled || self.ram.is_empty() {
+            return;
+        }
+        let bank = self.ram_bank();
+        write_banked(&mut self.ram, bank, RAM_BANK_SIZE, addr, val);
+    }
+    fn save_data(&self) -> Vec<u8> {
+        self.ram.clone()
+    }
+    fn load_save_data(&mut self, data: &[u8]) {
+        load_ram(&mut self.ram, data);
+    }
+}
+
+/// no light seen by the infrared receiver
+const IR_DARK: u8 = 0xc0;
+
+/// Hudson HuC1, MBC1 like banking with an infrared port mapped in place of RAM.
+pub struct HuC1 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    ir_mode: bool,
+    rom_bank: u8,
+    ram_bank: u8,
+    ir_led: bool,
+}
+
+impl HuC1 {
+    pub fn new(rom: Vec<u8>, ram_size: usize) -> HuC1 {
+        HuC1 {
+            rom,
+            ram: vec![0; ram_size],
+            ir_mode: false,
+            rom_bank: 1,
+            ram_bank: 0,
+            ir_led: false,
+        }
+    }
+}
+
+impl Mapper for HuC1 {
+    fn read_rom(&self, addr: u16) -> u8 {
+        match addr {
+            0x0000..0x4000 => read_banked(&self.rom, 0, ROM_BANK_SIZE, addr),
+            _ => read_banked(
+                &self.rom,
+                self.rom_bank as usize,
+                ROM_BANK_SIZE,
+                addr - 0x4000,
+            ),
+        }
+    }
+    fn write_rom(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x0000..0x2000 => self.ir_mode = val & 0x0f == 0x0e,
+            0x2000..0x4000 => self.rom_bank = (val & 0x3f).max(1),
+            0x4000..0x6000 => self.ram_bank = val & 0x03,
+            _ => {}
+        }
+    }
+    fn read_ram(&self, addr: u16) -> u8 {
+        if self.ir_mode {
+            return IR_DARK;
+        }
+        if self.ram.is_empty() {
+            return OPEN_BUS;
+        }
+        read_banked(&self.ram, self.ram_bank as usize, RAM_BANK_SIZE, addr)
+    }
+    fn write_ram(&mut self, addr: u16, val: u8) {
+        if self.ir_mode {
+            self.ir_led = val & 0x01 != 0;
+            return;
+        }
+        let bank = self.ram_bank as usize;
+        write_banked(&mut self.ram, bank, RAM_BANK_SIZE, addr, val);
+    }
+    fn save_data(&self) -> Vec<u8> {
+        self.ram.clone()
+    }
+    fn load_save_data(&mut self, data: &[u8]) {
+        load_ram(&mut self.ram, data);
+    }
+}
+
+const MINUTES_PER_DAY: u16 = 24 * 60;
+/// clock as minutes and days as little endian u32 then a u64 unix timestamp
+const HUC3_RTC_SAVE_SIZE: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum HuC3Mode {
+    /// 0x0 read only, 0xa read and write
+    Ram {
+        writable: bool,
+    },
+    /// 0xb, commands to the clock chip
+    Command,
+    /// 0xc, result of the last command
+    Response,
+    /// 0xd, clock chip ready flag
+    Semaphore,
+    /// 0xe
+    Infrared,
+    Disabled,
+}
+
+/// Hudson HuC3, MBC3 like banking with a clock chip talked to through 4 bit commands. The chip
+/// has 256 nibbles of memory, the clock is copied in and out of the first 6.
+pub struct HuC3 {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    mode: HuC3Mode,
+    rom_bank: u8,
+    ram_bank: u8,
+    clock: RtcClock,
+    minutes: u16,
+    /// 12 bit day counter
+    days: u16,
+    sub_minute: u32,
+    updated_at: u64,
+    nibbles: [u8; 256],
+    nibble_addr: u8,
+    last_command: u8,
+    response: u8,
+    ir_led: bool,
+}
+
+impl HuC3 {
+    pub fn new(rom: Vec<u8>, ram_size: usize) -> HuC3 {
+        HuC3 {
+            rom,
+            ram: vec![0; ram_size],
+            mode: HuC3Mode::Disabled,
+            rom_bank: 1,
+            ram_bank: 0,
+            clock: RtcClock::Cycles,
+            minutes: 0,
+            days: 0,
+            sub_minute: 0,
+            updated_at: unix_time(),
+            nibbles: [0; 256],
+            nibble_addr: 0,
+            last_command: 0,
+            response: 0,
+            ir_led: false,
+        }
+    }
+    fn advance_minutes(&mut self, minutes: u64) {
+        let total = self.minutes as u64 + minutes;
+        self.minutes = (total % MINUTES_PER_DAY as u64) as u16;
+        self.days = ((self.days as u64 + total / MINUTES_PER_DAY as u64) & 0x0fff) as u16;
+    }
+    fn sync(&mut self) {
+        let now = unix_time();
+        if self.clock == RtcClock::WallClock {
+            let elapsed = now.saturating_sub(self.updated_at) + self.sub_minute as u64;
+            self.advance_minutes(elapsed / 60);
+            self.sub_minute = (elapsed % 60) as u32;
+        }
+        self.updated_at = now;
+    }
+    fn run_command(&mut self, val: u8) {
+        let command = (val >> 4) & 0x07;
+        let arg = val & 0x0f;
+        self.last_command = command;
+        match command {
+            // read and increment
+            0x1 => {
+                self.response = self.nibbles[self.nibble_addr as usize];
+                self.nibble_addr = self.nibble_addr.wrapping_add(1);
+            }
+            // write and increment
+            0x3 => {
+                self.nibbles[self.nibble_addr as usize] = arg;
+                self.nibble_addr = self.nibble_addr.wrapping_add(1);
+            }
+            0x4 => self.nibble_addr = (self.nibble_addr & 0xf0) | arg,
+            0x5 => self.nibble_addr = (self.nibble_addr & 0x0f) | (arg << 4),
+            0x6 => match arg {
+                // clock to memory
+                0x0 => {
+                    self.sync();
+                    for i in 0..3 {
+                        self.nibbles[i] = ((self.minutes >> (4 * i)) & 0x0f) as u8;
+                        self.nibbles[3 + i] = ((self.days >> (4 * i)) & 0x0f) as u8;
+                    }
+                }
+                // memory to clock
+                0x1 => {
+                    self.sync();
+                    let nibbles = |start: usize| {
+                        (0..3).fold(0u16, |v, i| {
+                            v | ((self.nibbles[start + i] as u16) << (4 * i))
+                        })
+                    };
+                    self.minutes = nibbles(0) % MINUTES_PER_DAY;
+                    self.days = nibbles(3);
+                    self.sub_minute = 0;
+                }
+                // status, always ready
+                0x2 => self.response = 0x1,
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+}
+
+impl Mapper for HuC3 {
+    fn read_rom(&self, addr: u16) -> u8 {
+        match addr {
+            0x0000..0x4000 => read_banked(&self.rom, 0, ROM_BANK_SIZE, addr),
+            _ => read_banked(
+                &self.rom,
+                self.rom_bank as usize,
+                ROM_BANK_SIZE,
+                addr - 0x4000,
+            ),
+        }
+    }
+    fn write_rom(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x0000..0x2000 => {
+                self.mode = match val & 0x0f {
+                    0x0 => HuC3Mode::Ram { writable: false },
+                    0xa => HuC3Mode::Ram { writable: true },
+                    0xb => HuC3Mode::Command,
+                    0xc => HuC3Mode::Response,
+                    0xd => HuC3Mode::Semaphore,
+                    0xe => HuC3Mode::Infrared,
+                    _ => HuC3Mode::Disabled,
+                }
+            }
+            0x2000..0x4000 => self.rom_bank = val & 0x7f,
+            0x4000..0x6000 => self.ram_bank = val & 0x03,
+            _ => {}
+        }
+    }
+    fn read_ram(&self, addr: u16) -> u8 {
+        match self.mode {
+            HuC3Mode::Ram { .. } if !self.ram.is_empty() => {
+                read_banked(&self.ram, self.ram_bank as usize, RAM_BANK_SIZE, addr)
+            }
+            HuC3Mode::Response => 0x80 | (self.last_command << 4) | self.response,
+            HuC3Mode::Semaphore => 0x01,
+            HuC3Mode::Infrared => IR_DARK,
+            _ => OPEN_BUS,
+        }
+    }
+    fn write_ram(&mut self, addr: u16, val: u8) {
+        match self.mode {
+            HuC3Mode::Ram { writable: true } if !self.ram.is_empty() => {
+                let bank = self.ram_bank as usize;
+                write_banked(&mut self.ram, bank, RAM_BANK_SIZE, addr, val);
+            }
+            HuC3Mode::Command => self.run_command(val),
+            HuC3Mode::Infrared => self.ir_led = val & 0x01 != 0,
+            _ => {}
+        }
+    }
+    fn tick(&mut self, cycles: u8) {
+        if self.clock != RtcClock::Cycles {
+            return;
+        }
+        self.sub_minute += cycles as u32;
+        if self.sub_minute >= 60 * CYCLES_PER_SECOND {
+            self.sub_minute -= 60 * CYCLES_PER_SECOND;
+            self.advance_minutes(1);
+        }
+    }
+    fn set_rtc_clock(&mut self, clock: RtcClock) {
+        self.sync();
+        // sub_minute counts cycles or seconds depending on the clock
+        self.sub_minute = 0;
+        self.clock = clock;
+    }
+    fn save_data(&self) -> Vec<u8> {
+        let mut data = self.ram.clone();
+        data.extend_from_slice(&(self.minutes as u32).to_le_bytes());
+        data.extend_from_slice(&(self.days as u32).to_le_bytes());
+        let updated_at = match self.clock {
+            RtcClock::Cycles => self.updated_at,
+            RtcClock::WallClock => unix_time(),
+        };
+        data.extend_from_slice(&updated_at.to_le_bytes());
+        data
+    }
+    fn load_save_data(&mut self, data: &[u8]) {
+        load_ram(&mut self.ram, data);
+        let footer = data.get(self.ram.len()..).unwrap_or_default();
+        if footer.len() == HUC3_RTC_SAVE_SIZE {
+            let word = |i: usize| u32::from_le_bytes(footer[i..i + 4].try_into().unwrap());
+            self.minutes = (word(0) % MINUTES_PER_DAY as u32) as u16;
+            self.days = (word(4) & 0x0fff) as u16;
+            self.updated_at = u64::from_le_bytes(footer[8..16].try_into().unwrap());
+            self.sub_minute = 0;
+            self.sync();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// ROM of `banks` banks whose first byte is the bank number.
+    fn numbered_rom(banks: usize) -> Vec<u8> {
+        let mut rom = vec![0; banks * ROM_BANK_SIZE];
+        for bank in 0..banks {
+            rom[bank * ROM_BANK_SIZE] = bank as u8;
+        }
+        rom
+    }
+
+    /// Banks as seen at 0x0000 and 0x4000.
+    fn mapped_banks(mapper: &dyn Mapper) -> (u8, u8) {
+        (mapper.read_rom(0x0000), mapper.read_rom(0x4000))
+    }
+
+    #[test]
+    fn mbc1_rom_banks() {
+        let mut mbc = MBC1::new(numbered_rom(0x80), 0);
+        assert_eq!(mapped_banks(&mbc), (0x00, 0x01));
+        mbc.write_rom(0x2000, 0x05);
+        assert_eq!(mapped_banks(&mbc), (0x00, 0x05));
+        // bank 0 reads as 1, also with the upper bits set
+        mbc.write_rom(0x2000, 0x00);
+        assert_eq!(mapped_banks(&mbc), (0x00, 0x01));
+        mbc.write_rom(0x4000, 0x01);
+        assert_eq!(mapped_banks(&mbc), (0x00, 0x21));
+        // only 5 bits are stored
+        mbc.write_rom(0x2000, 0xe3);
+        assert_eq!(mapped_banks(&mbc), (0x00, 0x23));
+        // mode 1 applies the upper bits to 0x0000-0x3fff as well
+        mbc.write_rom(0x6000, 0x01);
+        assert_eq!(mapped_banks(&mbc), (0x20, 0x23));
+        mbc.write_rom(0x4000, 0x03);
+        assert_eq!(mapped_banks(&mbc), (0x60, 0x63));
+    }
+
+    #[test]
+    fn mbc1_masks_banks_to_the_rom_size() {
+        // 256 KiB
+        let mut mbc = MBC1::new(numbered_rom(0x10), 0);
+        mbc.write_rom(0x2000, 0x12);
+        assert_eq!(mapped_banks(&mbc), (0x00, 0x02));
+        // the zero check sees all 5 bits, 0x10 maps bank 0 once the unconnected bit is dropped
+        mbc.write_rom(0x2000, 0x10);
+        assert_eq!(mapped_banks(&mbc), (0x00, 0x00));
+        mbc.write_rom(0x4000, 0x03);
+        mbc.write_rom(0x6000, 0x01);
+        assert_eq!(mapped_banks(&mbc), (0x00, 0x00));
+    }
+
+    #[test]
+    fn mbc1_ram_banks() {
+        let mut mbc = MBC1::new(numbered_rom(0x04), 4 * RAM_BANK_SIZE);
+        // disabled RAM ignores writes and reads open bus
+        mbc.write_ram(0x0000, 0x12);
+        assert_eq!(mbc.read_ram(0x0000), OPEN_BUS);
+        mbc.write_rom(0x0000, 0x0a);
+        for bank in 0..4 {
+            mbc.write_rom(0x4000, bank);
+            mbc.write_rom(0x6000, 0x01);
+            mbc.write_ram(0x0000, 0x10 + bank);
+        }
+        for bank in 0..4 {
+            mbc.write_rom(0x4000, bank);
+            assert_eq!(mbc.read_ram(0x0000), 0x10 + bank);
+        }
+        // mode 0 always maps RAM bank 0
+        mbc.write_rom(0x6000, 0x00);
+        assert_eq!(mbc.read_ram(0x0000), 0x10);
+        mbc.write_rom(0x0000, 0x00);
+        assert_eq!(mbc.read_ram(0x0000), OPEN_BUS);
+        assert_eq!(
+            mbc.save_data()
+                .chunks(RAM_BANK_SIZE)
+                .map(|bank| bank[0])
+                .collect::<Vec<_>>(),
+            vec![0x10, 0x11, 0x12, 0x13]
+        );
+    }
+
+    #[test]
+    fn mbc1m_multicart() {
+        let mut rom = numbered_rom(0x40);
+        for game in 0..4 {
+            let start = game * 0x10 * ROM_BANK_SIZE + LOGO_START;
+            rom[start..start + LOGO_SIZE].fill(0xce);
+        }
+        let mut mbc = MBC1::new(rom, 0);
+        assert!(mbc.multicart);
+        // 4 bit ROM bank, bank2 picks the game
+        mbc.write_rom(0x2000, 0x12);
+        assert_eq!(mapped_banks(&mbc), (0x00, 0x02));
+        mbc.write_rom(0x4000, 0x01);
+        assert_eq!(mapped_banks(&mbc), (0x00, 0x12));
+        mbc.write_rom(0x6000, 0x01);
+        assert_eq!(mapped_banks(&mbc), (0x10, 0x12));
+        mbc.write_rom(0x4000, 0x03);
+        mbc.write_rom(0x2000, 0x00);
+        assert_eq!(mapped_banks(&mbc), (0x30, 0x31));
+    }
+
+    #[test]
+    fn mbc1_without_repeated_logo_is_not_a_multicart() {
+        let mut rom = numbered_rom(0x40);
+        rom[LOGO_START..LOGO_START + LOGO_SIZE].fill(0xce);
+        let mut mbc = MBC1::new(rom, 0);
+        assert!(!mbc.multicart);
+        mbc.write_rom(0x2000, 0x12);
+        mbc.write_rom(0x4000, 0x01);
+        assert_eq!(mapped_banks(&mbc), (0x00, 0x32));
+    }
+
+    fn mbc3_with_rtc() -> MBC3 {
+        let mut mbc = MBC3::new(numbered_rom(0x04), RAM_BANK_SIZE, true);
+        mbc.write_rom(0x0000, 0x0a);
+        mbc
+    }
+
+    fn write_rtc(mbc: &mut MBC3, reg: u8, val: u8) {
+        mbc.write_rom(0x4000, reg);
+        mbc.write_ram(0x0000, val);
+    }
+
+    fn latch_rtc(mbc: &mut MBC3) {
+        mbc.write_rom(0x6000, 0x00);
+        mbc.write_rom(0x6000, 0x01);
+    }
+
+    /// Latched seconds, minutes, hours, day low and day high.
+    fn read_rtc(mbc: &mut MBC3) -> [u8; 5] {
+        [0x08, 0x09, 0x0a, 0x0b, 0x0c].map(|reg| {
+            mbc.write_rom(0x4000, reg);
+            mbc.read_ram(0x0000)
+        })
+    }
+
+    fn tick_seconds(mapper: &mut dyn Mapper, seconds: u32) {
+        for _ in 0..seconds * CYCLES_PER_SECOND / 128 {
+            mapper.tick(128);
+        }
+    }
+
+    #[test]
+    fn rtc_latch() {
+        let mut mbc = mbc3_with_rtc();
+        write_rtc(&mut mbc, 0x08, 5);
+        // writes go to the live registers, reads keep the latched value until the next
+        // 0 then 1 write
+        assert_eq!(read_rtc(&mut mbc)[0], 0);
+        tick_seconds(&mut mbc, 1);
+        mbc.write_rom(0x6000, 0x01);
+        assert_eq!(read_rtc(&mut mbc)[0], 0);
+        latch_rtc(&mut mbc);
+        assert_eq!(read_rtc(&mut mbc)[0], 6);
+        tick_seconds(&mut mbc, 2);
+        assert_eq!(read_rtc(&mut mbc)[0], 6);
+        latch_rtc(&mut mbc);
+        assert_eq!(read_rtc(&mut mbc)[0], 8);
+    }
+
+    #[test]
+    fn rtc_day_counter_carry() {
+        let mut mbc = mbc3_with_rtc();
+        write_rtc(&mut mbc, 0x08, 59);
+        write_rtc(&mut mbc, 0x09, 59);
+        write_rtc(&mut mbc, 0x0a, 23);
+        write_rtc(&mut mbc, 0x0b, 0xff);
+        write_rtc(&mut mbc, 0x0c, 0x00);
+        tick_seconds(&mut mbc, 1);
+        latch_rtc(&mut mbc);
+        // day 255 to 256 sets the day high bit
+        assert_eq!(read_rtc(&mut mbc), [0, 0, 0, 0x00, RTC_DH_DAY_HIGH]);
+
+        write_rtc(&mut mbc, 0x08, 59);
+        write_rtc(&mut mbc, 0x09, 59);
+        write_rtc(&mut mbc, 0x0a, 23);
+        write_rtc(&mut mbc, 0x0b, 0xff);
+        tick_seconds(&mut mbc, 1);
+        latch_rtc(&mut mbc);
+        // day 511 wraps to 0 and sets the carry, which stays until written
+        assert_eq!(read_rtc(&mut mbc), [0, 0, 0, 0x00, RTC_DH_CARRY]);
+        tick_seconds(&mut mbc, 1);
+        latch_rtc(&mut mbc);
+        assert_eq!(read_rtc(&mut mbc), [1, 0, 0, 0x00, RTC_DH_CARRY]);
+        write_rtc(&mut mbc, 0x0c, 0x00);
+        latch_rtc(&mut mbc);
+        assert_eq!(read_rtc(&mut mbc)[4], 0x00);
+    }
+
+    #[test]
+    fn rtc_halt() {
+        let mut mbc = mbc3_with_rtc();
+        write_rtc(&mut mbc, 0x0c, RTC_DH_HALT);
+        write_rtc(&mut mbc, 0x08, 10);
+        tick_seconds(&mut mbc, 3);
+        latch_rtc(&mut mbc);
+        assert_eq!(read_rtc(&mut mbc), [10, 0, 0, 0, RTC_DH_HALT]);
+        write_rtc(&mut mbc, 0x0c, 0x00);
+        tick_seconds(&mut mbc, 3);
+        latch_rtc(&mut mbc);
+        assert_eq!(read_rtc(&mut mbc), [13, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn rtc_registers_drop_unused_bits() {
+        let mut mbc = mbc3_with_rtc();
+        write_rtc(&mut mbc, 0x08, 0xff);
+        write_rtc(&mut mbc, 0x09, 0xff);
+        write_rtc(&mut mbc, 0x0a, 0xff);
+        write_rtc(&mut mbc, 0x0c, 0xff | RTC_DH_HALT);
+        latch_rtc(&mut mbc);
+        assert_eq!(
+            read_rtc(&mut mbc),
+            [
+                0x3f,
+                0x3f,
+                0x1f,
+                0x00,
+                RTC_DH_DAY_HIGH | RTC_DH_HALT | RTC_DH_CARRY
+            ]
+        );
+    }
+
+    #[test]
+    fn mbc3_save_round_trip() {
+        let mut mbc = mbc3_with_rtc();
+        mbc.write_rom(0x4000, 0x00);
+        mbc.write_ram(0x0123, 0x45);
+        write_rtc(&mut mbc, 0x08, 12);
+        write_rtc(&mut mbc, 0x0b, 0x34);
+        write_rtc(&mut mbc, 0x0c, RTC_DH_DAY_HIGH);
+        latch_rtc(&mut mbc);
+        tick_seconds(&mut mbc, 1);
+        let data = mbc.save_data();
+        assert_eq!(data.len(), RAM_BANK_SIZE + RTC_SAVE_SIZE);
+        let footer = &data[RAM_BANK_SIZE..];
+        // live registers, then the latched ones
+        assert_eq!(footer[0..4], 13u32.to_le_bytes());
+        assert_eq!(footer[12..16], 0x34u32.to_le_bytes());
+        assert_eq!(footer[16..20], (RTC_DH_DAY_HIGH as u32).to_le_bytes());
+        assert_eq!(footer[20..24], 12u32.to_le_bytes());
+
+        let mut loaded = mbc3_with_rtc();
+        loaded.load_save_data(&data);
+        assert_eq!(
+            loaded.save_data()[..RAM_BANK_SIZE + 40],
+            data[..RAM_BANK_SIZE + 40]
+        );
+        loaded.write_rom(0x4000, 0x00);
+        assert_eq!(loaded.read_ram(0x0123), 0x45);
+        assert_eq!(read_rtc(&mut loaded), [12, 0, 0, 0x34, RTC_DH_DAY_HIGH]);
+        latch_rtc(&mut loaded);
+        assert_eq!(read_rtc(&mut loaded)[0], 13);
+    }
+
+    #[test]
+    fn mbc3_loads_32_bit_timestamp_saves() {
+        let mut mbc = mbc3_with_rtc();
+        write_rtc(&mut mbc, 0x0a, 7);
+        latch_rtc(&mut mbc);
+        let mut data = mbc.save_data();
+        data.truncate(RAM_BANK_SIZE + RTC_SAVE_SIZE_32BIT);
+        let mut loaded = mbc3_with_rtc();
+        loaded.load_save_data(&data);
+        assert_eq!(read_rtc(&mut loaded)[2], 7);
+    }
+
+    #[test]
+    fn mbc2_registers_and_ram() {
+        let mut mbc = MBC2::new(numbered_rom(0x10));
+        // address bit 8 picks the register, not the address range
+        mbc.write_rom(0x3eff, 0x0a);
+        mbc.write_rom(0x0100, 0x05);
+        assert_eq!(mapped_banks(&mbc), (0x00, 0x05));
+        mbc.write_rom(0x0100, 0x00);
+        assert_eq!(mapped_banks(&mbc), (0x00, 0x01));
+        // a RAM enable value sent to the bank register only changes the bank
+        mbc.write_rom(0x3f00, 0x0a);
+        assert_eq!(mapped_banks(&mbc), (0x00, 0x0a));
+        mbc.write_ram(0x0000, 0x5c);
+        assert_eq!(mbc.read_ram(0x0000), 0xfc);
+        // 512 half bytes repeat through the area
+        assert_eq!(mbc.read_ram(0x0200), 0xfc);
+        assert_eq!(mbc.read_ram(0x1e00), 0xfc);
+        assert_eq!(mbc.save_data()[0], 0x0c);
+        mbc.write_rom(0x0000, 0x00);
+        assert_eq!(mbc.read_ram(0x0000), OPEN_BUS);
+    }
+
+    #[test]
+    fn mbc5_rom_banks_and_ram_enable() {
+        // the bank number's ninth bit goes in the second byte of each bank
+        let mut rom = numbered_rom(0x200);
+        for bank in 0..0x200 {
+            rom[bank * ROM_BANK_SIZE + 1] = (bank >> 8) as u8;
+        }
+        let mut mbc = MBC5::new(rom, RAM_BANK_SIZE, false);
+        let high_bank =
+            |mbc: &MBC5| u16::from_le_bytes([mbc.read_rom(0x4000), mbc.read_rom(0x4001)]);
+        assert_eq!(high_bank(&mbc), 0x001);
+        mbc.write_rom(0x2000, 0x00);
+        assert_eq!(high_bank(&mbc), 0x000);
+        mbc.write_rom(0x3000, 0x01);
+        assert_eq!(high_bank(&mbc), 0x100);
+        mbc.write_rom(0x2000, 0x05);
+        assert_eq!(high_bank(&mbc), 0x105);
+        mbc.write_rom(0x3000, 0x02);
+        assert_eq!(high_bank(&mbc), 0x005);
+        assert_eq!(mbc.read_rom(0x0000), 0x00);
+
+        // only 0x0a enables RAM, the upper nibble isn't ignored
+        mbc.write_rom(0x0000, 0x0a);
+        mbc.write_ram(0x0000, 0x42);
+        assert_eq!(mbc.read_ram(0x0000), 0x42);
+        mbc.write_rom(0x0000, 0x1a);
+        assert_eq!(mbc.read_ram(0x0000), OPEN_BUS);
+        mbc.write_rom(0x0000, 0x0a);
+        assert_eq!(mbc.read_ram(0x0000), 0x42);
+        mbc.write_rom(0x0000, 0x0b);
+        assert_eq!(mbc.read_ram(0x0000), OPEN_BUS);
+    }
+
+    #[test]
+    fn mmm01_locks_the_mapping() {
+        let mut mbc = MMM01::new(numbered_rom(0x40), 0);
+        // the menu sits in the last two banks until mapped
+        assert_eq!(mapped_banks(&mbc), (0x3e, 0x3f));
+        // game at bank 0x20, bank bits 1-3 fixed by the menu
+        mbc.write_rom(0x2000, 0x22);
+        mbc.write_rom(0x6000, 0x1c);
+        mbc.write_rom(0x0000, 0x40);
+        // like MBC1 the zero check only sees the game's bits
+        assert_eq!(mapped_banks(&mbc), (0x22, 0x23));
+        // the game only changes the unmasked bits
+        mbc.write_rom(0x2000, 0x71);
+        assert_eq!(mapped_banks(&mbc), (0x22, 0x33));
+        mbc.write_rom(0x2000, 0x00);
+        assert_eq!(mapped_banks(&mbc), (0x22, 0x23));
+        // the base bank, mask and map enable can't change any more
+        mbc.write_rom(0x4000, 0x30);
+        mbc.write_rom(0x6000, 0x00);
+        mbc.write_rom(0x0000, 0x00);
+        mbc.write_rom(0x2000, 0x01);
+        assert_eq!(mapped_banks(&mbc), (0x22, 0x23));
+    }
+
+    #[test]
+    fn huc1_ir_register_select() {
+        let mut mbc = HuC1::new(numbered_rom(0x04), RAM_BANK_SIZE);
+        mbc.write_ram(0x0000, 0x5a);
+        assert_eq!(mbc.read_ram(0x0000), 0x5a);
+        // 0x0e maps the IR port over RAM, writes drive the LED and leave RAM alone
+        mbc.write_rom(0x0000, 0x0e);
+        assert_eq!(mbc.read_ram(0x0000), IR_DARK);
+        mbc.write_ram(0x0000, 0x01);
+        assert!(mbc.ir_led);
+        mbc.write_rom(0x0000, 0x00);
+        assert_eq!(mbc.read_ram(0x0000), 0x5a);
+    }
+
+    #[test]
+    fn huc3_rtc_commands() {
+        let mut mbc = HuC3::new(numbered_rom(0x04), RAM_BANK_SIZE);
+        let command = |mbc: &mut HuC3, val: u8| {
+            mbc.write_rom(0x0000, 0x0b);
+            mbc.write_ram(0x0000, val);
+            mbc.write_rom(0x0000, 0x0c);
+            mbc.read_ram(0x0000)
+        };
+        // status reads back as ready, with the command echoed in the response
+        assert_eq!(command(&mut mbc, 0x62), 0xe1);
+        mbc.write_rom(0x0000, 0x0d);
+        assert_eq!(mbc.read_ram(0x0000), 0x01);
+
+        // set address 0, write 0x123 minutes and day 0x045, then copy them to the clock
+        command(&mut mbc, 0x40);
+        command(&mut mbc, 0x50);
+        for nibble in [0x3, 0x2, 0x1, 0x5, 0x4, 0x0] {
+            command(&mut mbc, 0x30 | nibble);
+        }
+        command(&mut mbc, 0x61);
+        tick_seconds(&mut mbc, 60);
+        // copy the clock back to memory and read it nibble by nibble
+        command(&mut mbc, 0x60);
+        command(&mut mbc, 0x40);
+        command(&mut mbc, 0x50);
+        let nibbles: Vec<u8> = (0..6).map(|_| command(&mut mbc, 0x10)).collect();
+        assert_eq!(nibbles, [0x94, 0x92, 0x91, 0x95, 0x94, 0x90]);
+    }
+}
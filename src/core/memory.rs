@@ -1,22 +1,160 @@
+use crate::core::apu::{APU, DIV_FRAME_SEQUENCER_BIT};
+use crate::core::cartridge::Cartridge;
 use crate::core::constants::*;
+use crate::core::joypad::{Button, Joypad};
+use crate::core::mbc;
+use crate::core::timer::Timer;
 use rand::{self, RngCore};
 
 const BOOT_ROM_BYTES: &[u8; 256] = include_bytes!("DMG_ROM.bin");
 const RAM_SIZE: usize = 2usize.pow(16);
 
+/// Bits of each 0xff00-0xff7f register that read as 1 on DMG, 0xff for unmapped registers.
+#[rustfmt::skip]
+const IO_READ_MASKS: [u8; 0x80] = [
+    // P1    SB    SC          DIV   TIMA  TMA   TAC
+    0xc0, 0x00, 0x7e, 0xff, 0x00, 0x00, 0x00, 0xf8,
+    //                                           IF
+    0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xe0,
+    // NR10  NR11  NR12  NR13  NR14        NR21  NR22
+    0x80, 0x3f, 0x00, 0xff, 0xbf, 0xff, 0x3f, 0x00,
+    // NR23  NR24  NR30  NR31  NR32  NR33  NR34
+    0xff, 0xbf, 0x7f, 0xff, 0x9f, 0xff, 0xbf, 0xff,
+    // NR41  NR42  NR43  NR44  NR50  NR51  NR52
+    0xff, 0x00, 0x00, 0xbf, 0x00, 0x00, 0x70, 0xff,
+    0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+    // wave RAM
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    // LCDC  STAT  SCY   SCX   LY    LYC   DMA   BGP
+    0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    // OBP0  OBP1  WY    WX
+    0x00, 0x00, 0x00, 0x00, 0xff, 0xff, 0xff, 0xff,
+    // boot rom disable, write only
+    0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+    0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+    0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+    0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+    0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+    0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+];
+
+/// Cartridge side of the bus, 0x0000-0x7fff ROM and 0xa000-0xbfff external RAM. Addresses are
+/// relative to the start of each area.
+pub trait Mapper {
+    fn read_rom(&self, addr: u16) -> u8;
+    /// ROM writes go to the bank controller registers
+    fn write_rom(&mut self, addr: u16, val: u8);
+    fn read_ram(&self, addr: u16) -> u8;
+    fn write_ram(&mut self, addr: u16, val: u8);
+    /// Advance on-cartridge clocks by `cycles` in 4 MHz.
+    fn tick(&mut self, _cycles: u8) {}
+    fn set_rtc_clock(&mut self, _clock: RtcClock) {}
+    /// MBC5 rumble motor state
+    fn rumble(&self) -> bool {
+        false
+    }
+    /// Battery backed state, external RAM followed by mapper state such as the RTC.
+    fn save_data(&self) -> Vec<u8>;
+    fn load_save_data(&mut self, data: &[u8]);
+}
+
+/// What drives a cartridge real-time clock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RtcClock {
+    /// Counts emulated cycles, time only passes while the game runs.
+    #[default]
+    Cycles,
+    /// Follows the host clock, including time spent with the emulator closed.
+    WallClock,
+}
+
+/// OAM DMA transfer of 160 bytes from `source` into OAM.
+#[derive(Debug, Clone, Copy)]
+struct OamDma {
+    source: u16,
+    /// bytes copied so far
+    progress: u16,
+    /// cycles left before the first byte, a restarted transfer keeps the old one running
+    /// until it starts
+    startup: u8,
+    /// cycles towards the next byte
+    cycles: u8,
+    /// byte on the bus, what conflicting CPU reads see
+    last_byte: u8,
+}
+
+impl OamDma {
+    fn new(source: u16) -> OamDma {
+        OamDma {
+            source,
+            progress: 0,
+            startup: OAM_DMA_STARTUP_CYCLE_IN_4MHZ,
+            cycles: 0,
+            last_byte: 0xff,
+        }
+    }
+    /// DMG has an external bus for ROM, cartridge RAM and WRAM and a separate one for VRAM.
+    fn uses_vram_bus(&self) -> bool {
+        (0x8000..0xa000).contains(&self.source)
+    }
+    /// Whether a CPU access to `addr` collides with the transfer.
+    fn conflicts(&self, addr: u16) -> bool {
+        match addr {
+            0xfe00..0xff00 => true,
+            0xff00..=0xffff => false,
+            0x8000..0xa000 => self.uses_vram_bus(),
+            _ => !self.uses_vram_bus(),
+        }
+    }
+}
+
+/// CGB HBlank DMA, a block of 16 bytes goes to VRAM at the start of every HBlank.
+#[derive(Debug, Clone, Copy)]
+struct HblankDma {
+    /// blocks left to copy
+    blocks: u8,
+}
+
 pub struct Memory {
     data: [u8; RAM_SIZE],
-    pub game_rom: Vec<u8>,
+    mapper: Box<dyn Mapper>,
+    timer: Timer,
+    joypad: Joypad,
+    apu: APU,
+    oam_dma: Option<OamDma>,
+    /// transfer waiting for its start up delay
+    pending_oam_dma: Option<OamDma>,
     // VRAM and OAM access
     pub vram_accessible: bool,
     pub oam_accessible: bool,
+    /// CGB mode, picked from the cartridge header
+    cgb: bool,
+    /// CGB VRAM bank 1, bank 0 lives in `data`
+    vram_bank1: Box<[u8; VRAM_BANK_SIZE]>,
+    /// WRAM, bank 0 at 0xc000 and bank 1, or on CGB the one SVBK picks, at 0xd000
+    wram_banks: Box<[[u8; WRAM_BANK_SIZE]; 8]>,
+    /// CGB double speed, the CPU, timer and OAM DMA run at 8 MHz
+    double_speed: bool,
+    bg_palette_ram: [u8; CGB_PALETTE_RAM_SIZE],
+    obj_palette_ram: [u8; CGB_PALETTE_RAM_SIZE],
+    hblank_dma: Option<HblankDma>,
+    /// what HDMA5 reads, blocks left minus one with bit 7 cleared while HBlank DMA runs
+    vram_dma_status: u8,
+    /// cycles the CPU still waits for a VRAM DMA transfer
+    vram_dma_stall: u16,
 }
 
 impl Memory {
     pub fn new(randomize: bool) -> Memory {
         let mut data = [0; RAM_SIZE];
+        let mut wram_banks = Box::new([[0; WRAM_BANK_SIZE]; 8]);
         if randomize {
-            rand::thread_rng().fill_bytes(&mut data[VRAM_START..IO_START]);
+            let mut rng = rand::thread_rng();
+            rng.fill_bytes(&mut data[VRAM_START..IO_START]);
+            for bank in wram_banks.iter_mut() {
+                rng.fill_bytes(bank);
+            }
         }
 
         // io map is not ramdom
@@ -33,27 +171,229 @@ impl Memory {
         data[WINDOW_Y_POSITION_RW as usize] = 0;
         data[WINDOW_X_POSITION_MINUS_7_RW as usize] = 0;
         data[DISABLE_BOOT_ROM] = 0;
-        // lower 4 bits 0 means no key is pressed
-        data[IO_START] = 0b11001111;
 
         Memory {
             data,
-            game_rom: Vec::<u8>::new(),
+            mapper: Box::new(mbc::RomOnly::new(Vec::new(), 0)),
+            timer: Timer::new(),
+            joypad: Joypad::new(),
+            apu: APU::new(),
+            oam_dma: None,
+            pending_oam_dma: None,
             vram_accessible: true,
             oam_accessible: true,
+            cgb: false,
+            vram_bank1: Box::new([0; VRAM_BANK_SIZE]),
+            wram_banks,
+            double_speed: false,
+            bg_palette_ram: [0xff; CGB_PALETTE_RAM_SIZE],
+            obj_palette_ram: [0xff; CGB_PALETTE_RAM_SIZE],
+            hblank_dma: None,
+            vram_dma_status: 0xff,
+            vram_dma_stall: 0,
+        }
+    }
+    /// Switch to CGB mode with the I/O state the CGB boot ROM leaves, there is no CGB boot
+    /// ROM to run.
+    pub fn enable_cgb(&mut self) {
+        self.cgb = true;
+        self.data[DISABLE_BOOT_ROM] = 1;
+        self.data[LCD_CONTROL_RW as usize] = 0x91;
+        self.data[WRAM_BANK_RW as usize] = 1;
+        self.apu.set(NR52_RW, 0x80);
+        self.apu.set(NR51_RW, 0xf3);
+        self.apu.set(NR50_RW, 0x77);
+    }
+    pub fn cgb_mode(&self) -> bool {
+        self.cgb
+    }
+    pub fn double_speed(&self) -> bool {
+        self.double_speed
+    }
+    /// STOP switches speed when KEY1 armed it, returns whether it did.
+    pub fn switch_speed(&mut self) -> bool {
+        if !self.cgb || self.data[SPEED_SWITCH_RW as usize] & 1 == 0 {
+            return false;
+        }
+        self.data[SPEED_SWITCH_RW as usize] = 0;
+        self.double_speed = !self.double_speed;
+        true
+    }
+    /// `cycles` of the CPU clock as cycles of the 4 MHz clock the PPU, APU and cartridge
+    /// keep in double speed.
+    pub fn normal_speed_cycles(&self, cycles: u8) -> u8 {
+        if self.double_speed {
+            cycles / 2
+        } else {
+            cycles
+        }
+    }
+    pub fn load_cartridge(&mut self, game_rom: Vec<u8>, cartridge: &Cartridge) {
+        self.mapper = mbc::from_cartridge(game_rom, cartridge);
+    }
+    pub fn mapper(&self) -> &dyn Mapper {
+        self.mapper.as_ref()
+    }
+    pub fn mapper_mut(&mut self) -> &mut dyn Mapper {
+        self.mapper.as_mut()
+    }
+    pub fn apu(&self) -> &APU {
+        &self.apu
+    }
+    pub fn apu_mut(&mut self) -> &mut APU {
+        &mut self.apu
+    }
+    pub fn tick(&mut self, cycles: u8) {
+        let normal_cycles = self.normal_speed_cycles(cycles);
+        self.mapper.tick(normal_cycles);
+        let div = self.timer.get(DIVIDER_RW);
+        if self.timer.tick(cycles) {
+            self.set_bit(INTERRUPT_FLAG, INTR_TIMER_BIT, true);
+        }
+        self.check_frame_sequencer(div);
+        self.apu.tick(normal_cycles);
+        self.tick_oam_dma(cycles);
+    }
+    /// The APU frame sequencer steps on falling edges of a DIV bit, DIV resets included. Double
+    /// speed uses the next bit to keep 512 Hz.
+    fn check_frame_sequencer(&mut self, div_before: u8) {
+        let div = self.timer.get(DIVIDER_RW);
+        let bit = if self.double_speed {
+            DIV_FRAME_SEQUENCER_BIT << 1
+        } else {
+            DIV_FRAME_SEQUENCER_BIT
+        };
+        if div_before & bit != 0 && div & bit == 0 {
+            self.apu.step_frame_sequencer();
         }
     }
+    fn tick_oam_dma(&mut self, cycles: u8) {
+        for _ in 0..cycles {
+            if let Some(pending) = &mut self.pending_oam_dma {
+                pending.startup -= 1;
+                if pending.startup == 0 {
+                    self.oam_dma = self.pending_oam_dma.take();
+                    continue;
+                }
+            }
+            let Some(mut dma) = self.oam_dma else {
+                continue;
+            };
+            dma.cycles += 1;
+            if dma.cycles == OAM_DMA_BYTE_CYCLE_IN_4MHZ {
+                dma.cycles = 0;
+                let val = self.dma_read(dma.source + dma.progress);
+                self.data[OAM_RAM_START + dma.progress as usize] = val;
+                dma.last_byte = val;
+                dma.progress += 1;
+            }
+            self.oam_dma = if dma.progress < OAM_RAM_SIZE as u16 {
+                Some(dma)
+            } else {
+                None
+            };
+        }
+    }
+    /// DMA reads 0xe000 and up from WRAM like echo RAM.
+    fn dma_read(&self, addr: u16) -> u8 {
+        match addr {
+            0x0000..0x8000 => self.mapper.read_rom(addr),
+            0x8000..0xa000 => self.cpu_vram(addr),
+            0xa000..0xc000 => self.mapper.read_ram(addr - EXTERNAL_RAM_START as u16),
+            _ => self.wram(addr),
+        }
+    }
+    /// HDMA5 write, starts a general purpose or HBlank DMA or cancels a running HBlank DMA.
+    fn start_vram_dma(&mut self, val: u8) {
+        let blocks = (val & 0x7f) + 1;
+        if self.hblank_dma.is_some() && val & 0x80 == 0 {
+            // the blocks left stay readable
+            self.hblank_dma = None;
+            self.vram_dma_status |= 0x80;
+        } else if val & 0x80 != 0 {
+            self.hblank_dma = Some(HblankDma { blocks });
+            self.vram_dma_status = blocks - 1;
+        } else {
+            // general purpose DMA copies everything right away
+            for _ in 0..blocks {
+                self.copy_vram_dma_block();
+            }
+            self.vram_dma_status = 0xff;
+        }
+    }
+    /// Copy 16 bytes from HDMA1-2 to VRAM at HDMA3-4 and move both on, the destination wraps
+    /// inside VRAM.
+    fn copy_vram_dma_block(&mut self) {
+        let source =
+            u16::from_be_bytes([self.data[HDMA1_W as usize], self.data[HDMA2_W as usize]]) & 0xfff0;
+        let dest =
+            u16::from_be_bytes([self.data[HDMA3_W as usize], self.data[HDMA4_W as usize]]) & 0x1ff0;
+        for i in 0..VRAM_DMA_BLOCK_SIZE {
+            let val = self.dma_read(source.wrapping_add(i));
+            self.write_cpu_vram(VRAM_START as u16 + dest + i, val);
+        }
+        let [source_high, source_low] = source.wrapping_add(VRAM_DMA_BLOCK_SIZE).to_be_bytes();
+        let [dest_high, dest_low] = ((dest + VRAM_DMA_BLOCK_SIZE) & 0x1ff0).to_be_bytes();
+        self.data[HDMA1_W as usize] = source_high;
+        self.data[HDMA2_W as usize] = source_low;
+        self.data[HDMA3_W as usize] = dest_high;
+        self.data[HDMA4_W as usize] = dest_low;
+        // the transfer takes as long in double speed, twice the CPU cycles
+        self.vram_dma_stall += VRAM_DMA_BLOCK_CYCLE_IN_4MHZ << self.double_speed as u8;
+    }
+    /// Start of HBlank on a visible line, HBlank DMA copies its next block.
+    pub fn hblank(&mut self) {
+        let Some(dma) = &mut self.hblank_dma else {
+            return;
+        };
+        dma.blocks -= 1;
+        let blocks = dma.blocks;
+        self.copy_vram_dma_block();
+        if blocks == 0 {
+            self.hblank_dma = None;
+            self.vram_dma_status = 0xff;
+        } else {
+            self.vram_dma_status = blocks - 1;
+        }
+    }
+    /// The CPU waits 4 cycles when a VRAM DMA transfer holds the bus.
+    pub fn stall_for_vram_dma(&mut self) -> bool {
+        if self.vram_dma_stall == 0 {
+            return false;
+        }
+        self.vram_dma_stall = self.vram_dma_stall.saturating_sub(4);
+        true
+    }
+    pub fn oam_dma_active(&self) -> bool {
+        self.oam_dma.is_some()
+    }
+    /// CPU view of the bus. During OAM DMA only HRAM and I/O are reliable, OAM reads 0xff and
+    /// the bus used by the transfer returns the byte being copied.
     pub fn get(&self, addr: u16) -> u8 {
+        if let Some(dma) = &self.oam_dma {
+            if dma.conflicts(addr) {
+                return match addr {
+                    0xfe00..0xff00 => 0xff,
+                    _ => dma.last_byte,
+                };
+            }
+        }
+        self.read(addr)
+    }
+    fn read(&self, addr: u16) -> u8 {
         if self.data[DISABLE_BOOT_ROM] > 0 || addr > 0xff {
             match addr {
-                0..0x8000 => self.game_rom[addr as usize],
+                0..0x8000 => self.mapper.read_rom(addr),
                 0x8000..0xa000 => {
                     if self.vram_accessible {
-                        self.data[addr as usize]
+                        self.cpu_vram(addr)
                     } else {
                         0xff
                     }
                 }
+                0xa000..0xc000 => self.mapper.read_ram(addr - EXTERNAL_RAM_START as u16),
+                // and its echo up to 0xfdff
+                0xc000..0xfe00 => self.wram(addr),
                 0xfe00..0xfea0 => {
                     if self.oam_accessible {
                         self.data[addr as usize]
@@ -61,36 +401,512 @@ impl Memory {
                         0xff
                     }
                 }
+                // unusable, DMG reads 0 unless OAM is blocked
+                0xfea0..0xff00 => {
+                    if self.oam_accessible {
+                        0x00
+                    } else {
+                        0xff
+                    }
+                }
+                0xff00..0xff80 => self.get_io(addr),
                 _ => self.data[addr as usize],
             }
         } else {
             BOOT_ROM_BYTES[addr as usize]
         }
     }
+    fn get_io(&self, addr: u16) -> u8 {
+        if self.cgb {
+            if let Some(val) = self.get_cgb_io(addr) {
+                return val;
+            }
+        }
+        let val = match addr {
+            JOYPAD_RW => self.joypad.get(),
+            DIVIDER_RW..=TIMER_CONTROL_RW => self.timer.get(addr),
+            NR10_RW..=NR52_RW | WAVE_RAM_START..=WAVE_RAM_END => self.apu.get(addr),
+            _ => self.data[addr as usize],
+        };
+        val | IO_READ_MASKS[addr as usize - IO_START]
+    }
+    /// CGB registers, None for the ones shared with DMG.
+    fn get_cgb_io(&self, addr: u16) -> Option<u8> {
+        let val = match addr {
+            HDMA1_W..=HDMA4_W => 0xff,
+            HDMA5_RW => self.vram_dma_status,
+            SPEED_SWITCH_RW => 0x7e | ((self.double_speed as u8) << 7) | self.data[addr as usize],
+            VRAM_BANK_RW => 0xfe | self.data[addr as usize],
+            WRAM_BANK_RW => 0xf8 | self.data[addr as usize],
+            CGB_BG_PALETTE_INDEX_RW | CGB_OBJ_PALETTE_INDEX_RW => 0x40 | self.data[addr as usize],
+            CGB_BG_PALETTE_DATA_RW | CGB_OBJ_PALETTE_DATA_RW => {
+                // palette RAM is locked while the PPU draws
+                if !self.vram_accessible {
+                    return Some(0xff);
+                }
+                let (index, ram) = self.palette_ram(addr - 1);
+                ram[index]
+            }
+            _ => return None,
+        };
+        Some(val)
+    }
+    /// Palette RAM of an index register and the entry it selects.
+    fn palette_ram(&self, index_addr: u16) -> (usize, &[u8; CGB_PALETTE_RAM_SIZE]) {
+        let index = (self.data[index_addr as usize] & 0x3f) as usize;
+        if index_addr == CGB_BG_PALETTE_INDEX_RW {
+            (index, &self.bg_palette_ram)
+        } else {
+            (index, &self.obj_palette_ram)
+        }
+    }
+    /// BCPD or OCPD write, moving on to the next entry when the index register asks to.
+    fn set_palette_data(&mut self, index_addr: u16, val: u8) {
+        let index = self.data[index_addr as usize];
+        if self.vram_accessible {
+            let ram = if index_addr == CGB_BG_PALETTE_INDEX_RW {
+                &mut self.bg_palette_ram
+            } else {
+                &mut self.obj_palette_ram
+            };
+            ram[(index & 0x3f) as usize] = val;
+        }
+        if index & 0x80 != 0 {
+            self.data[index_addr as usize] = 0x80 | (index.wrapping_add(1) & 0x3f);
+        }
+    }
+    /// Bank and offset of a WRAM address, 0xe000 and up mirror 0xc000. SVBK 0 maps bank 1.
+    fn wram_location(&self, addr: u16) -> (usize, usize) {
+        let offset = (addr as usize - RAM_START) % (2 * WRAM_BANK_SIZE);
+        if offset < WRAM_BANK_SIZE {
+            (0, offset)
+        } else if self.cgb {
+            let bank = (self.data[WRAM_BANK_RW as usize] & 0x07).max(1);
+            (bank as usize, offset - WRAM_BANK_SIZE)
+        } else {
+            (1, offset - WRAM_BANK_SIZE)
+        }
+    }
+    fn wram(&self, addr: u16) -> u8 {
+        let (bank, offset) = self.wram_location(addr);
+        self.wram_banks[bank][offset]
+    }
+    fn write_wram(&mut self, addr: u16, val: u8) {
+        let (bank, offset) = self.wram_location(addr);
+        self.wram_banks[bank][offset] = val;
+    }
+    fn cpu_vram_bank(&self) -> u8 {
+        if self.cgb {
+            self.data[VRAM_BANK_RW as usize] & 1
+        } else {
+            0
+        }
+    }
+    /// VRAM through the bank selected by VBK.
+    fn cpu_vram(&self, addr: u16) -> u8 {
+        self.vram_bank(self.cpu_vram_bank(), addr)
+    }
+    fn write_cpu_vram(&mut self, addr: u16, val: u8) {
+        if self.cpu_vram_bank() == 1 {
+            self.vram_bank1[addr as usize - VRAM_START] = val;
+        } else {
+            self.data[addr as usize] = val;
+        }
+    }
+    /// PPU side VRAM read, not blocked by the PPU mode or DMA.
+    pub fn vram(&self, addr: u16) -> u8 {
+        self.data[addr as usize]
+    }
+    /// PPU side read of a CGB VRAM bank.
+    pub fn vram_bank(&self, bank: u8, addr: u16) -> u8 {
+        if bank == 1 {
+            self.vram_bank1[addr as usize - VRAM_START]
+        } else {
+            self.data[addr as usize]
+        }
+    }
+    pub fn bg_palette_ram(&self) -> &[u8; CGB_PALETTE_RAM_SIZE] {
+        &self.bg_palette_ram
+    }
+    pub fn obj_palette_ram(&self) -> &[u8; CGB_PALETTE_RAM_SIZE] {
+        &self.obj_palette_ram
+    }
     pub fn get_chunck(&self, addr: u16, size: usize) -> &[u8] {
         let addr = addr as usize;
         &self.data[addr..addr + size]
     }
     pub fn set(&mut self, addr: u16, val: u8) {
+        if self.oam_dma.is_some_and(|dma| dma.conflicts(addr)) {
+            return;
+        }
         match addr {
-            // joy pad is read only from cpu
-            0xff00 => {
-                self.data[addr as usize] =
-                    (val & 0b00110000) + 0b11000000 + (self.data[0xff00] & 0x0f);
-            }
+            0..0x8000 => self.mapper.write_rom(addr, val),
+            0x8000..0xa000 => self.write_cpu_vram(addr, val),
+            0xa000..0xc000 => self.mapper.write_ram(addr - EXTERNAL_RAM_START as u16, val),
+            0xc000..0xfe00 => self.write_wram(addr, val),
+            0xfea0..0xff00 => {}
+            0xff00..0xff80 => self.set_io(addr, val),
             _ => {
                 self.data[addr as usize] = val;
             }
         }
     }
+    /// CPU writes to I/O registers, read only bits keep their value.
+    fn set_io(&mut self, addr: u16, val: u8) {
+        match addr {
+            JOYPAD_RW => {
+                if self.joypad.set(val) {
+                    self.set_bit(INTERRUPT_FLAG, INTR_HIGHTOLOW_BIT, true);
+                }
+                return;
+            }
+            DIVIDER_RW..=TIMER_CONTROL_RW => {
+                let div = self.timer.get(DIVIDER_RW);
+                self.timer.set(addr, val);
+                self.check_frame_sequencer(div);
+                return;
+            }
+            NR10_RW..=NR52_RW | WAVE_RAM_START..=WAVE_RAM_END => {
+                self.apu.set(addr, val);
+                return;
+            }
+            CGB_BG_PALETTE_DATA_RW | CGB_OBJ_PALETTE_DATA_RW if self.cgb => {
+                self.set_palette_data(addr - 1, val);
+                return;
+            }
+            HDMA5_RW if self.cgb => {
+                self.start_vram_dma(val);
+                return;
+            }
+            _ => {}
+        }
+        let old = self.data[addr as usize];
+        self.data[addr as usize] = match addr {
+            // mode and coincidence flag
+            STATUS_ADDR_RW => (val & 0b0111_1000) | (old & 0b1000_0111),
+            Y_COORDINATE_R => old,
+            OAM_DMA => {
+                self.pending_oam_dma = Some(OamDma::new((val as u16) << 8));
+                val
+            }
+            SPEED_SWITCH_RW | VRAM_BANK_RW if self.cgb => val & 1,
+            WRAM_BANK_RW if self.cgb => val & 0x07,
+            CGB_BG_PALETTE_INDEX_RW | CGB_OBJ_PALETTE_INDEX_RW if self.cgb => val & 0xbf,
+            // the boot rom can't be mapped back
+            0xff50 => old | val,
+            _ => val,
+        };
+    }
+    pub fn set_button(&mut self, button: Button, pressed: bool) {
+        if self.joypad.set_button(button, pressed) {
+            self.set_bit(INTERRUPT_FLAG, INTR_HIGHTOLOW_BIT, true);
+        }
+    }
+    /// Hardware side write that bypasses the CPU view of read only registers.
+    pub fn set_register(&mut self, addr: u16, val: u8) {
+        self.data[addr as usize] = val;
+    }
     pub fn get_bit(&self, addr: u16, bit: u8) -> bool {
         (self.get(addr) & (1 << bit)) != 0
     }
     pub fn set_bit(&mut self, addr: u16, bit: u8, one: bool) {
         if one {
-            self.data[addr as usize] = self.data[addr as usize] | (1 << bit);
+            self.data[addr as usize] |= 1 << bit;
         } else {
-            self.data[addr as usize] = self.data[addr as usize] & (0xff ^ (1 << bit));
+            self.data[addr as usize] &= 0xff ^ (1 << bit);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::ppu::PPU;
+
+    #[test]
+    fn io_reads_set_unused_bits() {
+        let mut mem = Memory::new(false);
+        // register, what it reads after writing 0
+        let registers = [
+            (0xff02, 0x7e),
+            (TIMER_CONTROL_RW, 0xf8),
+            (INTERRUPT_FLAG, 0xe0),
+            (NR10_RW, 0x80),
+            (NR11_RW, 0x3f),
+            (NR13_RW, 0xff),
+            (NR14_RW, 0xbf),
+            (NR30_RW, 0x7f),
+            (NR32_RW, 0x9f),
+            (NR44_RW, 0xbf),
+            (NR52_RW, 0x70),
+            (LCD_CONTROL_RW, 0x00),
+            (WINDOW_X_POSITION_MINUS_7_RW, 0x00),
+        ];
+        for (addr, expected) in registers {
+            mem.set(addr, 0x00);
+            assert_eq!(mem.get(addr), expected, "{:04x}", addr);
+        }
+        // unmapped I/O reads open bus whatever was written, CGB registers included on DMG
+        let unmapped = [0xff03]
+            .into_iter()
+            .chain(0xff08..=0xff0e)
+            .chain([0xff15, 0xff1f])
+            .chain(0xff27..=0xff2f)
+            .chain(0xff4c..=0xff4f)
+            .chain(0xff51..=0xff7f);
+        for addr in unmapped {
+            mem.set(addr, 0x00);
+            assert_eq!(mem.get(addr), 0xff, "{:04x}", addr);
         }
     }
+
+    #[test]
+    fn echo_ram_mirrors_wram() {
+        let mut mem = Memory::new(false);
+        mem.set(0xc123, 0x42);
+        assert_eq!(mem.get(0xe123), 0x42);
+        mem.set(0xfdff, 0x24);
+        assert_eq!(mem.get(0xddff), 0x24);
+        // the mirror stops before OAM
+        mem.set(0xde00, 0x99);
+        assert_ne!(mem.get(0xfe00), 0x99);
+    }
+
+    #[test]
+    fn unusable_area_reads_zero() {
+        let mut mem = Memory::new(false);
+        mem.set(0xfea0, 0x12);
+        mem.set(0xfeff, 0x34);
+        assert_eq!(mem.get(0xfea0), 0x00);
+        assert_eq!(mem.get(0xfeff), 0x00);
+        // the PPU holding OAM blocks it too
+        mem.oam_accessible = false;
+        assert_eq!(mem.get(0xfea0), 0xff);
+    }
+
+    #[test]
+    fn oam_dma_blocks_the_bus() {
+        let mut mem = Memory::new(false);
+        for i in 0..OAM_RAM_SIZE as u16 {
+            mem.set(0xc000 + i, i as u8 + 1);
+        }
+        mem.set(0x8000, 0x55);
+        mem.set(0xff80, 0x77);
+        mem.set(OAM_DMA, 0xc0);
+        // nothing happens during the startup delay
+        assert_eq!(mem.get(0xc010), 0x11);
+        mem.tick(OAM_DMA_STARTUP_CYCLE_IN_4MHZ);
+        assert!(mem.oam_dma_active());
+        // the external bus reads what DMA is moving, OAM reads 0xff
+        assert_eq!(mem.get(0xc010), 0xff);
+        assert_eq!(mem.get(0xfe00), 0xff);
+        mem.tick(OAM_DMA_BYTE_CYCLE_IN_4MHZ);
+        assert_eq!(mem.get(0xc050), 0x01);
+        assert_eq!(mem.get(0x0150), 0x01);
+        // HRAM and the VRAM bus are free, conflicting writes are dropped
+        assert_eq!(mem.get(0xff80), 0x77);
+        assert_eq!(mem.get(0x8000), 0x55);
+        mem.set(0xc0a0, 0x99);
+        mem.set(0xff81, 0x88);
+        assert_eq!(mem.get(0xff81), 0x88);
+        for _ in 1..OAM_RAM_SIZE - 1 {
+            mem.tick(OAM_DMA_BYTE_CYCLE_IN_4MHZ);
+        }
+        assert!(mem.oam_dma_active());
+        assert_eq!(mem.get(0xc000), 0x9f);
+        // 4 + 160 * 4 cycles in total
+        mem.tick(OAM_DMA_BYTE_CYCLE_IN_4MHZ);
+        assert!(!mem.oam_dma_active());
+        assert_eq!(mem.get(0xc0a0), 0x00);
+        for i in 0..OAM_RAM_SIZE as u16 {
+            assert_eq!(mem.get(0xfe00 + i), i as u8 + 1);
+        }
+    }
+
+    /// Power the APU on and start channel 2 with one length tick left.
+    fn start_short_channel(mem: &mut Memory) {
+        mem.set(NR52_RW, 0x80);
+        mem.set(NR22_RW, 0xf0);
+        mem.set(NR21_RW, 0x3f);
+        mem.set(NR24_RW, 0xc0);
+        assert_eq!(mem.get(NR52_RW) & 0x02, 0x02);
+    }
+
+    #[test]
+    fn frame_sequencer_steps_on_div_bit_falling_edges() {
+        let mut mem = Memory::new(false);
+        mem.set(DIVIDER_RW, 0);
+        start_short_channel(&mut mem);
+        // DIV bit 4 falls every 8192 cycles
+        for _ in 0..8188 / 4 {
+            mem.tick(4);
+        }
+        assert_eq!(mem.get(NR52_RW) & 0x02, 0x02);
+        mem.tick(4);
+        assert_eq!(mem.get(NR52_RW) & 0x02, 0x00);
+
+        // resetting DIV with the bit set is a falling edge too, on the next length step
+        for _ in 0..8192 / 4 {
+            mem.tick(4);
+        }
+        start_short_channel(&mut mem);
+        for _ in 0..4096 / 4 {
+            mem.tick(4);
+        }
+        assert_eq!(mem.get(NR52_RW) & 0x02, 0x02);
+        mem.set(DIVIDER_RW, 0);
+        assert_eq!(mem.get(NR52_RW) & 0x02, 0x00);
+    }
+
+    fn cgb_memory() -> Memory {
+        let mut mem = Memory::new(false);
+        mem.enable_cgb();
+        mem
+    }
+
+    #[test]
+    fn svbk_switches_wram_banks() {
+        let mut mem = cgb_memory();
+        for bank in 1..8 {
+            mem.set(WRAM_BANK_RW, bank);
+            mem.set(0xd000, 0x10 + bank);
+        }
+        mem.set(0xc000, 0x42);
+        // bank 0 maps bank 1, only 3 bits are kept
+        mem.set(WRAM_BANK_RW, 0);
+        assert_eq!(mem.get(WRAM_BANK_RW), 0xf8);
+        assert_eq!(mem.get(0xd000), 0x11);
+        mem.set(WRAM_BANK_RW, 0xfd);
+        assert_eq!(mem.get(WRAM_BANK_RW), 0xfd);
+        assert_eq!(mem.get(0xd000), 0x15);
+        // echo RAM follows the switch, 0xc000 doesn't
+        assert_eq!(mem.get(0xf000), 0x15);
+        mem.set(0xf001, 0x99);
+        assert_eq!(mem.get(0xd001), 0x99);
+        assert_eq!(mem.get(0xc000), 0x42);
+        mem.set(WRAM_BANK_RW, 7);
+        assert_eq!(mem.get(0xd000), 0x17);
+        assert_eq!(mem.get(0xd001), 0x00);
+    }
+
+    #[test]
+    fn dmg_ignores_svbk() {
+        let mut mem = Memory::new(false);
+        mem.set(0xd000, 0x11);
+        mem.set(WRAM_BANK_RW, 2);
+        assert_eq!(mem.get(WRAM_BANK_RW), 0xff);
+        assert_eq!(mem.get(0xd000), 0x11);
+    }
+
+    #[test]
+    fn vbk_switches_vram_banks() {
+        let mut mem = cgb_memory();
+        mem.set(0x8000, 0x12);
+        mem.set(VRAM_BANK_RW, 0xff);
+        assert_eq!(mem.get(VRAM_BANK_RW), 0xff);
+        assert_eq!(mem.get(0x8000), 0x00);
+        mem.set(0x8000, 0x34);
+        mem.set(VRAM_BANK_RW, 0x00);
+        assert_eq!(mem.get(VRAM_BANK_RW), 0xfe);
+        assert_eq!(mem.get(0x8000), 0x12);
+        // the PPU reads either bank whatever VBK says
+        assert_eq!(mem.vram_bank(1, 0x8000), 0x34);
+    }
+
+    #[test]
+    fn palette_index_auto_increment() {
+        let mut mem = cgb_memory();
+        // entry 0x3f with auto-increment wraps to 0
+        mem.set(CGB_BG_PALETTE_INDEX_RW, 0xbf);
+        mem.set(CGB_BG_PALETTE_DATA_RW, 0x12);
+        assert_eq!(mem.get(CGB_BG_PALETTE_INDEX_RW), 0xc0);
+        mem.set(CGB_BG_PALETTE_DATA_RW, 0x34);
+        mem.set(CGB_BG_PALETTE_DATA_RW, 0x56);
+        assert_eq!(mem.get(CGB_BG_PALETTE_INDEX_RW), 0xc2);
+        assert_eq!(mem.bg_palette_ram()[0x3f], 0x12);
+        assert_eq!(mem.bg_palette_ram()[..2], [0x34, 0x56]);
+        // reads don't move the index, writes without bit 7 don't either
+        mem.set(CGB_BG_PALETTE_INDEX_RW, 0x01);
+        assert_eq!(mem.get(CGB_BG_PALETTE_DATA_RW), 0x56);
+        assert_eq!(mem.get(CGB_BG_PALETTE_DATA_RW), 0x56);
+        mem.set(CGB_BG_PALETTE_DATA_RW, 0x78);
+        assert_eq!(mem.get(CGB_BG_PALETTE_INDEX_RW), 0x41);
+        assert_eq!(mem.get(CGB_BG_PALETTE_DATA_RW), 0x78);
+        // locked while the PPU draws, the index still moves on
+        mem.set(CGB_OBJ_PALETTE_INDEX_RW, 0x80);
+        mem.vram_accessible = false;
+        mem.set(CGB_OBJ_PALETTE_DATA_RW, 0x9a);
+        assert_eq!(mem.get(CGB_OBJ_PALETTE_DATA_RW), 0xff);
+        mem.vram_accessible = true;
+        assert_eq!(mem.get(CGB_OBJ_PALETTE_INDEX_RW), 0xc1);
+        assert_eq!(mem.obj_palette_ram()[0], 0xff);
+    }
+
+    /// Numbered bytes at 0xc000 to be copied to VRAM at 0x8000.
+    fn vram_dma_setup() -> Memory {
+        let mut mem = cgb_memory();
+        for i in 0..0x80 {
+            mem.set(0xc000 + i, i as u8 + 1);
+        }
+        mem.set(HDMA1_W, 0xc0);
+        mem.set(HDMA2_W, 0x00);
+        mem.set(HDMA3_W, 0x00);
+        mem.set(HDMA4_W, 0x00);
+        mem
+    }
+
+    /// VRAM bytes copied so far, counted up to the first one still 0.
+    fn vram_copied(mem: &Memory) -> u16 {
+        (0..0x100)
+            .take_while(|&i| mem.vram(0x8000 + i) != 0)
+            .count() as u16
+    }
+
+    #[test]
+    fn general_purpose_dma_copies_everything_and_stalls() {
+        let mut mem = vram_dma_setup();
+        mem.set(HDMA5_RW, 0x02);
+        assert_eq!(vram_copied(&mem), 3 * VRAM_DMA_BLOCK_SIZE);
+        assert_eq!(mem.get(HDMA5_RW), 0xff);
+        // the source and destination registers moved on, HDMA1-4 read 0xff
+        assert_eq!(mem.data[HDMA2_W as usize], 0x30);
+        assert_eq!(mem.data[HDMA4_W as usize], 0x30);
+        assert_eq!(mem.get(HDMA2_W), 0xff);
+        // 32 cycles per block
+        let stalls = (0..).take_while(|_| mem.stall_for_vram_dma()).count();
+        assert_eq!(stalls, 3 * 32 / 4);
+    }
+
+    #[test]
+    fn hblank_dma_copies_a_block_per_hblank() {
+        let mut mem = vram_dma_setup();
+        mem.set(HDMA5_RW, 0x81);
+        assert_eq!(vram_copied(&mem), 0);
+        // bit 7 cleared while active, blocks left minus one
+        assert_eq!(mem.get(HDMA5_RW), 0x01);
+        let mut ppu = PPU::new();
+        for _ in 0..SCANLINE_CYCLE_IN_4MHZ {
+            ppu.tick(&mut mem, 1);
+        }
+        assert_eq!(vram_copied(&mem), VRAM_DMA_BLOCK_SIZE);
+        assert_eq!(mem.get(HDMA5_RW), 0x00);
+        for _ in 0..2 * SCANLINE_CYCLE_IN_4MHZ {
+            ppu.tick(&mut mem, 1);
+        }
+        assert_eq!(vram_copied(&mem), 2 * VRAM_DMA_BLOCK_SIZE);
+        assert_eq!(mem.get(HDMA5_RW), 0xff);
+    }
+
+    #[test]
+    fn hblank_dma_cancel() {
+        let mut mem = vram_dma_setup();
+        mem.set(HDMA5_RW, 0x83);
+        mem.hblank();
+        assert_eq!(mem.get(HDMA5_RW), 0x02);
+        // writing bit 7 cleared stops it, the blocks left stay readable with bit 7 set
+        mem.set(HDMA5_RW, 0x00);
+        assert_eq!(mem.get(HDMA5_RW), 0x82);
+        mem.hblank();
+        assert_eq!(vram_copied(&mem), VRAM_DMA_BLOCK_SIZE);
+    }
 }
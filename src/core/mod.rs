@@ -1,15 +1,39 @@
+pub mod apu;
+pub mod audio_sink;
+pub mod cartridge;
 pub mod constants;
 pub mod cpu;
+pub mod instruction;
+pub mod joypad;
+pub mod mbc;
 pub mod memory;
 pub mod ppu;
+pub mod timer;
+use constants::{FRAME_CYCLE_IN_4MHZ, LCD_CONTROL_RW};
 use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
 
-use constants::DISABLE_BOOT_ROM;
+/// how often battery backed RAM is written out, 5 seconds
+const AUTOSAVE_CYCLE_IN_4MHZ: u32 = 5 * 4_194_304;
 
 pub struct Core {
     pub cpu: cpu::CPU,
     mem: memory::Memory,
     ppu: ppu::PPU,
+    cartridge: Option<cartridge::Cartridge>,
+    /// .sav next to the rom, only for carts with a battery
+    save_path: Option<PathBuf>,
+    /// what is on disk, to skip writing unchanged saves
+    saved_data: Vec<u8>,
+    cycles_since_save: u32,
+    /// time since the last frame, frames keep coming at the same pace while the LCD is off
+    cycles_since_frame: u32,
+    /// clock for cartridge RTCs, applied before the .sav is read
+    rtc_clock: memory::RtcClock,
+    audio_sinks: Vec<Box<dyn audio_sink::AudioSink>>,
+    /// samples for `drain_audio_samples`, a second at most
+    audio_samples: Vec<f32>,
 }
 
 impl Core {
@@ -18,26 +42,156 @@ impl Core {
             cpu: cpu::CPU::new(),
             mem: memory::Memory::new(randomize),
             ppu: ppu::PPU::new(),
+            cartridge: None,
+            save_path: None,
+            saved_data: Vec::new(),
+            cycles_since_save: 0,
+            cycles_since_frame: 0,
+            rtc_clock: memory::RtcClock::default(),
+            audio_sinks: Vec::new(),
+            audio_samples: Vec::new(),
         }
     }
-    pub fn load_game_rom(&mut self, game_rom_path: &str) {
-        let game_rom = fs::read(game_rom_path).expect("game rom path");
-        self.mem.game_rom = game_rom;
+    /// Load a cartridge image, refusing it when it can't be read or its header can't be parsed.
+    pub fn load_game_rom(
+        &mut self,
+        game_rom_path: &str,
+    ) -> Result<&cartridge::Cartridge, cartridge::CartridgeError> {
+        let game_rom = fs::read(game_rom_path)?;
+        let cartridge = cartridge::Cartridge::parse(&game_rom)?;
+        self.mem.load_cartridge(game_rom, &cartridge);
+        // the wall clock catches up with the time since the save while loading it
+        self.mem.mapper_mut().set_rtc_clock(self.rtc_clock);
+        if cartridge.cgb != cartridge::CgbSupport::None {
+            self.mem.enable_cgb();
+            self.cpu.set_cgb_post_boot_state();
+        }
+        self.save_path = None;
+        if cartridge.cartridge_type.battery {
+            let save_path = Path::new(game_rom_path).with_extension("sav");
+            if let Ok(data) = fs::read(&save_path) {
+                self.mem.mapper_mut().load_save_data(&data);
+            }
+            self.saved_data = self.mem.mapper().save_data();
+            self.save_path = Some(save_path);
+        }
+        Ok(self.cartridge.insert(cartridge))
+    }
+    /// Write battery backed RAM and RTC to the .sav file if they changed since the last save.
+    pub fn save(&mut self) -> io::Result<()> {
+        let Some(save_path) = &self.save_path else {
+            return Ok(());
+        };
+        let data = self.mem.mapper().save_data();
+        if data.is_empty() || data == self.saved_data {
+            return Ok(());
+        }
+        fs::write(save_path, &data)?;
+        self.saved_data = data;
+        Ok(())
+    }
+    pub fn set_button(&mut self, button: joypad::Button, pressed: bool) {
+        self.mem.set_button(button, pressed);
+    }
+    /// Clock for the RTC of this and later cartridges. Set it before `load_game_rom` for
+    /// `RtcClock::WallClock` to count the time since the .sav was written.
+    pub fn set_rtc_clock(&mut self, clock: memory::RtcClock) {
+        self.rtc_clock = clock;
+        self.mem.mapper_mut().set_rtc_clock(clock);
+    }
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.mem.apu_mut().set_sample_rate(sample_rate);
+    }
+    pub fn sample_rate(&self) -> u32 {
+        self.mem.apu().sample_rate()
+    }
+    /// Mute or unmute one of the four sound channels, 0 to 3.
+    pub fn set_audio_channel_enabled(&mut self, channel: usize, enabled: bool) {
+        self.mem.apu_mut().set_channel_enabled(channel, enabled);
+    }
+    pub fn audio_channel_enabled(&self, channel: usize) -> bool {
+        self.mem.apu().channel_enabled(channel)
+    }
+    /// Interleaved stereo samples generated since the last call.
+    pub fn drain_audio_samples(&mut self) -> Vec<f32> {
+        self.flush_audio();
+        std::mem::take(&mut self.audio_samples)
+    }
+    /// Send everything the APU outputs from now on to `sink` as well, after each frame.
+    pub fn add_audio_sink(&mut self, sink: Box<dyn audio_sink::AudioSink>) {
+        self.audio_sinks.push(sink);
     }
+    /// Hand the last samples to the sinks and close them.
+    pub fn finish_audio(&mut self) -> io::Result<()> {
+        self.flush_audio();
+        for mut sink in self.audio_sinks.drain(..) {
+            sink.finish()?;
+        }
+        Ok(())
+    }
+    fn flush_audio(&mut self) {
+        let samples = self.mem.apu_mut().drain_samples();
+        if samples.is_empty() {
+            return;
+        }
+        let sample_rate = self.mem.apu().sample_rate();
+        self.audio_sinks
+            .retain_mut(|sink| match sink.write(&samples, sample_rate) {
+                Ok(()) => true,
+                Err(err) => {
+                    eprintln!("audio sink failed: {}", err);
+                    false
+                }
+            });
+        if self.audio_samples.len() < 2 * sample_rate as usize {
+            self.audio_samples.extend(samples);
+        }
+    }
+    pub fn cartridge(&self) -> Option<&cartridge::Cartridge> {
+        self.cartridge.as_ref()
+    }
+    /// Run one CPU step, returns whether a frame is ready: the PPU finished one, a frame's
+    /// worth of time passed with the LCD off, or the break point was hit.
     pub fn tick(&mut self, break_point_option: Option<u16>) -> bool {
-        // println!("cpu start");
-        // let cpu_before_tick = self.cpu.clone();
         let cpu_cycle_in_4mhz = self.cpu.tick(&mut self.mem, break_point_option);
         if cpu_cycle_in_4mhz == 0 {
             return true;
         }
-        // if cpu_before_tick == self.cpu{
-        //     panic!("cpu is not changing. {}", self.cpu);
-        // }
-        // println!("cpu end");
-        self.ppu.tick(&mut self.mem, cpu_cycle_in_4mhz)
+        self.mem.tick(cpu_cycle_in_4mhz);
+        // the PPU doesn't follow the CPU into double speed
+        let cycle_in_4mhz = self.mem.normal_speed_cycles(cpu_cycle_in_4mhz);
+        self.cycles_since_save += cycle_in_4mhz as u32;
+        if self.cycles_since_save >= AUTOSAVE_CYCLE_IN_4MHZ {
+            self.cycles_since_save = 0;
+            if let Err(err) = self.save() {
+                eprintln!("autosave failed: {}", err);
+            }
+        }
+        let mut new_frame = self.ppu.tick(&mut self.mem, cycle_in_4mhz);
+        // with the LCD off a frame's worth of time still makes a frame, so input and audio
+        // keep going. The count starts over when the LCD is switched off.
+        if self.mem.get_bit(LCD_CONTROL_RW, 7) {
+            self.cycles_since_frame = 0;
+        } else {
+            self.cycles_since_frame += cycle_in_4mhz as u32;
+            if self.cycles_since_frame >= FRAME_CYCLE_IN_4MHZ {
+                new_frame = true;
+            }
+        }
+        if new_frame {
+            self.cycles_since_frame = 0;
+            self.flush_audio();
+        }
+        new_frame
+    }
+    /// 160x144 RGBA image of the LCD.
+    pub fn frame_buffer(&self) -> &Vec<u8> {
+        self.ppu.frame_buffer()
+    }
+    /// Keep the background map and tile data views up to date, they cost a redraw per frame.
+    pub fn set_debug_views(&mut self, enabled: bool) {
+        self.ppu.set_debug_views(enabled);
     }
-    pub fn get_new_frame_buffer(&self) {}
     pub fn get_bg_frame_buffer(&self) -> &Vec<u8> {
         self.ppu.bg_frame_buffer()
     }
@@ -45,3 +199,146 @@ impl Core {
         self.ppu.tiles_frame_buffer()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_rom_is_an_error() {
+        let mut core = Core::new(false);
+        let err = core
+            .load_game_rom("/nonexistent/rom.gb")
+            .map(|_| ())
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            cartridge::CartridgeError::Io {
+                kind: io::ErrorKind::NotFound,
+                ..
+            }
+        ));
+    }
+
+    /// Write `rom` to a fresh temp dir.
+    fn temp_rom(name: &str, rom: &[u8]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rboy-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let rom_path = dir.join("game.gb");
+        fs::write(&rom_path, rom).unwrap();
+        rom_path
+    }
+
+    /// MBC3 with RAM, RTC and battery next to a .sav holding `save`, both in a fresh temp dir.
+    fn mbc3_rom_with_save(name: &str, save: &[u8]) -> PathBuf {
+        let mut rom = vec![0; 2 * cartridge::ROM_BANK_SIZE];
+        rom[0x0147] = 0x10;
+        rom[0x0149] = 0x02;
+        let rom_path = temp_rom(name, &rom);
+        fs::write(rom_path.with_extension("sav"), save).unwrap();
+        rom_path
+    }
+
+    #[test]
+    fn wall_clock_rtc_counts_time_since_the_save() {
+        let two_hours_ago = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+            - 2 * 60 * 60;
+        let mut save = vec![0; cartridge::RAM_BANK_SIZE];
+        // live and latched registers, all zero, then the timestamp
+        save.extend_from_slice(&[0; 40]);
+        save.extend_from_slice(&two_hours_ago.to_le_bytes());
+        let rom_path = mbc3_rom_with_save("stale-sav", &save);
+
+        let mut core = Core::new(false);
+        core.set_rtc_clock(memory::RtcClock::WallClock);
+        core.load_game_rom(rom_path.to_str().unwrap()).unwrap();
+        let mapper = core.mem.mapper_mut();
+        mapper.write_rom(0x0000, 0x0a);
+        mapper.write_rom(0x6000, 0x00);
+        mapper.write_rom(0x6000, 0x01);
+        mapper.write_rom(0x4000, 0x0a);
+        let hours = mapper.read_ram(0x0000);
+        fs::remove_dir_all(rom_path.parent().unwrap()).unwrap();
+        assert_eq!(hours, 2);
+    }
+
+    #[test]
+    fn save_round_trip() {
+        let rom_path = mbc3_rom_with_save("round-trip", &[]);
+        let rom_path_str = rom_path.to_str().unwrap();
+        let mut core = Core::new(false);
+        core.load_game_rom(rom_path_str).unwrap();
+        let mapper = core.mem.mapper_mut();
+        mapper.write_rom(0x0000, 0x0a);
+        mapper.write_rom(0x4000, 0x00);
+        mapper.write_ram(0x1fff, 0x5a);
+        mapper.write_rom(0x4000, 0x0b);
+        mapper.write_ram(0x0000, 0x21);
+        // RTC writes only show up after a latch
+        mapper.write_rom(0x6000, 0x00);
+        mapper.write_rom(0x6000, 0x01);
+        core.save().unwrap();
+        let save = fs::read(rom_path.with_extension("sav")).unwrap();
+
+        let mut loaded = Core::new(false);
+        loaded.load_game_rom(rom_path_str).unwrap();
+        fs::remove_dir_all(rom_path.parent().unwrap()).unwrap();
+        // RAM followed by the 48 byte RTC footer
+        assert_eq!(save.len(), cartridge::RAM_BANK_SIZE + 48);
+        assert_eq!(
+            loaded.mem.mapper().save_data()[..save.len() - 8],
+            save[..save.len() - 8]
+        );
+        let mapper = loaded.mem.mapper_mut();
+        mapper.write_rom(0x0000, 0x0a);
+        mapper.write_rom(0x4000, 0x00);
+        assert_eq!(mapper.read_ram(0x1fff), 0x5a);
+        mapper.write_rom(0x4000, 0x0b);
+        assert_eq!(mapper.read_ram(0x0000), 0x21);
+    }
+
+    #[test]
+    fn frames_keep_coming_with_the_lcd_off() {
+        let mut rom = vec![0; 2 * cartridge::ROM_BANK_SIZE];
+        // CGB flag to start at 0x0100 without the boot ROM
+        rom[0x0143] = 0x80;
+        // xor a; ldh (LCDC), a; jr -4
+        rom[0x0100..0x0105].copy_from_slice(&[0xaf, 0xe0, 0x40, 0x18, 0xfc]);
+        let rom_path = temp_rom("lcd-off", &rom);
+        let mut core = Core::new(false);
+        core.load_game_rom(rom_path.to_str().unwrap()).unwrap();
+        fs::remove_dir_all(rom_path.parent().unwrap()).unwrap();
+
+        // ten and a half frames of 12 cycle instructions
+        let mut frames = 0;
+        for _ in 0..FRAME_CYCLE_IN_4MHZ * 21 / 2 / 12 {
+            frames += core.tick(None) as u32;
+        }
+        assert!(!core.mem.get_bit(LCD_CONTROL_RW, 7));
+        assert_eq!(frames, 10);
+    }
+
+    #[test]
+    fn lcd_off_frame_counts_from_the_switch_off() {
+        let mut rom = vec![0; 2 * cartridge::ROM_BANK_SIZE];
+        rom[0x0143] = 0x80;
+        // jr -2
+        rom[0x0100..0x0102].copy_from_slice(&[0x18, 0xfe]);
+        let rom_path = temp_rom("lcd-off-mid-frame", &rom);
+        let mut core = Core::new(false);
+        core.load_game_rom(rom_path.to_str().unwrap()).unwrap();
+        fs::remove_dir_all(rom_path.parent().unwrap()).unwrap();
+
+        // half a frame with the LCD on, then a whole frame of 12 cycle instructions before
+        // the first frame with it off
+        for _ in 0..FRAME_CYCLE_IN_4MHZ / 2 / 12 {
+            assert!(!core.tick(None));
+        }
+        core.mem.set(LCD_CONTROL_RW, 0x00);
+        let ticks = (1..).find(|_| core.tick(None)).unwrap();
+        assert_eq!(ticks, FRAME_CYCLE_IN_4MHZ.div_ceil(12));
+    }
+}
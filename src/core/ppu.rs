@@ -1,32 +1,118 @@
+use std::collections::VecDeque;
+
 use crate::core::constants::*;
 
 use super::memory;
 
 const GRAY_SHADES: [u8; 4] = [255, 170, 85, 0];
+const MAX_OBJECTS_PER_LINE: usize = 10;
+/// the first background fetch of a line is thrown away
+const DISCARDED_FETCH_CYCLE_IN_4MHZ: u8 = 6;
+const OBJ_FETCH_CYCLE_IN_4MHZ: u8 = 6;
 
 enum PPUState {
-    HBLANK,
-    VBLANK,
-    OAM,
-    DRAWING,
+    HBlank,
+    VBlank,
+    OamScan,
+    Drawing,
+}
+
+impl PPUState {
+    /// STAT bits 0-1
+    fn mode(&self) -> u8 {
+        match self {
+            PPUState::HBlank => 0,
+            PPUState::VBlank => 1,
+            PPUState::OamScan => 2,
+            PPUState::Drawing => 3,
+        }
+    }
 }
 
+#[derive(Clone, Copy, PartialEq, Eq)]
 enum FetcherState {
     ReadTileID,
-    ReadTileData,
+    ReadTileDataLow,
+    ReadTileDataHigh,
     PushToFIFO,
-    Idle,
 }
 
-/// Run at 4Mhz
-struct PixelFifo {}
+/// Background or window pixel waiting in the background FIFO.
+#[derive(Clone, Copy)]
+struct BgPixel {
+    color_id: usize,
+    /// [CGB Mode only] BGP0-7
+    palette: u8,
+    /// [CGB Mode only] colors 1-3 are drawn over objects
+    priority: bool,
+}
 
-/// Run at 2 Mhz
+/// Object pixel waiting in the object FIFO.
+#[derive(Clone, Copy)]
+struct ObjPixel {
+    color_id: usize,
+    /// BG and Window colors 1–3 are drawn over it
+    priority: bool,
+    dmg_palette: bool,
+    /// [CGB Mode only] OBP0-7
+    cgb_palette: u8,
+    /// CGB draws the object first in OAM on top
+    oam_index: u8,
+}
+
+/// Run at 4Mhz, shifts a pixel out to the LCD every cycle while it holds background pixels.
+struct PixelFifo {
+    bg: VecDeque<BgPixel>,
+    obj: VecDeque<ObjPixel>,
+}
+
+impl PixelFifo {
+    fn new() -> PixelFifo {
+        PixelFifo {
+            bg: VecDeque::with_capacity(16),
+            obj: VecDeque::with_capacity(8),
+        }
+    }
+    fn clear(&mut self) {
+        self.bg.clear();
+        self.obj.clear();
+    }
+}
+
+/// Run at 2 Mhz, every step but the push takes two cycles.
 struct PixelFetcher {
     state: FetcherState,
+    /// cycles spent in the current step
+    cycle: u8,
+    /// tile column, relative to SCX or to the left edge of the window
+    tile_x: u8,
+    tile_idx: u8,
+    /// [CGB Mode only] BG map attributes from VRAM bank 1
+    attributes: u8,
+    low: u8,
+    high: u8,
+    /// fetching window tiles instead of background ones
+    window: bool,
 }
 
-struct OAM {
+impl PixelFetcher {
+    fn new(window: bool) -> PixelFetcher {
+        PixelFetcher {
+            state: FetcherState::ReadTileID,
+            cycle: 0,
+            tile_x: 0,
+            tile_idx: 0,
+            attributes: 0,
+            low: 0,
+            high: 0,
+            window,
+        }
+    }
+}
+
+/// Object attribute memory entry.
+#[derive(Clone, Copy)]
+struct OamEntry {
     y: u8,
     x: u8,
     tile_idx: u8,
@@ -38,10 +124,36 @@ struct OAM {
     x_flip: bool,
     /// [Non CGB Mode only]: 0 = OBP0, 1 = OBP1
     dmg_palette: bool,
+    /// [CGB Mode only]: tile data from VRAM bank 0 or 1
+    vram_bank: u8,
+    /// [CGB Mode only]: OBP0-7
+    cgb_palette: u8,
+    /// position in OAM
+    index: u8,
+}
+
+impl OamEntry {
+    fn from_bytes(index: u8, bytes: &[u8]) -> OamEntry {
+        let flags = bytes[3];
+        OamEntry {
+            y: bytes[0],
+            x: bytes[1],
+            tile_idx: bytes[2],
+            priority: flags & 0x80 != 0,
+            y_flip: flags & 0x40 != 0,
+            x_flip: flags & 0x20 != 0,
+            dmg_palette: flags & 0x10 != 0,
+            vram_bank: (flags >> 3) & 1,
+            cgb_palette: flags & 0x07,
+            index,
+        }
+    }
 }
 
 /// PIXEL PROCESS UNIT
 pub struct PPU {
+    /// CGB mode, color palettes and VRAM bank 1 attributes
+    cgb: bool,
     lcd_ppu_enable: bool,
 
     /// false = 9800–9BFF; true = 9C00–9FFF
@@ -58,41 +170,86 @@ pub struct PPU {
     obj_size: bool,
     obj_enable: bool,
     bg_and_window_enable_priority: bool,
+    /// LCD image, RGBA
     frame_buffer: Vec<u8>,
+    /// draw the background map and tile data views, only debug frontends show them
+    debug_views: bool,
     bg_frame_buffer: Vec<u8>,
     tiles_frame_buffer: Vec<u8>,
-    remained_cycle: u8,
     current_state: PPUState,
-    current_state_cycle: u16,
-    pixel_fifo: Vec<u8>,
+    /// cycles since the start of the current line
+    line_cycle: u16,
+    fifo: PixelFifo,
+    fetcher: PixelFetcher,
+    /// next LCD column of the current line
+    lcd_x: u8,
+    /// background pixels to drop, the SCX fine scroll or the part of the window left of WX 7
+    discard: u8,
+    /// cycles before the fetcher starts on the current line
+    fetch_delay: u8,
+    /// index in line_objects of the next object to fetch
+    next_object: usize,
+    /// cycles left on the current object fetch
+    obj_fetch_cycles: u8,
+    /// the window can start on the current line
+    window_line_enabled: bool,
+    /// the window was drawn on the current line
+    window_drawn: bool,
+    /// the STAT interrupt line, interrupts are requested on its rising edge only
+    stat_line: bool,
+    /// WY matched LY on some line of this frame
+    window_y_triggered: bool,
+    /// window row to draw next, only advances on lines showing the window
+    window_line: u8,
+    /// objects found by the OAM scan for the current line, in drawing priority order
+    line_objects: Vec<OamEntry>,
 }
+/// Color index 0-3 of bit `shift` in a tile row, the first byte holds the low bits.
+fn tile_color_id(low: u8, high: u8, shift: u8) -> usize {
+    ((((high >> shift) & 1) << 1) | ((low >> shift) & 1)) as usize
+}
+
+/// Shade of `color_id` through a DMG palette register.
+fn palette_shade(palette: u8, color_id: usize) -> u8 {
+    GRAY_SHADES[((palette >> (color_id * 2)) & 0b11) as usize]
+}
+
+/// 15-bit color of `color_id` in CGB palette RAM, as 8-bit RGB.
+fn cgb_palette_color(palette_ram: &[u8], palette: u8, color_id: usize) -> [u8; 3] {
+    let idx = palette as usize * 8 + color_id * 2;
+    let color = u16::from_le_bytes([palette_ram[idx], palette_ram[idx + 1]]);
+    let channel = |shift: u16| {
+        let val = ((color >> shift) & 0x1f) as u8;
+        (val << 3) | (val >> 2)
+    };
+    [channel(0), channel(5), channel(10)]
+}
+
 fn calculate_tile(data: &[u8], palette: u8) -> [u8; 64] {
     let mut tile_data = [0; 64];
-    let colors: Vec<u8> = (0..4).map(|x| (palette >> (x * 2)) & 0b11).collect();
     for y in 0..8 {
         let idx = y * 2;
         for c in 0..8 {
-            let shift = 7 - c;
-            let upper_bit = (data[idx] >> shift) & 1;
-            let lower_bit = (data[idx + 1] >> shift) & 1;
-            let color_id = ((upper_bit << 1) + lower_bit) as usize;
-            let color = colors[color_id];
-            tile_data[y * 8 + c] = GRAY_SHADES[color as usize];
+            let color_id = tile_color_id(data[idx], data[idx + 1], 7 - c as u8);
+            tile_data[y * 8 + c] = palette_shade(palette, color_id);
         }
     }
     tile_data
 }
 
+impl Default for PPU {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl PPU {
     pub fn new() -> PPU {
-        let frame_buffer =
-            Vec::from_iter(std::iter::repeat(255).take((LCD_HEIGHT * LCD_WIDTH * 4) as usize));
-        let bg_frame_buffer =
-            Vec::from_iter(std::iter::repeat(255).take((BG_SIZE * BG_SIZE * 4) as usize));
-        let tiles_frame_buffer = Vec::from_iter(
-            std::iter::repeat(255).take(((BG_SIZE - LCD_HEIGHT) * LCD_WIDTH * 4) as usize),
-        );
+        let frame_buffer = vec![255; (LCD_HEIGHT * LCD_WIDTH * 4) as usize];
+        let bg_frame_buffer = vec![255; (BG_SIZE * BG_SIZE * 4) as usize];
+        let tiles_frame_buffer = vec![255; ((BG_SIZE - LCD_HEIGHT) * LCD_WIDTH * 4) as usize];
         PPU {
+            cgb: false,
             lcd_ppu_enable: false,
             window_tile_map_area: false,
             window_enable: false,
@@ -102,12 +259,31 @@ impl PPU {
             obj_enable: false,
             bg_and_window_enable_priority: false,
             frame_buffer,
+            debug_views: false,
             bg_frame_buffer,
             tiles_frame_buffer,
-            remained_cycle: 0,
-            current_state: PPUState::OAM,
-            current_state_cycle: 0,
-            pixel_fifo: vec![],
+            current_state: PPUState::OamScan,
+            line_cycle: 0,
+            fifo: PixelFifo::new(),
+            fetcher: PixelFetcher::new(false),
+            lcd_x: 0,
+            discard: 0,
+            fetch_delay: 0,
+            next_object: 0,
+            obj_fetch_cycles: 0,
+            window_line_enabled: false,
+            window_drawn: false,
+            stat_line: false,
+            window_y_triggered: false,
+            window_line: 0,
+            line_objects: Vec::with_capacity(MAX_OBJECTS_PER_LINE),
+        }
+    }
+    fn window_tile_map_start(&self) -> u16 {
+        if self.window_tile_map_area {
+            0x9c00
+        } else {
+            0x9800
         }
     }
     fn bg_tile_map_range(&self) -> std::ops::Range<u16> {
@@ -117,19 +293,305 @@ impl PPU {
             0x9800..0x9c00
         }
     }
-    fn bg_tile_data_start_addr(&self) -> u16 {
+    /// 0x8000 addressing uses unsigned tile indexes, 0x8800 signed ones from 0x9000.
+    fn tile_data_addr(&self, tile_idx: u8) -> u16 {
         if self.bg_and_window_tile_data_area {
-            0x8000
+            0x8000 + tile_idx as u16 * TILE_DATA_SIZE as u16
+        } else {
+            0x9000u16.wrapping_add_signed(tile_idx as i8 as i16 * TILE_DATA_SIZE as i16)
+        }
+    }
+    fn obj_height(&self) -> u8 {
+        if self.obj_size {
+            16
         } else {
-            0x8800
+            8
+        }
+    }
+    /// Mode 2, pick the first 10 objects in OAM that cover `line_y`, in fetch order. On DMG
+    /// the one with the smaller X is drawn on top, OAM order breaks ties. CGB only goes by
+    /// OAM order.
+    fn scan_oam(&mut self, mem: &memory::Memory, line_y: u8) {
+        let height = self.obj_height();
+        self.line_objects.clear();
+        for (index, bytes) in mem
+            .get_chunck(OAM_RAM_START as u16, OAM_RAM_SIZE)
+            .chunks_exact(4)
+            .enumerate()
+        {
+            let obj = OamEntry::from_bytes(index as u8, bytes);
+            // y is the bottom of a 16 pixel tall object
+            let top = obj.y as i16 - 16;
+            if (top..top + height as i16).contains(&(line_y as i16)) {
+                self.line_objects.push(obj);
+                if self.line_objects.len() == MAX_OBJECTS_PER_LINE {
+                    break;
+                }
+            }
+        }
+        self.line_objects.sort_by_key(|obj| obj.x);
+    }
+    /// Check at the start of a line whether the window shows up from now on.
+    fn check_window_y(&mut self, mem: &memory::Memory, line_y: u8) {
+        if mem.get(WINDOW_Y_POSITION_RW) == line_y {
+            self.window_y_triggered = true;
+        }
+    }
+    /// Mode 3 set up, registers are read again as the line is drawn.
+    fn start_line(&mut self, mem: &memory::Memory) {
+        self.fifo.clear();
+        self.fetcher = PixelFetcher::new(false);
+        self.lcd_x = 0;
+        self.discard = mem.get(SCROLL_X_RW) % 8;
+        self.fetch_delay = DISCARDED_FETCH_CYCLE_IN_4MHZ;
+        self.next_object = 0;
+        self.obj_fetch_cycles = 0;
+        // on CGB LCDC bit 0 only takes the priority away from the background and window
+        self.window_line_enabled = self.window_enable
+            && (self.cgb || self.bg_and_window_enable_priority)
+            && self.window_y_triggered;
+        self.window_drawn = false;
+    }
+    /// Tile row being fetched, SCY is read on every fetch.
+    fn fetcher_y(&self, mem: &memory::Memory, line_y: u8) -> u8 {
+        if self.fetcher.window {
+            self.window_line
+        } else {
+            mem.get(SCROLL_Y_RW).wrapping_add(line_y)
+        }
+    }
+    fn fetcher_tile_map_addr(&self, mem: &memory::Memory, line_y: u8) -> u16 {
+        let row = self.fetcher_y(mem, line_y) as u16 / 8 * 32;
+        if self.fetcher.window {
+            self.window_tile_map_start() + row + self.fetcher.tile_x as u16
+        } else {
+            let col = (mem.get(SCROLL_X_RW) / 8).wrapping_add(self.fetcher.tile_x) & 31;
+            self.bg_tile_map_range().start + row + col as u16
+        }
+    }
+    fn fetcher_tile_row_addr(&self, mem: &memory::Memory, line_y: u8) -> u16 {
+        let mut row = self.fetcher_y(mem, line_y) % 8;
+        if self.fetcher.attributes & 0x40 != 0 {
+            row = 7 - row;
+        }
+        self.tile_data_addr(self.fetcher.tile_idx) + row as u16 * 2
+    }
+    /// Tile data through the VRAM bank picked by the BG map attributes.
+    fn fetcher_tile_data(&self, mem: &memory::Memory, addr: u16) -> u8 {
+        mem.vram_bank((self.fetcher.attributes >> 3) & 1, addr)
+    }
+    fn step_fetcher(&mut self, mem: &memory::Memory, line_y: u8) {
+        if self.fetcher.state == FetcherState::PushToFIFO {
+            // waits for the FIFO to run empty
+            if self.fifo.bg.is_empty() {
+                let attributes = self.fetcher.attributes;
+                for col in 0..8 {
+                    let shift = if attributes & 0x20 != 0 { col } else { 7 - col };
+                    self.fifo.bg.push_back(BgPixel {
+                        color_id: tile_color_id(self.fetcher.low, self.fetcher.high, shift),
+                        palette: attributes & 0x07,
+                        priority: attributes & 0x80 != 0,
+                    });
+                }
+                self.fetcher.tile_x = self.fetcher.tile_x.wrapping_add(1);
+                self.fetcher.state = FetcherState::ReadTileID;
+            }
+            return;
+        }
+        self.fetcher.cycle += 1;
+        if self.fetcher.cycle < 2 {
+            return;
+        }
+        self.fetcher.cycle = 0;
+        self.fetcher.state = match self.fetcher.state {
+            FetcherState::ReadTileID => {
+                let addr = self.fetcher_tile_map_addr(mem, line_y);
+                self.fetcher.tile_idx = mem.vram(addr);
+                if self.cgb {
+                    self.fetcher.attributes = mem.vram_bank(1, addr);
+                }
+                FetcherState::ReadTileDataLow
+            }
+            FetcherState::ReadTileDataLow => {
+                let addr = self.fetcher_tile_row_addr(mem, line_y);
+                self.fetcher.low = self.fetcher_tile_data(mem, addr);
+                FetcherState::ReadTileDataHigh
+            }
+            _ => {
+                let addr = self.fetcher_tile_row_addr(mem, line_y) + 1;
+                self.fetcher.high = self.fetcher_tile_data(mem, addr);
+                FetcherState::PushToFIFO
+            }
+        };
+    }
+    /// The next object starts at the current column, objects partly left of the screen start
+    /// at column 0.
+    fn object_due(&self) -> bool {
+        self.line_objects
+            .get(self.next_object)
+            .is_some_and(|obj| obj.x as i16 - 8 <= self.lcd_x as i16)
+    }
+    /// Mix the row of `obj` on the current line into the object FIFO, pixels already there
+    /// win unless transparent, or on CGB unless `obj` comes first in OAM.
+    fn fetch_object(&mut self, mem: &memory::Memory, obj: OamEntry, line_y: u8) {
+        let height = self.obj_height();
+        // LCDC can switch to 8x8 objects between the OAM scan and the fetch, only the low
+        // bits of the row are used then
+        let mut row = (line_y as i16 - (obj.y as i16 - 16)) as u8 & (height - 1);
+        if obj.y_flip {
+            row = height - 1 - row;
+        }
+        let tile_idx = if height == 16 {
+            obj.tile_idx & 0xfe
+        } else {
+            obj.tile_idx
+        };
+        let row_addr = 0x8000 + tile_idx as u16 * TILE_DATA_SIZE as u16 + row as u16 * 2;
+        let bank = if self.cgb { obj.vram_bank } else { 0 };
+        let (low, high) = (
+            mem.vram_bank(bank, row_addr),
+            mem.vram_bank(bank, row_addr + 1),
+        );
+        // columns left of the screen are already gone
+        let skip = (self.lcd_x as i16 - (obj.x as i16 - 8)).max(0) as u8;
+        for col in skip..8 {
+            let shift = if obj.x_flip { col } else { 7 - col };
+            let pixel = ObjPixel {
+                color_id: tile_color_id(low, high, shift),
+                priority: obj.priority,
+                dmg_palette: obj.dmg_palette,
+                cgb_palette: obj.cgb_palette,
+                oam_index: obj.index,
+            };
+            match self.fifo.obj.get_mut((col - skip) as usize) {
+                Some(existing)
+                    if existing.color_id == 0
+                        || (self.cgb
+                            && pixel.color_id != 0
+                            && pixel.oam_index < existing.oam_index) =>
+                {
+                    *existing = pixel
+                }
+                Some(_) => {}
+                None => self.fifo.obj.push_back(pixel),
+            }
+        }
+    }
+    /// One cycle of mode 3.
+    fn tick_drawing(&mut self, mem: &memory::Memory, line_y: u8) {
+        if self.fetch_delay > 0 {
+            self.fetch_delay -= 1;
+            return;
+        }
+        if self.obj_fetch_cycles > 0 {
+            self.obj_fetch_cycles -= 1;
+            if self.obj_fetch_cycles == 0 {
+                let obj = self.line_objects[self.next_object];
+                self.fetch_object(mem, obj, line_y);
+                self.next_object += 1;
+            }
+            return;
+        }
+        if self.discard == 0 && self.object_due() {
+            if !self.obj_enable {
+                self.next_object += 1;
+                return;
+            }
+            // the background fetcher finishes its tile before the object fetch starts
+            if self.fetcher.state == FetcherState::PushToFIFO && !self.fifo.bg.is_empty() {
+                self.obj_fetch_cycles = OBJ_FETCH_CYCLE_IN_4MHZ;
+            } else {
+                self.step_fetcher(mem, line_y);
+            }
+            return;
+        }
+        if self.window_line_enabled && !self.fetcher.window {
+            // WX is the left edge plus 7, values below 7 cut off the left of the window
+            let window_left = mem.get(WINDOW_X_POSITION_MINUS_7_RW) as i16 - 7;
+            if window_left < LCD_WIDTH as i16 && self.lcd_x as i16 >= window_left {
+                self.fifo.bg.clear();
+                self.fetcher = PixelFetcher::new(true);
+                self.discard = (-window_left).max(0) as u8;
+                self.window_drawn = true;
+            }
+        }
+        self.step_fetcher(mem, line_y);
+        let Some(bg) = self.fifo.bg.pop_front() else {
+            return;
+        };
+        if self.discard > 0 {
+            self.discard -= 1;
+            return;
+        }
+        let obj = self
+            .fifo
+            .obj
+            .pop_front()
+            .filter(|obj| obj.color_id != 0 && self.obj_enable);
+        let color = if self.cgb {
+            self.cgb_pixel_color(mem, bg, obj)
+        } else {
+            let shade = self.dmg_pixel_shade(mem, bg.color_id, obj);
+            [shade; 3]
+        };
+        self.set_pixel(self.lcd_x, line_y, color);
+        self.lcd_x += 1;
+    }
+    /// Mix the background and opaque object pixels through the DMG palette registers.
+    fn dmg_pixel_shade(&self, mem: &memory::Memory, color_id: usize, obj: Option<ObjPixel>) -> u8 {
+        // with LCDC bit 0 cleared the background and window are blank
+        let color_id = if self.bg_and_window_enable_priority {
+            color_id
+        } else {
+            0
+        };
+        match obj {
+            // BG colors 1-3 cover objects with the priority flag
+            Some(obj) if !obj.priority || color_id == 0 => {
+                let palette = if obj.dmg_palette {
+                    mem.get(OBJ_PALETTE_1_DATA)
+                } else {
+                    mem.get(OBJ_PALETTE_0_DATA)
+                };
+                palette_shade(palette, obj.color_id)
+            }
+            _ => palette_shade(mem.get(BG_PALETTE_DATA), color_id),
+        }
+    }
+    /// Mix the background and opaque object pixels through CGB palette RAM.
+    fn cgb_pixel_color(&self, mem: &memory::Memory, bg: BgPixel, obj: Option<ObjPixel>) -> [u8; 3] {
+        match obj {
+            // LCDC bit 0 cleared puts objects above everything, otherwise BG colors 1-3 cover
+            // them when either the object or the BG map entry asks for it
+            Some(obj)
+                if !self.bg_and_window_enable_priority
+                    || bg.color_id == 0
+                    || (!bg.priority && !obj.priority) =>
+            {
+                cgb_palette_color(mem.obj_palette_ram(), obj.cgb_palette, obj.color_id)
+            }
+            _ => cgb_palette_color(mem.bg_palette_ram(), bg.palette, bg.color_id),
+        }
+    }
+    fn start_frame(&mut self) {
+        self.window_y_triggered = false;
+        self.window_line = 0;
+    }
+    fn set_pixel(&mut self, x: u8, y: u8, color: [u8; 3]) {
+        let idx = (y as usize * LCD_WIDTH as usize + x as usize) * 4;
+        self.frame_buffer[idx..idx + 3].copy_from_slice(&color);
+    }
+    /// The LCD shows white while it is off.
+    fn clear_frame(&mut self) {
+        for pixel in self.frame_buffer.chunks_exact_mut(4) {
+            pixel[..3].fill(GRAY_SHADES[0]);
         }
     }
     fn draw_bg_frame(&mut self, mem: &memory::Memory) {
         for (i, addr) in self.bg_tile_map_range().enumerate() {
-            let tile_idx = mem.get(addr);
-            let tile_data_addr =
-                tile_idx as u16 * TILE_DATA_SIZE as u16 + self.bg_tile_data_start_addr();
-            let palette = mem.get(BG_PALETTE_DATA as u16);
+            let tile_idx = mem.vram(addr);
+            let tile_data_addr = self.tile_data_addr(tile_idx);
+            let palette = mem.get(BG_PALETTE_DATA);
 
             let tile_data = calculate_tile(mem.get_chunck(tile_data_addr, TILE_DATA_SIZE), palette);
 
@@ -147,7 +609,7 @@ impl PPU {
     }
     fn draw_tiles_frame(&mut self, mem: &memory::Memory) {
         let tile_nums = ((BG_SIZE - LCD_HEIGHT) / 8 * LCD_WIDTH / 8) as usize;
-        let palette = mem.get(BG_PALETTE_DATA as u16);
+        let palette = mem.get(BG_PALETTE_DATA);
         for (i, tile_data_start) in (VRAM_START..VRAM_START + tile_nums * TILE_DATA_SIZE)
             .step_by(TILE_DATA_SIZE)
             .enumerate()
@@ -192,6 +654,12 @@ impl PPU {
             self.bg_frame_buffer[idx + 2] = 0;
         }
     }
+    pub fn frame_buffer(&self) -> &Vec<u8> {
+        &self.frame_buffer
+    }
+    pub fn set_debug_views(&mut self, enabled: bool) {
+        self.debug_views = enabled;
+    }
     pub fn bg_frame_buffer(&self) -> &Vec<u8> {
         &self.bg_frame_buffer
     }
@@ -199,6 +667,33 @@ impl PPU {
         &self.tiles_frame_buffer
     }
 
+    /// Keep the mode and LYC bits of STAT up to date and request the LCD STAT interrupt when
+    /// one of the enabled sources goes up while none was.
+    fn update_stat(&mut self, mem: &mut memory::Memory) {
+        let stat = mem.get(STATUS_ADDR_RW);
+        let line_y = mem.get(Y_COORDINATE_R);
+        let coincidence = line_y == mem.get(LY_COMPARE_RW);
+        mem.set_register(
+            STATUS_ADDR_RW,
+            (stat & 0b1111_1000) | ((coincidence as u8) << 2) | self.current_state.mode(),
+        );
+        let mode_source = match self.current_state {
+            PPUState::HBlank => stat & (1 << 3) != 0,
+            // the OAM source also fires as line 144 starts
+            PPUState::VBlank => {
+                stat & (1 << 4) != 0
+                    || (stat & (1 << 5) != 0 && line_y == LCD_HEIGHT as u8 && self.line_cycle == 0)
+            }
+            PPUState::OamScan => stat & (1 << 5) != 0,
+            PPUState::Drawing => false,
+        };
+        let stat_line = mode_source || (coincidence && stat & (1 << 6) != 0);
+        if stat_line && !self.stat_line {
+            mem.set_bit(INTERRUPT_FLAG, INTR_LCDC_BIT, true);
+        }
+        self.stat_line = stat_line;
+    }
+
     fn check_lcdc(&mut self, mem: &memory::Memory) {
         self.lcd_ppu_enable = mem.get_bit(LCD_CONTROL_RW, 7);
         self.window_tile_map_area = mem.get_bit(LCD_CONTROL_RW, 6);
@@ -211,64 +706,96 @@ impl PPU {
     }
 
     fn check_all_registers(&mut self, mem: &memory::Memory) {
+        self.cgb = mem.cgb_mode();
         self.check_lcdc(mem);
     }
 
     /// PPU runs at 4MHz
     /// return has frame
     pub fn tick(&mut self, mem: &mut memory::Memory, cpu_cycle_in_4mhz: u8) -> bool {
+        let was_enabled = self.lcd_ppu_enable;
         self.check_all_registers(mem);
         if !self.lcd_ppu_enable {
+            if was_enabled {
+                self.clear_frame();
+                self.start_frame();
+                self.current_state = PPUState::OamScan;
+                self.line_cycle = 0;
+                self.stat_line = false;
+                mem.set_register(Y_COORDINATE_R, 0);
+                // STAT reads mode 0 while the LCD is off
+                let stat = mem.get(STATUS_ADDR_RW);
+                mem.set_register(STATUS_ADDR_RW, stat & 0b1111_1100);
+                mem.vram_accessible = true;
+                mem.oam_accessible = true;
+            }
             return false;
         }
-        self.remained_cycle += cpu_cycle_in_4mhz;
-        self.current_state_cycle += cpu_cycle_in_4mhz as u16;
+        let mut has_frame = false;
+        for _ in 0..cpu_cycle_in_4mhz {
+            has_frame |= self.tick_cycle(mem);
+            self.update_stat(mem);
+        }
+        has_frame
+    }
+
+    fn tick_cycle(&mut self, mem: &mut memory::Memory) -> bool {
+        self.line_cycle += 1;
         let line_y = mem.get(Y_COORDINATE_R);
         match self.current_state {
-            PPUState::OAM => {
-                if self.current_state_cycle >= OAM_CYCLE_IN_4MHZ {
-                    // panic!("start DRAWING");
-                    self.current_state = PPUState::DRAWING;
-                    self.current_state_cycle -= OAM_CYCLE_IN_4MHZ;
+            PPUState::OamScan => {
+                if self.line_cycle == OAM_CYCLE_IN_4MHZ {
+                    self.check_window_y(mem, line_y);
+                    self.scan_oam(mem, line_y);
+                    self.start_line(mem);
+                    self.current_state = PPUState::Drawing;
                     mem.vram_accessible = false;
                 }
             }
-            PPUState::DRAWING => {
-                if self.current_state_cycle >= MAX_DRAWING_CYCLE_IN_4MHZ {
-                    // panic!("start HB");
-                    self.current_state = PPUState::HBLANK;
+            PPUState::Drawing => {
+                self.tick_drawing(mem, line_y);
+                if self.lcd_x == LCD_WIDTH as u8 {
+                    if self.window_drawn {
+                        self.window_line += 1;
+                    }
+                    self.current_state = PPUState::HBlank;
                     mem.vram_accessible = true;
                     mem.oam_accessible = true;
+                    mem.hblank();
                 }
             }
-            PPUState::HBLANK => {
-                if self.current_state_cycle >= DRAW_AND_HBLANK_CYCLE_IN_4MHZ {
-                    self.current_state_cycle -= DRAW_AND_HBLANK_CYCLE_IN_4MHZ;
-                    mem.set(Y_COORDINATE_R, line_y + 1);
+            PPUState::HBlank => {
+                if self.line_cycle == SCANLINE_CYCLE_IN_4MHZ {
+                    self.line_cycle = 0;
+                    mem.set_register(Y_COORDINATE_R, line_y + 1);
                     if line_y + 1 < LCD_HEIGHT as u8 {
-                        self.current_state = PPUState::OAM;
+                        self.current_state = PPUState::OamScan;
                         mem.oam_accessible = false;
                     } else {
-                        self.current_state = PPUState::VBLANK;
+                        self.current_state = PPUState::VBlank;
                         mem.set_bit(INTERRUPT_FLAG, INTR_VBLANK_BIT, true);
-                        mem.vram_accessible = true;
-                        mem.oam_accessible = true;
-                        // draw frame
-                        self.draw_bg_frame(mem);
-                        self.draw_view_port(mem);
-                        self.draw_tiles_frame(mem);
+                        if self.debug_views {
+                            self.draw_bg_frame(mem);
+                            self.draw_view_port(mem);
+                            self.draw_tiles_frame(mem);
+                        }
                         return true;
                     }
                 }
             }
-            PPUState::VBLANK => {
-                if self.current_state_cycle >= VBLANK_CYCLE_IN_4MHZ {
-                    self.current_state_cycle -= VBLANK_CYCLE_IN_4MHZ;
-                    mem.set(Y_COORDINATE_R, line_y + 1);
-                    if line_y + 1 >= VBLANK_END_LY {
-                        self.current_state = PPUState::OAM;
+            PPUState::VBlank => {
+                if line_y == VBLANK_END_LY && self.line_cycle == LAST_LINE_LY_CYCLE_IN_4MHZ {
+                    // LY already reads 0 for most of line 153, LYC=0 matches there
+                    mem.set_register(Y_COORDINATE_R, 0);
+                } else if self.line_cycle == SCANLINE_CYCLE_IN_4MHZ {
+                    self.line_cycle = 0;
+                    if line_y == 0 {
+                        self.start_frame();
+                        self.current_state = PPUState::OamScan;
                         mem.oam_accessible = false;
-                        mem.set(Y_COORDINATE_R, 0);
+                        mem.set_register(Y_COORDINATE_R, 0);
+                    } else {
+                        mem.set_register(Y_COORDINATE_R, line_y + 1);
                     }
                 }
             }
@@ -276,3 +803,177 @@ impl PPU {
         false
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn object_size_change_after_the_oam_scan() {
+        let mut mem = memory::Memory::new(false);
+        // tile 0, only row 5 has color 1
+        mem.set(0x8000 + 5 * 2, 0xff);
+        // y flipped object with its top at line 0
+        mem.set(OAM_RAM_START as u16, 16);
+        mem.set(OAM_RAM_START as u16 + 1, 8);
+        mem.set(OAM_RAM_START as u16 + 3, 0x40);
+        let mut ppu = PPU::new();
+        ppu.obj_size = true;
+        ppu.scan_oam(&mem, 10);
+        assert_eq!(ppu.line_objects.len(), 1);
+        ppu.obj_size = false;
+        // row 10 becomes row 2, flipped to 5
+        ppu.fetch_object(&mem, ppu.line_objects[0], 10);
+        assert_eq!(ppu.fifo.obj.len(), 8);
+        assert!(ppu.fifo.obj.iter().all(|pixel| pixel.color_id == 1));
+    }
+
+    fn run_cycles(ppu: &mut PPU, mem: &mut memory::Memory, cycles: u32) {
+        for _ in 0..cycles {
+            ppu.tick(mem, 1);
+        }
+    }
+
+    fn run_line(ppu: &mut PPU, mem: &mut memory::Memory) {
+        run_cycles(ppu, mem, SCANLINE_CYCLE_IN_4MHZ as u32);
+    }
+
+    fn shade_at(ppu: &PPU, x: usize, y: usize) -> u8 {
+        ppu.frame_buffer[(y * LCD_WIDTH as usize + x) * 4]
+    }
+
+    const WINDOW_ON: u8 = 0b1011_1001;
+    const WINDOW_OFF: u8 = 0b1001_1001;
+
+    /// Window map at 0x9800 all tile 0 with `row` as every row but `dark_row`, background map
+    /// at 0x9c00 all blank tile 1.
+    fn window_setup(row: u8, dark_row: Option<u16>) -> memory::Memory {
+        let mut mem = memory::Memory::new(false);
+        for addr in 0x9c00..0xa000 {
+            mem.set(addr, 1);
+        }
+        for tile_row in 0..8 {
+            let val = if dark_row.is_none_or(|dark| dark == tile_row) {
+                row
+            } else {
+                0
+            };
+            mem.set(0x8000 + tile_row * 2, val);
+            mem.set(0x8000 + tile_row * 2 + 1, val);
+        }
+        mem.set(WINDOW_Y_POSITION_RW, 0);
+        mem
+    }
+
+    #[test]
+    fn window_line_counter_skips_hidden_lines() {
+        // only window row 2 is dark
+        let mut mem = window_setup(0xff, Some(2));
+        mem.set(WINDOW_X_POSITION_MINUS_7_RW, 7);
+        mem.set(LCD_CONTROL_RW, WINDOW_ON);
+        let mut ppu = PPU::new();
+        for line in 0..6 {
+            match line {
+                2 => mem.set(LCD_CONTROL_RW, WINDOW_OFF),
+                5 => mem.set(LCD_CONTROL_RW, WINDOW_ON),
+                _ => {}
+            }
+            run_line(&mut ppu, &mut mem);
+        }
+        // lines 2-4 show the background, line 5 picks up at window row 2 rather than LY - WY
+        assert_eq!(shade_at(&ppu, 0, 1), GRAY_SHADES[0]);
+        assert_eq!(shade_at(&ppu, 0, 2), GRAY_SHADES[0]);
+        assert_eq!(shade_at(&ppu, 0, 5), GRAY_SHADES[3]);
+        assert_eq!(ppu.window_line, 3);
+    }
+
+    #[test]
+    fn window_x_below_7_cuts_off_the_left() {
+        // window column 4 is dark
+        let mut mem = window_setup(0x08, None);
+        mem.set(WINDOW_X_POSITION_MINUS_7_RW, 3);
+        mem.set(LCD_CONTROL_RW, WINDOW_ON);
+        let mut ppu = PPU::new();
+        run_line(&mut ppu, &mut mem);
+        assert_eq!(shade_at(&ppu, 0, 0), GRAY_SHADES[3]);
+        assert_eq!(shade_at(&ppu, 4, 0), GRAY_SHADES[0]);
+        assert_eq!(ppu.window_line, 1);
+    }
+
+    #[test]
+    fn window_x_166_shows_one_column() {
+        // window column 0 is dark
+        let mut mem = window_setup(0x80, None);
+        mem.set(WINDOW_X_POSITION_MINUS_7_RW, 166);
+        mem.set(LCD_CONTROL_RW, WINDOW_ON);
+        let mut ppu = PPU::new();
+        run_line(&mut ppu, &mut mem);
+        assert_eq!(shade_at(&ppu, 158, 0), GRAY_SHADES[0]);
+        assert_eq!(shade_at(&ppu, 159, 0), GRAY_SHADES[3]);
+        assert_eq!(ppu.window_line, 1);
+        // past the right edge the window isn't drawn and its line counter stays put
+        mem.set(WINDOW_X_POSITION_MINUS_7_RW, 167);
+        run_line(&mut ppu, &mut mem);
+        assert_eq!(shade_at(&ppu, 159, 1), GRAY_SHADES[0]);
+        assert_eq!(ppu.window_line, 1);
+    }
+
+    /// Run to `cycles` into line `line` with STAT set to `stat`, then clear the STAT interrupt.
+    fn stat_setup(stat: u8, line: u32, cycles: u32) -> (PPU, memory::Memory) {
+        let mut mem = memory::Memory::new(false);
+        mem.set(STATUS_ADDR_RW, stat);
+        mem.set(LCD_CONTROL_RW, 0x91);
+        let mut ppu = PPU::new();
+        run_cycles(
+            &mut ppu,
+            &mut mem,
+            line * SCANLINE_CYCLE_IN_4MHZ as u32 + cycles,
+        );
+        mem.set_bit(INTERRUPT_FLAG, INTR_LCDC_BIT, false);
+        (ppu, mem)
+    }
+
+    fn stat_requested(mem: &memory::Memory) -> bool {
+        mem.get_bit(INTERRUPT_FLAG, INTR_LCDC_BIT)
+    }
+
+    #[test]
+    fn stat_hblank_to_vblank_fires_once() {
+        // HBlank of line 143 into line 144
+        let (mut ppu, mut mem) = stat_setup(0x18, 143, 400);
+        run_cycles(&mut ppu, &mut mem, 100);
+        assert_eq!(mem.get(STATUS_ADDR_RW) & 0b11, 1);
+        assert!(!stat_requested(&mem));
+        // the VBlank source alone does fire
+        let (mut ppu, mut mem) = stat_setup(0x10, 143, 400);
+        run_cycles(&mut ppu, &mut mem, 100);
+        assert!(stat_requested(&mem));
+    }
+
+    #[test]
+    fn stat_lyc_match_while_the_line_is_high() {
+        // LYC 5 matches as HBlank of line 4 ends, the line never drops
+        let (mut ppu, mut mem) = stat_setup(0x48, 4, 400);
+        mem.set(LY_COMPARE_RW, 5);
+        run_cycles(&mut ppu, &mut mem, 100);
+        assert_eq!(mem.get(Y_COORDINATE_R), 5);
+        assert!(mem.get(STATUS_ADDR_RW) & 0b100 != 0);
+        assert!(!stat_requested(&mem));
+        // with the LYC source alone it rises
+        let (mut ppu, mut mem) = stat_setup(0x40, 4, 400);
+        mem.set(LY_COMPARE_RW, 5);
+        run_cycles(&mut ppu, &mut mem, 100);
+        assert!(stat_requested(&mem));
+    }
+
+    #[test]
+    fn stat_write_enabling_an_active_source_fires() {
+        let (mut ppu, mut mem) = stat_setup(0x00, 0, 400);
+        assert_eq!(mem.get(STATUS_ADDR_RW) & 0b11, 0);
+        run_cycles(&mut ppu, &mut mem, 1);
+        assert!(!stat_requested(&mem));
+        mem.set(STATUS_ADDR_RW, 0x08);
+        run_cycles(&mut ppu, &mut mem, 1);
+        assert!(stat_requested(&mem));
+    }
+}
@@ -0,0 +1,210 @@
+use crate::core::constants::*;
+
+/// Internal counter bit whose falling edge ticks TIMA, by TAC clock select.
+const TAC_COUNTER_BITS: [u8; 4] = [9, 3, 5, 7];
+const TAC_ENABLE: u8 = 0b100;
+
+/// DIV is the upper byte of a 16 bit counter running at 4 MHz. TIMA counts falling edges of
+/// one of its bits, gated by the TAC enable, so resetting DIV or changing TAC can tick TIMA.
+pub struct Timer {
+    counter: u16,
+    tima: u8,
+    tma: u8,
+    tac: u8,
+    /// TIMA overflowed during the last M-cycle, it reads 0 until the reload
+    overflowed: bool,
+    /// TIMA was reloaded from TMA during the last M-cycle
+    reloaded: bool,
+}
+
+impl Default for Timer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Timer {
+    pub fn new() -> Timer {
+        Timer {
+            counter: 0,
+            tima: 0,
+            tma: 0,
+            tac: 0,
+            overflowed: false,
+            reloaded: false,
+        }
+    }
+    fn signal(&self) -> bool {
+        let bit = TAC_COUNTER_BITS[(self.tac & 0b11) as usize];
+        self.tac & TAC_ENABLE != 0 && self.counter & (1 << bit) != 0
+    }
+    fn increment_tima(&mut self) {
+        let (tima, overflow) = self.tima.overflowing_add(1);
+        self.tima = tima;
+        self.overflowed |= overflow;
+    }
+    /// Change the counter or TAC through `update`, ticking TIMA on a falling edge.
+    fn update(&mut self, update: impl FnOnce(&mut Timer)) {
+        let before = self.signal();
+        update(self);
+        if before && !self.signal() {
+            self.increment_tima();
+        }
+    }
+    /// Run for `cycles` in 4 MHz, a multiple of 4. Returns whether the timer interrupt is
+    /// requested.
+    pub fn tick(&mut self, cycles: u8) -> bool {
+        let mut interrupt = false;
+        for _ in 0..cycles / 4 {
+            self.reloaded = false;
+            if self.overflowed {
+                self.overflowed = false;
+                self.tima = self.tma;
+                self.reloaded = true;
+                interrupt = true;
+            }
+            self.update(|timer| timer.counter = timer.counter.wrapping_add(4));
+        }
+        interrupt
+    }
+    pub fn get(&self, addr: u16) -> u8 {
+        match addr {
+            DIVIDER_RW => (self.counter >> 8) as u8,
+            TIMER_COUNTER_RW => self.tima,
+            TIMER_MODULO_RW => self.tma,
+            _ => self.tac,
+        }
+    }
+    pub fn set(&mut self, addr: u16, val: u8) {
+        match addr {
+            // any write resets the whole counter
+            DIVIDER_RW => self.update(|timer| timer.counter = 0),
+            // ignored on the reload cycle, cancels a pending reload and interrupt before it
+            TIMER_COUNTER_RW => {
+                if !self.reloaded {
+                    self.tima = val;
+                    self.overflowed = false;
+                }
+            }
+            // the reload cycle copies the new value as well
+            TIMER_MODULO_RW => {
+                self.tma = val;
+                if self.reloaded {
+                    self.tima = val;
+                }
+            }
+            _ => self.update(|timer| timer.tac = val & 0b111),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Timer counting every 16 cycles, internal counter bit 3.
+    fn fast_timer() -> Timer {
+        let mut timer = Timer::new();
+        timer.set(TIMER_CONTROL_RW, TAC_ENABLE | 0b01);
+        timer
+    }
+
+    #[test]
+    fn counts_falling_edges() {
+        let mut timer = fast_timer();
+        timer.tick(12);
+        assert_eq!(timer.get(TIMER_COUNTER_RW), 0);
+        timer.tick(4);
+        assert_eq!(timer.get(TIMER_COUNTER_RW), 1);
+        timer.tick(64);
+        assert_eq!(timer.get(TIMER_COUNTER_RW), 5);
+        assert_eq!(timer.get(DIVIDER_RW), 0);
+        timer.tick(176);
+        assert_eq!(timer.get(DIVIDER_RW), 1);
+    }
+
+    #[test]
+    fn div_write_ticks_tima_when_the_bit_is_high() {
+        let mut timer = fast_timer();
+        timer.tick(4);
+        timer.set(DIVIDER_RW, 0x12);
+        assert_eq!(timer.get(TIMER_COUNTER_RW), 0);
+        assert_eq!(timer.get(DIVIDER_RW), 0);
+        timer.tick(8);
+        timer.set(DIVIDER_RW, 0x12);
+        assert_eq!(timer.get(TIMER_COUNTER_RW), 1);
+        // the counter restarts, the next edge is 16 cycles away
+        timer.tick(12);
+        assert_eq!(timer.get(TIMER_COUNTER_RW), 1);
+        timer.tick(4);
+        assert_eq!(timer.get(TIMER_COUNTER_RW), 2);
+    }
+
+    #[test]
+    fn tac_change_ticks_tima_when_the_signal_falls() {
+        let mut timer = fast_timer();
+        timer.tick(8);
+        // disabling while the selected bit is high
+        timer.set(TIMER_CONTROL_RW, 0b01);
+        assert_eq!(timer.get(TIMER_COUNTER_RW), 1);
+        // enabling doesn't
+        timer.set(TIMER_CONTROL_RW, TAC_ENABLE | 0b01);
+        assert_eq!(timer.get(TIMER_COUNTER_RW), 1);
+        // bit 3 high to bit 9 low
+        timer.set(TIMER_CONTROL_RW, TAC_ENABLE);
+        assert_eq!(timer.get(TIMER_COUNTER_RW), 2);
+        // bit 9 low to bit 3 high
+        timer.set(TIMER_CONTROL_RW, TAC_ENABLE | 0b01);
+        assert_eq!(timer.get(TIMER_COUNTER_RW), 2);
+        assert_eq!(timer.get(TIMER_CONTROL_RW), TAC_ENABLE | 0b01);
+    }
+
+    /// Timer whose TIMA overflowed during the last M-cycle.
+    fn overflowed_timer() -> Timer {
+        let mut timer = fast_timer();
+        timer.set(TIMER_MODULO_RW, 0x42);
+        timer.set(TIMER_COUNTER_RW, 0xff);
+        assert!(!timer.tick(16));
+        timer
+    }
+
+    #[test]
+    fn overflow_reloads_one_cycle_later() {
+        let mut timer = overflowed_timer();
+        assert_eq!(timer.get(TIMER_COUNTER_RW), 0);
+        assert!(timer.tick(4));
+        assert_eq!(timer.get(TIMER_COUNTER_RW), 0x42);
+        assert!(!timer.tick(4));
+    }
+
+    #[test]
+    fn tima_write_before_the_reload_cancels_it() {
+        let mut timer = overflowed_timer();
+        timer.set(TIMER_COUNTER_RW, 0x10);
+        assert!(!timer.tick(4));
+        assert_eq!(timer.get(TIMER_COUNTER_RW), 0x10);
+    }
+
+    #[test]
+    fn tima_write_on_the_reload_cycle_is_ignored() {
+        let mut timer = overflowed_timer();
+        assert!(timer.tick(4));
+        timer.set(TIMER_COUNTER_RW, 0x10);
+        assert_eq!(timer.get(TIMER_COUNTER_RW), 0x42);
+        // the cycle after accepts writes again
+        timer.tick(4);
+        timer.set(TIMER_COUNTER_RW, 0x10);
+        assert_eq!(timer.get(TIMER_COUNTER_RW), 0x10);
+    }
+
+    #[test]
+    fn tma_write_on_the_reload_cycle_is_copied() {
+        let mut timer = overflowed_timer();
+        assert!(timer.tick(4));
+        timer.set(TIMER_MODULO_RW, 0x99);
+        assert_eq!(timer.get(TIMER_COUNTER_RW), 0x99);
+        timer.tick(4);
+        timer.set(TIMER_MODULO_RW, 0x55);
+        assert_eq!(timer.get(TIMER_COUNTER_RW), 0x99);
+    }
+}
@@ -0,0 +1,368 @@
+use std::collections::BTreeMap;
+use std::fmt::{self, Display, Formatter};
+use std::ops::RangeInclusive;
+
+use crate::core::cartridge::ROM_BANK_SIZE;
+use crate::core::instruction::{
+    decode_from, Condition, Instruction, Operand, Register16, Register8,
+};
+use crate::core::memory;
+
+/// cartridge header, logo to global checksum
+const HEADER_DATA: RangeInclusive<u16> = 0x0104..=0x014f;
+const DATA_BYTES_PER_LINE: usize = 8;
+
+const VECTOR_LABELS: [(u16, &str); 14] = [
+    (0x0000, "RST_00"),
+    (0x0008, "RST_08"),
+    (0x0010, "RST_10"),
+    (0x0018, "RST_18"),
+    (0x0020, "RST_20"),
+    (0x0028, "RST_28"),
+    (0x0030, "RST_30"),
+    (0x0038, "RST_38"),
+    (0x0040, "VBlankInterrupt"),
+    (0x0048, "LCDCInterrupt"),
+    (0x0050, "TimerOverflowInterrupt"),
+    (0x0058, "SerialTransferCompleteInterrupt"),
+    (0x0060, "JoypadTransitionInterrupt"),
+    (0x0100, "Boot"),
+];
+
+/// One line of a listing: an instruction or a run of data bytes.
+pub struct Line {
+    pub addr: u16,
+    pub bytes: Vec<u8>,
+    pub label: Option<String>,
+    /// RGBDS source text
+    pub text: String,
+}
+
+impl Display for Line {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if let Some(label) = &self.label {
+            writeln!(f, "{}:", label)?;
+        }
+        let bytes: Vec<String> = self.bytes.iter().map(|b| format!("{:02x}", b)).collect();
+        write!(
+            f,
+            "    {:04X}  {:<24}{}",
+            self.addr,
+            bytes.join(" "),
+            self.text
+        )
+    }
+}
+
+fn register8_name(reg: Register8) -> &'static str {
+    match reg {
+        Register8::A => "a",
+        Register8::B => "b",
+        Register8::C => "c",
+        Register8::D => "d",
+        Register8::E => "e",
+        Register8::H => "h",
+        Register8::L => "l",
+    }
+}
+
+fn register16_name(reg: Register16) -> &'static str {
+    match reg {
+        Register16::AF => "af",
+        Register16::BC => "bc",
+        Register16::DE => "de",
+        Register16::HL => "hl",
+        Register16::SP => "sp",
+    }
+}
+
+fn condition_name(condition: Condition) -> &'static str {
+    match condition {
+        Condition::NZ => "nz",
+        Condition::Z => "z",
+        Condition::NC => "nc",
+        Condition::C => "c",
+    }
+}
+
+fn address_name(addr: u16, labels: &BTreeMap<u16, String>) -> String {
+    match labels.get(&addr) {
+        Some(label) => label.clone(),
+        None => format!("${:04x}", addr),
+    }
+}
+
+fn format_operand(operand: &Operand, labels: &BTreeMap<u16, String>) -> String {
+    match operand {
+        Operand::Reg8(reg) => register8_name(*reg).to_string(),
+        Operand::Reg16(reg) => register16_name(*reg).to_string(),
+        Operand::Indirect(reg) => format!("[{}]", register16_name(*reg)),
+        Operand::IndirectInc => "[hl+]".to_string(),
+        Operand::IndirectDec => "[hl-]".to_string(),
+        Operand::HighC => "[c]".to_string(),
+        Operand::High(a8) => format!("[${:04x}]", 0xff00 + *a8 as u16),
+        Operand::Direct(a16) => format!("[{}]", address_name(*a16, labels)),
+        Operand::Imm8(d8) => format!("${:02x}", d8),
+        Operand::Imm16(d16) => format!("${:04x}", d16),
+        Operand::Signed(r8) => format!("{}", r8),
+        Operand::SpOffset(r8) if *r8 < 0 => format!("sp - {}", (*r8 as i16).unsigned_abs()),
+        Operand::SpOffset(r8) => format!("sp + {}", r8),
+    }
+}
+
+fn branch_target(instruction: &Instruction, addr: u16) -> Option<u16> {
+    match instruction {
+        Instruction::Jr(_, offset) => Some(
+            addr.wrapping_add(instruction.length() as u16)
+                .wrapping_add_signed(*offset as i16),
+        ),
+        Instruction::Jp(_, target) | Instruction::Call(_, target) => Some(*target),
+        Instruction::Rst(vector) => Some(*vector as u16),
+        _ => None,
+    }
+}
+
+/// Render `instruction` located at `addr` in RGBDS syntax, branch targets found in `labels`
+/// are printed by name.
+pub fn format_instruction(
+    instruction: &Instruction,
+    addr: u16,
+    labels: &BTreeMap<u16, String>,
+) -> String {
+    let mnemonic = instruction.mnemonic();
+    let condition_prefix = |condition: &Option<Condition>| match condition {
+        Some(condition) => format!("{}, ", condition_name(*condition)),
+        None => String::new(),
+    };
+    match instruction {
+        Instruction::Ld(dst, src) | Instruction::Ldh(dst, src) | Instruction::Add(dst, src) => {
+            format!(
+                "{} {}, {}",
+                mnemonic,
+                format_operand(dst, labels),
+                format_operand(src, labels)
+            )
+        }
+        Instruction::Inc(operand)
+        | Instruction::Dec(operand)
+        | Instruction::Rlc(operand)
+        | Instruction::Rrc(operand)
+        | Instruction::Rl(operand)
+        | Instruction::Rr(operand)
+        | Instruction::Sla(operand)
+        | Instruction::Sra(operand)
+        | Instruction::Swap(operand)
+        | Instruction::Srl(operand) => format!("{} {}", mnemonic, format_operand(operand, labels)),
+        Instruction::Adc(operand)
+        | Instruction::Sub(operand)
+        | Instruction::Sbc(operand)
+        | Instruction::And(operand)
+        | Instruction::Xor(operand)
+        | Instruction::Or(operand)
+        | Instruction::Cp(operand) => {
+            format!("{} a, {}", mnemonic, format_operand(operand, labels))
+        }
+        Instruction::Bit(bit, operand)
+        | Instruction::Res(bit, operand)
+        | Instruction::Set(bit, operand) => {
+            format!("{} {}, {}", mnemonic, bit, format_operand(operand, labels))
+        }
+        Instruction::Jr(condition, _)
+        | Instruction::Jp(condition, _)
+        | Instruction::Call(condition, _) => {
+            let target = branch_target(instruction, addr).unwrap_or_default();
+            format!(
+                "{} {}{}",
+                mnemonic,
+                condition_prefix(condition),
+                address_name(target, labels)
+            )
+        }
+        Instruction::Ret(Some(condition)) => {
+            format!("{} {}", mnemonic, condition_name(*condition))
+        }
+        Instruction::JpHl => format!("{} hl", mnemonic),
+        Instruction::Rst(vector) => format!("{} ${:02x}", mnemonic, vector),
+        Instruction::Push(reg) | Instruction::Pop(reg) => {
+            format!("{} {}", mnemonic, register16_name(*reg))
+        }
+        Instruction::Illegal(op) => format!("{} ${:02x}", mnemonic, op),
+        _ => mnemonic.to_string(),
+    }
+}
+
+fn data_text(bytes: &[u8]) -> String {
+    let bytes: Vec<String> = bytes.iter().map(|b| format!("${:02x}", b)).collect();
+    format!("db {}", bytes.join(", "))
+}
+
+enum Item {
+    Code(Instruction),
+    Data,
+}
+
+/// Disassemble `range` with bytes supplied by `read`. Addresses inside `data` are dumped as
+/// `db` instead of being decoded. Branch targets inside the range get labels.
+fn disassemble_with_data(
+    read: impl Fn(u16) -> u8,
+    range: RangeInclusive<u16>,
+    data: Option<RangeInclusive<u16>>,
+    vector_labels: bool,
+) -> Vec<Line> {
+    let (start, end) = (*range.start() as u32, *range.end() as u32);
+    let in_data = |addr: u32| data.as_ref().is_some_and(|d| d.contains(&(addr as u16)));
+    let mut items = Vec::<(u16, usize, Item)>::new();
+    let mut addr = start;
+    while addr <= end {
+        if in_data(addr) {
+            let mut len = 0;
+            while len < DATA_BYTES_PER_LINE
+                && addr + (len as u32) <= end
+                && in_data(addr + len as u32)
+            {
+                len += 1;
+            }
+            items.push((addr as u16, len, Item::Data));
+            addr += len as u32;
+            continue;
+        }
+        let instruction = decode_from(&read, addr as u16);
+        let len = instruction.length() as u32;
+        if addr + len - 1 > end || (1..len).any(|i| in_data(addr + i)) {
+            // cut off by the end of the range or by data
+            items.push((addr as u16, 1, Item::Data));
+            addr += 1;
+            continue;
+        }
+        items.push((addr as u16, len as usize, Item::Code(instruction)));
+        addr += len;
+    }
+
+    let mut labels = BTreeMap::<u16, String>::new();
+    if vector_labels {
+        for (vector, name) in VECTOR_LABELS {
+            if range.contains(&vector) {
+                labels.insert(vector, name.to_string());
+            }
+        }
+        if let Some(d) = &data {
+            labels.insert(*d.start(), "Header".to_string());
+        }
+    }
+    let starts: Vec<u16> = items.iter().map(|(addr, _, _)| *addr).collect();
+    for (addr, _, item) in items.iter() {
+        if let Item::Code(instruction) = item {
+            if let Some(target) = branch_target(instruction, *addr) {
+                if starts.binary_search(&target).is_ok() && !labels.contains_key(&target) {
+                    labels.insert(target, format!("label_{:04x}", target));
+                }
+            }
+        }
+    }
+
+    items
+        .into_iter()
+        .map(|(addr, len, item)| {
+            let bytes: Vec<u8> = (0..len as u16).map(|i| read(addr + i)).collect();
+            let text = match item {
+                Item::Code(instruction) => format_instruction(&instruction, addr, &labels),
+                Item::Data => data_text(&bytes),
+            };
+            Line {
+                addr,
+                bytes,
+                label: labels.get(&addr).cloned(),
+                text,
+            }
+        })
+        .collect()
+}
+
+/// Disassemble `range` with bytes supplied by `read`.
+pub fn disassemble(read: impl Fn(u16) -> u8, range: RangeInclusive<u16>) -> Vec<Line> {
+    disassemble_with_data(read, range, None, false)
+}
+
+/// Disassemble `range` as currently mapped in `mem`.
+pub fn disassemble_memory(mem: &memory::Memory, range: RangeInclusive<u16>) -> Vec<Line> {
+    disassemble(|addr| mem.get(addr), range)
+}
+
+/// Address range a ROM bank is mapped to, bank 0 at 0x0000 and the others at 0x4000.
+pub fn bank_range(bank: usize) -> RangeInclusive<u16> {
+    if bank == 0 {
+        0x0000..=0x3fff
+    } else {
+        0x4000..=0x7fff
+    }
+}
+
+/// Disassemble `range` of ROM bank `bank` of a cartridge image, addresses as seen by the cpu.
+pub fn disassemble_bank(rom: &[u8], bank: usize, range: RangeInclusive<u16>) -> Vec<Line> {
+    let bank_start = bank_range(bank).start().to_owned() as usize;
+    let read = |addr: u16| {
+        (addr as usize)
+            .checked_sub(bank_start)
+            .and_then(|offset| rom.get(bank * ROM_BANK_SIZE + offset))
+            .copied()
+            .unwrap_or(0xff)
+    };
+    if bank == 0 {
+        disassemble_with_data(read, range, Some(HEADER_DATA), true)
+    } else {
+        disassemble_with_data(read, range, None, false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bank_zero_listing() {
+        let mut rom = vec![0; ROM_BANK_SIZE];
+        rom[0x0100..0x0104].copy_from_slice(&[0x00, 0xc3, 0x50, 0x01]);
+        rom[0x0104..0x0150].fill(0xce);
+        rom[0x0150..0x0164].copy_from_slice(&[
+            0xcb, 0x37, // swap a
+            0xcb, 0x7e, // bit 7, [hl]
+            0x3e, 0x12, // ld a, $12
+            0x21, 0x34, 0x12, // ld hl, $1234
+            0xe0, 0x40, // ldh [$ff40], a
+            0x18, 0xf3, // jr $0150
+            0xf8, 0xfe, // ld hl, sp - 2
+            0xfa, 0x00, 0xc0, // ld a, [$c000]
+            0xcd, 0x01, // call, cut off by the end of the range
+        ]);
+        let lines = disassemble_bank(&rom, 0, 0x0100..=0x0163);
+        let listing: Vec<(u16, Option<&str>, &str)> = lines
+            .iter()
+            .map(|line| (line.addr, line.label.as_deref(), line.text.as_str()))
+            .collect();
+        let header = "db $ce, $ce, $ce, $ce, $ce, $ce, $ce, $ce";
+        let mut expected = vec![
+            (0x0100, Some("Boot"), "nop"),
+            (0x0101, None, "jp label_0150"),
+            (0x0104, Some("Header"), header),
+        ];
+        // 76 header bytes, 8 per line
+        for addr in (0x010c..0x014c).step_by(8) {
+            expected.push((addr, None, header));
+        }
+        expected.extend([
+            (0x014c, None, "db $ce, $ce, $ce, $ce"),
+            (0x0150, Some("label_0150"), "swap a"),
+            (0x0152, None, "bit 7, [hl]"),
+            (0x0154, None, "ld a, $12"),
+            (0x0156, None, "ld hl, $1234"),
+            (0x0159, None, "ldh [$ff40], a"),
+            (0x015b, None, "jr label_0150"),
+            (0x015d, None, "ld hl, sp - 2"),
+            (0x015f, None, "ld a, [$c000]"),
+            (0x0162, None, "db $cd"),
+            (0x0163, None, "db $01"),
+        ]);
+        assert_eq!(listing, expected);
+        assert_eq!(lines[1].bytes, [0xc3, 0x50, 0x01]);
+    }
+}
@@ -18,39 +18,19 @@ pub fn set_debug_window(scale: u8) {
 
 pub struct Screen {
     scale: u8,
-    frame_buffer: Vec<u8>,
-    debug: bool,
 }
 impl Screen {
     pub fn new(scale: u8, debug: bool) -> Screen {
-        let frame_buffer: Vec<u8> =
-            Vec::from_iter(std::iter::repeat(255).take((LCD_HEIGHT * LCD_WIDTH * 4) as usize));
-        let mut bg_frame_buffer = Vec::<u8>::new();
         if debug {
-            bg_frame_buffer =
-                Vec::from_iter(std::iter::repeat(255).take((BG_SIZE * BG_SIZE * 4) as usize));
             set_debug_window(scale);
         } else {
             set_gameboy_window_scale(scale);
         }
-        Screen {
-            scale,
-            frame_buffer,
-            debug,
-        }
-    }
-    pub fn update_pixel_in_buffer(&mut self, x: u32, y: u32, val: u8) {
-        let idx = ((y * LCD_WIDTH + x) * 4) as usize;
-        self.frame_buffer[idx] = val;
-        self.frame_buffer[idx + 1] = val;
-        self.frame_buffer[idx + 2] = val;
+        Screen { scale }
     }
-    pub fn draw_frame(&self) {
-        let texture = Texture2D::from_rgba8(
-            LCD_WIDTH as u16,
-            LCD_HEIGHT as u16,
-            self.frame_buffer.as_slice(),
-        );
+    pub fn draw_frame(&self, frame_buffer: &Vec<u8>) {
+        let texture =
+            Texture2D::from_rgba8(LCD_WIDTH as u16, LCD_HEIGHT as u16, frame_buffer.as_slice());
         texture.set_filter(macroquad::texture::FilterMode::Nearest);
         let (window_width, window_height) = gameboy_window_size(self.scale);
         draw_texture_ex(
@@ -98,7 +78,7 @@ impl Screen {
             DrawTextureParams {
                 dest_size: Some(vec2(
                     window_width as f32,
-                    scaled_bg_size as f32 - window_height as f32,
+                    scaled_bg_size - window_height as f32,
                 )),
                 ..Default::default()
             },
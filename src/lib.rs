@@ -1,2 +1,4 @@
+pub mod audio;
 pub mod core;
+pub mod disassembler;
 pub mod graphic;